@@ -17,7 +17,6 @@ import { SystemManager } from "./diskrom.js";
 const PROCESSOR_RATE = 3.579545 * 1000 * 1000; // MSX CPU clock
 const AUDIO_SAMPLE_RATE = 44100; // Web Audio standard rate
 const AUDIO_BUFFER_SIZE = 2048; // Larger buffer for stability
-const PSG_NATIVE_RATE = 111860; // PSG native rate (CPU clock / 32)
 
 const PALETTE = [
   0x000000,
@@ -211,12 +210,38 @@ class Emulator {
     const cycleTime = cycles / PROCESSOR_RATE;
     this.timeBudget -= cycleTime;
 
+    this.pollGamepads();
+
     // Step the machine for the calculated cycles
     if (cycles > 0) {
       this.machine.step_for(cycles);
     }
   }
 
+  /**
+   * Feeds the first two connected gamepads into joystick ports 1 and 2.
+   */
+  private pollGamepads() {
+    if (!navigator.getGamepads) {
+      return;
+    }
+    const pads = navigator.getGamepads().filter((pad) => pad && pad.connected);
+    pads.slice(0, 2).forEach((pad, port) => {
+      const pressed = (index: number) => !!pad!.buttons[index]?.pressed;
+      const [x, y] = [pad!.axes[0] ?? 0, pad!.axes[1] ?? 0];
+      const held = [
+        pressed(12) || y < -0.5, // up
+        pressed(13) || y > 0.5, // down
+        pressed(14) || x < -0.5, // left
+        pressed(15) || x > 0.5, // right
+        pressed(0), // trigger A
+        pressed(1), // trigger B
+      ];
+      const buttons = held.reduce((bits, on, bit) => bits | (on ? 1 << bit : 0), 0);
+      this.machine.setJoystick(port, buttons);
+    });
+  }
+
   /**
    * Toggles the emulator running state between running and paused.
    */
@@ -329,11 +354,13 @@ class Emulator {
         latencyHint: "interactive", // Lower latency for better responsiveness
       });
 
+      this.machine.setAudioSampleRate(this.audioContext.sampleRate);
+
       // Create a script processor for audio generation
       this.audioProcessor = this.audioContext.createScriptProcessor(
         AUDIO_BUFFER_SIZE,
         0, // no input channels
-        1, // mono output
+        2, // stereo output, mono is copied to both sides
       );
 
       // Connect to speakers
@@ -346,32 +373,26 @@ class Emulator {
 
       this.audioProcessor.onaudioprocess = (event) => {
         const output = event.outputBuffer.getChannelData(0);
+        const outputRight = event.outputBuffer.getChannelData(1);
         const bufferSize = event.outputBuffer.length;
 
         if (!this.running || !this.audioEnabled) {
           // Fill with silence when paused or disabled
           output.fill(0);
+          outputRight.fill(0);
           return;
         }
 
-        // Calculate the number of samples to generate from the PSG
-        const resampleRatio = PSG_NATIVE_RATE / AUDIO_SAMPLE_RATE;
-        const samplesToGenerate = Math.ceil(bufferSize * resampleRatio);
-
-        // Generate audio samples from the PSG
-        const samples = this.machine.generateAudioSamples(samplesToGenerate);
-
-        // Downsample from 112kHz to 44.1kHz
-        let sampleIndex = 0;
-        for (let i = 0; i < bufferSize; i++) {
-          let total = 0;
-          let count = 0;
-          while (sampleIndex < (i + 1) * resampleRatio) {
-            total += samples[sampleIndex] || 0;
-            count++;
-            sampleIndex++;
+        // The emulator resamples to the audio context rate itself
+        const samples = this.machine.generateAudioSamples(bufferSize);
+        if (this.machine.audioChannels === 2) {
+          for (let i = 0; i < bufferSize; i++) {
+            output[i] = samples[i * 2];
+            outputRight[i] = samples[i * 2 + 1];
           }
-          output[i] = count > 0 ? total / count : 0;
+        } else {
+          output.set(samples);
+          outputRight.set(samples);
         }
       };
     } catch (error) {
@@ -632,6 +653,21 @@ function setupMachine(machine: Machine, isRestart: boolean = false) {
     });
   }
 
+  // Set up the key routing selector, which decides whether the arrow keys and Space
+  // press keyboard keys, joystick 1 or both
+  const keyRouting = document.getElementById(
+    "key-routing",
+  ) as HTMLSelectElement;
+
+  if (keyRouting) {
+    machine.setKeyRouting(keyRouting.value);
+    keyRouting.onchange = () => {
+      (window as any).currentMachine?.setKeyRouting(keyRouting.value);
+      // Give the keys back to the emulator instead of the select
+      keyRouting.blur();
+    };
+  }
+
   let lastTime = Date.now();
 
   // Only add event listeners once to avoid duplication
@@ -665,6 +701,19 @@ function setupMachine(machine: Machine, isRestart: boolean = false) {
         machine.keyUp(e.code);
       }
     });
+
+    window.addEventListener("paste", (e) => {
+      const text = e.clipboardData?.getData("text/plain");
+      const machine = (window as any).currentMachine ||
+        currentEmulator?.machine;
+      if (machine && text) {
+        try {
+          machine.paste(text);
+        } catch (err) {
+          console.warn("Cannot paste:", err);
+        }
+      }
+    });
   }
 
   const frame = () => {
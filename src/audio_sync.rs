@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+
+/// Keeps the host audio buffer at a fixed latency while emulation runs from the video clock.
+///
+/// The emulated machine and the host audio device each run from their own crystal, so
+/// the samples the emulation produces and those the device plays never quite agree.
+/// Instead of letting audio demand run the machine, the controller nudges the resampler
+/// ratio by a fraction of a percent, which is inaudible, so production follows
+/// consumption. The integral part of the correction settles on the drift between the
+/// two clocks, and the proportional part pulls the buffer back to the target.
+///
+/// Playback is held back until the buffer has filled to the target, and again after it
+/// ran dry, so a stall costs one gap instead of a stream of clicks.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AudioSync {
+    enabled: bool,
+    target_latency_ms: f32,
+    playing: bool,
+    // Smoothed buffer fill in host rate frames
+    fill: f64,
+    // Estimated clock drift, as a ratio offset
+    drift: f64,
+    // Ratio correction currently applied to the resampler
+    adjust: f64,
+}
+
+impl AudioSync {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            target_latency_ms: DEFAULT_LATENCY_MS,
+            playing: false,
+            fill: 0.0,
+            drift: 0.0,
+            adjust: 0.0,
+        }
+    }
+
+    /// Starts over from an empty buffer; the drift estimate is kept since the clocks
+    /// have not changed
+    pub fn reset(&mut self) {
+        self.playing = false;
+        self.fill = 0.0;
+        self.adjust = self.drift;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// With sync off the host pulls drive the emulation, as without a video clock
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.reset();
+    }
+
+    pub fn target_latency_ms(&self) -> f32 {
+        self.target_latency_ms
+    }
+
+    pub fn set_target_latency_ms(&mut self, latency_ms: f32) {
+        self.target_latency_ms = latency_ms.clamp(MIN_LATENCY_MS, MAX_LATENCY_MS);
+    }
+
+    /// Target buffer fill at `sample_rate`, in frames
+    pub fn target_frames(&self, sample_rate: u32) -> usize {
+        (self.target_latency_ms * sample_rate as f32 / 1000.0) as usize
+    }
+
+    /// Whether buffered audio is being handed to the host
+    pub fn playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Estimated drift of the emulated clock against the audio device, in parts per
+    /// million; positive when the emulation produces samples too fast
+    pub fn drift_ppm(&self) -> f32 {
+        (self.drift * 1e6) as f32
+    }
+
+    /// Called before the host takes `count` frames with `buffered` frames waiting.
+    ///
+    /// Returns the ratio to scale the resampler step by, above 1 to produce fewer
+    /// frames. The host gets silence while this leaves `playing` off.
+    pub fn update(&mut self, buffered: usize, count: usize, sample_rate: u32) -> f64 {
+        let target = self.target_frames(sample_rate).max(count) as f64;
+
+        if !self.playing {
+            if buffered as f64 >= target {
+                self.playing = true;
+                self.fill = buffered as f64;
+            }
+            return 1.0 + self.adjust;
+        }
+
+        self.fill += (buffered as f64 - self.fill) * FILL_SMOOTHING;
+        let error = ((self.fill - target) / target).clamp(-1.0, 1.0);
+        self.drift = (self.drift + error * DRIFT_GAIN).clamp(-MAX_DRIFT, MAX_DRIFT);
+        self.adjust = (self.drift + error * PROPORTIONAL_GAIN).clamp(-MAX_ADJUST, MAX_ADJUST);
+
+        if buffered < count {
+            // Ran dry: play what is left and fill up again
+            self.playing = false;
+        }
+        1.0 + self.adjust
+    }
+}
+
+impl Default for AudioSync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub const DEFAULT_LATENCY_MS: f32 = 100.0;
+pub const MIN_LATENCY_MS: f32 = 20.0;
+/// Leaves headroom in the output buffer for a late video frame
+pub const MAX_LATENCY_MS: f32 = 250.0;
+
+// Per host pull; callbacks come every 10-50ms
+const FILL_SMOOTHING: f64 = 0.2;
+const PROPORTIONAL_GAIN: f64 = 0.004;
+const DRIFT_GAIN: f64 = 0.000_02;
+// 0.5% between two crystals would already be a broken clock
+const MAX_DRIFT: f64 = 0.005;
+// About 9 cents of pitch at most
+const MAX_ADJUST: f64 = 0.005;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds the controller from a producer running `drift` faster than the consumer
+    fn run(sync: &mut AudioSync, drift: f64, pulls: usize) -> f64 {
+        let mut buffered = 0.0;
+        let mut ratio = 1.0;
+        for _ in 0..pulls {
+            buffered += 1024.0 * (1.0 + drift) / ratio;
+            ratio = sync.update(buffered as usize, 1024, 44100);
+            if sync.playing() {
+                buffered = (buffered - 1024.0).max(0.0);
+            }
+        }
+        buffered
+    }
+
+    #[test]
+    fn test_fills_to_target_before_playing() {
+        let mut sync = AudioSync::new();
+        sync.set_target_latency_ms(50.0);
+        assert_eq!(sync.target_frames(44100), 2205);
+
+        assert_eq!(sync.update(1024, 1024, 44100), 1.0);
+        assert!(!sync.playing());
+        sync.update(2300, 1024, 44100);
+        assert!(sync.playing());
+
+        // Running dry goes back to filling up
+        sync.update(500, 1024, 44100);
+        assert!(!sync.playing());
+    }
+
+    #[test]
+    fn test_tracks_clock_drift() {
+        let mut sync = AudioSync::new();
+        let buffered = run(&mut sync, 0.003, 20_000);
+        let target = sync.target_frames(44100) as f64;
+        assert!(
+            (buffered - target).abs() < 1024.0 + target * 0.05,
+            "buffered {} for a target of {}",
+            buffered,
+            target
+        );
+        assert!(
+            (sync.drift_ppm() - 3000.0).abs() < 300.0,
+            "drift {}ppm",
+            sync.drift_ppm()
+        );
+        assert!(sync.playing(), "never ran dry once settled");
+    }
+}
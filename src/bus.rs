@@ -4,10 +4,22 @@ use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::wasm_bindgen;
 use z80::Z80_io;
 
-use super::{ppi::Ppi, psg::AY38910, vdp::TMS9918};
+use super::{
+    ppi::Ppi,
+    psg::{AY38910, PSG_SAMPLE_DIVIDER},
+    vdp::TMS9918,
+    y8950::Y8950,
+};
 use crate::{
+    cassette::Cassette,
+    fm,
+    joystick::{JoystickState, Joysticks, KeyRouting},
     machine::Message,
-    slot::{RamSlot, RomSlot, SlotType},
+    mixer::{AudioSource, Mixer},
+    one_bit::{OneBitOutput, CASSETTE_OUT_VOLUME, KEY_CLICK_VOLUME},
+    scc::SccChip,
+    slot::{MsxMusicSlot, RamSlot, RomSlot, SccSlot, SlotType},
+    vgm::{VgmLog, VgmWrite},
 };
 
 pub struct Bus {
@@ -15,7 +27,23 @@ pub struct Bus {
     pub vdp: TMS9918,
     pub psg: AY38910,
     pub ppi: Ppi,
-
+    // Mixes every sound source into the host rate stream
+    pub mixer: Mixer,
+    // MSX-AUDIO, when the machine has it
+    pub msx_audio: Option<Y8950>,
+    // 1-bit sound lines on PPI port C bits 7 and 5
+    pub key_click: OneBitOutput,
+    pub cassette_out: OneBitOutput,
+    // What is plugged into the joystick ports
+    pub joysticks: Joysticks,
+    // The tape deck
+    pub cassette: Cassette,
+
+    // CPU cycle at which the current instruction started
+    cpu_cycle: u64,
+    // Sound chip register writes being recorded
+    vgm: Option<VgmLog>,
+    queue: Rc<RefCell<VecDeque<Message>>>,
     slots: [SlotType; 4],
 }
 
@@ -25,29 +53,76 @@ impl Bus {
             panic!("Bus requires exactly 4 slots, got {}", slots.len());
         }
 
-        Self {
-            vdp: TMS9918::new(queue),
+        let mut bus = Self {
+            vdp: TMS9918::new(queue.clone()),
             psg: AY38910::new(),
             ppi: Ppi::new(),
+            mixer: Mixer::new(),
+            msx_audio: None,
+            key_click: OneBitOutput::new(KEY_CLICK_VOLUME),
+            cassette_out: OneBitOutput::new(CASSETTE_OUT_VOLUME),
+            joysticks: Joysticks::new(),
+            cassette: Cassette::new(),
+            cpu_cycle: 0,
+            vgm: None,
+            queue,
             slots: [
                 slots[0].clone(),
                 slots[1].clone(),
                 slots[2].clone(),
                 slots[3].clone(),
             ],
-        }
+        };
+        bus.update_mixer_sources();
+        bus
     }
 
+    /// A host key, routed to the keyboard matrix or a joystick as the key routing says
     pub fn key_down(&mut self, key: String) {
-        // Send key press to both keyboard and joystick handlers
-        self.ppi.key_down(key.clone());
-        self.psg.joystick_key_down(key);
+        let to_keyboard = self.joysticks.key_down(&key);
+        self.update_joysticks();
+        if to_keyboard {
+            self.ppi.key_down(key);
+        }
     }
 
     pub fn key_up(&mut self, key: String) {
-        // Send key release to both keyboard and joystick handlers
-        self.ppi.key_up(key.clone());
-        self.psg.joystick_key_up(key);
+        self.joysticks.key_up(&key);
+        self.update_joysticks();
+        self.ppi.key_up(key);
+    }
+
+    pub fn set_key_routing(&mut self, routing: KeyRouting) {
+        self.joysticks.set_routing(routing);
+        self.update_joysticks();
+    }
+
+    pub fn set_joystick(&mut self, port: usize, state: JoystickState) {
+        self.joysticks.set_state(port, state);
+        self.update_joysticks();
+    }
+
+    /// Host mouse motion and buttons, for a mouse plugged into either port
+    pub fn mouse_input(&mut self, dx: i32, dy: i32, left: bool, right: bool) {
+        self.joysticks.move_mouse(dx, dy);
+        self.joysticks.set_mouse_buttons(left, right);
+        self.update_joysticks();
+    }
+
+    /// Presents the joystick ports' lines to PSG register 14
+    pub fn update_joysticks(&mut self) {
+        for port in 0..2 {
+            self.psg.joystick_ports[port] = self.joysticks.lines(port);
+        }
+    }
+
+    /// Called at the end of every video frame
+    pub fn end_frame(&mut self) {
+        self.psg.end_frame();
+        self.mixer.end_frame();
+        self.joysticks.end_frame();
+        self.update_joysticks();
+        self.ppi.keyboard.end_frame();
     }
 
     pub fn mem_size(&self) -> usize {
@@ -58,17 +133,164 @@ impl Bus {
         self.vdp.reset();
         self.psg.reset();
         self.ppi.reset();
+        self.mixer.reset();
+        self.key_click.reset();
+        self.cassette_out.reset();
+        self.joysticks.reset();
+        if let Some(msx_audio) = self.msx_audio.as_mut() {
+            msx_audio.reset();
+        }
+        for slot in self.slots.iter_mut() {
+            match slot {
+                SlotType::Scc(cartridge) => cartridge.reset(),
+                SlotType::MsxMusic(cartridge) => cartridge.reset(),
+                _ => {}
+            }
+        }
     }
 
     pub fn clock(&mut self, cycles: u32) {
-        // Clock the PSG for audio generation
-        self.psg.clock(cycles);
+        if let Some(msx_audio) = self.msx_audio.as_mut() {
+            msx_audio.clock(cycles);
+        }
+
+        // The 1-bit lines sample in step with the mixer, whose current step began this
+        // many cycles before the instruction
+        let sample_start = self
+            .cpu_cycle
+            .saturating_sub(self.mixer.clock_phase() as u64);
+        self.key_click.align(sample_start);
+        self.cassette_out.align(sample_start);
+
+        let psg = &mut self.psg;
+        let key_click = &mut self.key_click;
+        let cassette_out = &mut self.cassette_out;
+        let slots = &mut self.slots;
+        let msx_audio = &mut self.msx_audio;
+        self.mixer.clock(cycles, |source| {
+            let level = match source {
+                AudioSource::Psg => return psg.next_frame(),
+                AudioSource::KeyClick => key_click.next_sample(),
+                AudioSource::Cassette => cassette_out.next_sample(),
+                AudioSource::Scc => slots
+                    .iter_mut()
+                    .map(|slot| match slot {
+                        SlotType::Scc(cartridge) => cartridge.scc.next_sample(),
+                        _ => 0.0,
+                    })
+                    .sum(),
+                AudioSource::Opll => slots
+                    .iter_mut()
+                    .map(|slot| match slot {
+                        SlotType::MsxMusic(cartridge) => cartridge.opll.next_sample(),
+                        _ => 0.0,
+                    })
+                    .sum(),
+                AudioSource::MsxAudio => msx_audio.as_mut().map_or(0.0, |chip| chip.next_sample()),
+            };
+            [level, level]
+        });
+    }
+
+    /// Registers a mixer source for every sound chip that is fitted and drops the others
+    fn update_mixer_sources(&mut self) {
+        let has_slot = |matches: fn(&SlotType) -> bool| self.slots.iter().any(matches);
+        let sources = [
+            (AudioSource::Psg, true, PSG_SAMPLE_DIVIDER),
+            (AudioSource::KeyClick, true, PSG_SAMPLE_DIVIDER),
+            (AudioSource::Cassette, true, PSG_SAMPLE_DIVIDER),
+            (
+                AudioSource::Scc,
+                has_slot(|slot| matches!(slot, SlotType::Scc(_))),
+                PSG_SAMPLE_DIVIDER,
+            ),
+            (
+                AudioSource::Opll,
+                has_slot(|slot| matches!(slot, SlotType::MsxMusic(_))),
+                fm::CLOCKS_PER_SAMPLE,
+            ),
+            (
+                AudioSource::MsxAudio,
+                self.msx_audio.is_some(),
+                fm::CLOCKS_PER_SAMPLE,
+            ),
+        ];
+        for (source, fitted, cycles_per_sample) in sources {
+            if fitted {
+                self.mixer.register(source, cycles_per_sample);
+            } else {
+                self.mixer.unregister(source);
+            }
+        }
+    }
+
+    /// Switches the output between mono and stereo, with the PSG's channel pans
+    pub fn set_stereo(&mut self, stereo: bool) {
+        self.psg.set_stereo(stereo);
+        self.mixer.set_stereo(stereo);
+    }
+
+    /// Adds or removes the MSX-AUDIO chip with `ram_size` bytes of sample RAM
+    pub fn set_msx_audio(&mut self, ram_size: Option<usize>) {
+        self.msx_audio = ram_size.map(|size| Y8950::new(self.queue.clone(), size));
+        self.update_mixer_sources();
+    }
+
+    /// Keeps the devices that model access timing in step with the CPU
+    pub fn set_cpu_cycle(&mut self, cycle: u64) {
+        self.cpu_cycle = cycle;
+        self.vdp.set_cpu_cycle(cycle);
+    }
+
+    /// Forwards PPI port C bit 7 (key click) and bit 5 (cassette output) to their lines,
+    /// and bit 4, low to run it, to the cassette motor
+    pub fn update_one_bit_outputs(&mut self) {
+        let (register_c, cycle) = (self.ppi.register_c(), self.cpu_cycle);
+        self.key_click.set_level(register_c & 0x80 != 0, cycle);
+        self.cassette_out.set_level(register_c & 0x20 != 0, cycle);
+        if self.cassette.motor() != (register_c & 0x10 == 0) {
+            self.cassette.set_motor(register_c & 0x10 == 0, cycle);
+        }
     }
 
-    pub fn update_psg_pulse_signal(&mut self) {
-        // Update PSG pulse signal based on PPI register C bits 5 and 7
-        let pulse_active = (self.ppi.register_c() & 0xa0) != 0;
-        self.psg.set_pulse_signal(pulse_active);
+    /// Starts recording sound chip register writes, from the chips' current state
+    pub fn start_vgm_log(&mut self, cycle: u64) {
+        let mut log = VgmLog::new(cycle, self.psg.model());
+        log.write_psg_state(&self.psg);
+        for slot in self.slots.iter() {
+            match slot {
+                SlotType::Scc(cartridge) => log.write_scc_state(&cartridge.scc),
+                SlotType::MsxMusic(cartridge) => log.write_opll_state(&cartridge.opll),
+                _ => {}
+            }
+        }
+        if let Some(msx_audio) = self.msx_audio.as_ref() {
+            log.write_y8950_state(msx_audio);
+        }
+        self.vgm = Some(log);
+    }
+
+    /// Stops recording and returns the VGM file, if a recording was running
+    pub fn stop_vgm_log(&mut self, cycle: u64) -> Option<Vec<u8>> {
+        self.vgm.take().map(|log| log.finish(cycle))
+    }
+
+    pub fn vgm_logging(&self) -> bool {
+        self.vgm.is_some()
+    }
+
+    fn log_sound_write(&mut self, write: VgmWrite) {
+        if let Some(log) = self.vgm.as_mut() {
+            log.write(self.cpu_cycle, write);
+        }
+    }
+
+    /// The MSX-MUSIC cartridge whose YM2413 answers on ports 0x7C-0x7D, if any
+    fn msx_music_mut(&mut self) -> Option<&mut MsxMusicSlot> {
+        self.slots.iter_mut().find_map(|slot| match slot {
+            SlotType::MsxMusic(cartridge) if cartridge.io_enabled() => Some(cartridge),
+            _ => None,
+        })
     }
 
     pub fn get_slot(&self, slot: usize) -> &SlotType {
@@ -80,7 +302,7 @@ impl Bus {
     }
 
     pub fn input(&mut self, port: u8) -> u8 {
-        if (0x7C..=0x7F).contains(&port) || (0xD0..=0xDF).contains(&port) {
+        if (0x7E..=0x7F).contains(&port) || (0xD0..=0xDF).contains(&port) {
             let ppi_a8 = self.ppi.primary_slot_config;
             tracing::warn!(
                 "[FDC I/O Port Check - INPUT] Port {:02X}. PPI A8: {:02X} (P0:{:X}, P1:{:X}, P2:{:X}, P3:{:X})",
@@ -91,39 +313,22 @@ impl Bus {
         }
         match port {
             0x98 | 0x99 => self.vdp.read(port),
-            0xA0 | 0xA1 | 0xA2 => self.psg.read(port),
-            0xA8 => self.ppi.read(port), // Primary slot config
-            0xA9 => {
-                // Special handling for keyboard port (0xA9)
-                // This is where we implement the multiplexing between keyboard and joystick
-
-                // First, get the keyboard state from PPI
-                let keyboard_state = self.ppi.read(port);
-
-                // If we're reading row 8 (where space bar is located), we need to combine with joystick
-                if self.ppi.keyboard_row_selected() == 8 {
-                    // Get joystick state from PSG (bit 4 is fire button/space)
-                    let joystick_state = self.psg.joystick_port_a;
-
-                    // If space is pressed on joystick (bit 4 is 0), clear bit 0 in keyboard state
-                    // This simulates the space key being pressed in row 8
-                    if (joystick_state & (1 << 4)) == 0 {
-                        tracing::info!(
-                            "[BUS] Multiplexing joystick space to keyboard: KB:{:08b}, Joy:{:08b}, Result:{:08b}",
-                            keyboard_state,
-                            joystick_state,
-                            keyboard_state & !(1 << 0)
-                        );
-                        return keyboard_state & !(1 << 0);
-                    }
-                }
-
-                keyboard_state
+            0xA0 | 0xA1 | 0xA2 => {
+                // Bit 7 of register 14 follows the tape signal
+                self.psg.cassette_input = self.cassette.input(self.cpu_cycle).unwrap_or(true);
+                self.psg.read(port)
             }
+            0xA8 => self.ppi.read(port), // Primary slot config
+            0xA9 => self.ppi.read(port),
             0xAA | 0xAB => self.ppi.read(port), // Other PPI ports
+            0xC0 | 0xC1 => match self.msx_audio.as_mut() {
+                Some(msx_audio) if port == 0xC0 => msx_audio.read_status(),
+                Some(msx_audio) => msx_audio.read_data(),
+                None => 0xFF,
+            },
             _ => {
                 // Only log disk-related ports
-                if (0x7C..=0x7F).contains(&port)
+                if (0x7E..=0x7F).contains(&port)
                     || port == 0xFB
                     || port == 0xD8
                     || (0xD0..=0xD7).contains(&port)
@@ -139,7 +344,7 @@ impl Bus {
     }
 
     pub fn output(&mut self, port: u8, data: u8) {
-        if (0x7C..=0x7F).contains(&port)
+        if (0x7E..=0x7F).contains(&port)
             || (0xD0..=0xDF).contains(&port)
             || port == 0xD8
             || port == 0xFB
@@ -155,7 +360,21 @@ impl Bus {
 
         match port {
             0x98 | 0x99 => self.vdp.write(port, data),
-            0xA0 | 0xA1 => self.psg.write(port, data),
+            0xA0 => self.psg.write(port, data),
+            0xA1 => {
+                self.log_sound_write(VgmWrite::Psg {
+                    register: self.psg.selected_register(),
+                    value: data,
+                });
+                self.psg.write(port, data);
+                if matches!(self.psg.selected_register(), 7 | 15) {
+                    // Register 15 drives the output pins of both joystick ports, while
+                    // register 7 bit 7 makes it an output
+                    let pins = [self.psg.output_pins(0), self.psg.output_pins(1)];
+                    self.joysticks.write_pins(pins, self.cpu_cycle);
+                    self.update_joysticks();
+                }
+            }
             0xA2 => {
                 // Port 0xA2 is read-only for PSG, writes are ignored
                 tracing::trace!("[BUS] Ignored write to PSG read port 0xA2: {:02X}", data);
@@ -170,11 +389,37 @@ impl Bus {
             }
             0xAA | 0xAB => {
                 // PPI Port C or Control
-                let old_register_c = self.ppi.register_c();
                 self.ppi.write(port, data);
-                // Update PSG pulse signal if register C changed
-                if port == 0xAA || (port == 0xAB && old_register_c != self.ppi.register_c()) {
-                    self.update_psg_pulse_signal();
+                self.update_one_bit_outputs();
+            }
+            0x7C => {
+                if let Some(cartridge) = self.msx_music_mut() {
+                    cartridge.opll.write_address(data);
+                }
+            }
+            0x7D => {
+                if let Some(cartridge) = self.msx_music_mut() {
+                    let register = cartridge.opll.address();
+                    cartridge.opll.write_data(data);
+                    self.log_sound_write(VgmWrite::Opll {
+                        register,
+                        value: data,
+                    });
+                }
+            }
+            0xC0 => {
+                if let Some(msx_audio) = self.msx_audio.as_mut() {
+                    msx_audio.write_address(data);
+                }
+            }
+            0xC1 => {
+                if let Some(msx_audio) = self.msx_audio.as_mut() {
+                    let register = msx_audio.address();
+                    msx_audio.write_data(data);
+                    self.log_sound_write(VgmWrite::Y8950 {
+                        register,
+                        value: data,
+                    });
                 }
             }
             0xFB => {
@@ -188,7 +433,7 @@ impl Bus {
             }
             _ => {
                 // Only log disk-related ports
-                if (0x7C..=0x7F).contains(&port)
+                if (0x7E..=0x7F).contains(&port)
                     || port == 0xFB
                     || port == 0xD8
                     || (0xD0..=0xD7).contains(&port)
@@ -214,6 +459,11 @@ impl Bus {
 
     pub fn write_byte(&mut self, addr: u16, data: u8) {
         let (slot_number, addr) = self.translate_address(addr);
+        if self.vgm.is_some() {
+            if let Some(write) = self.slots[slot_number].sound_write(addr, data) {
+                self.log_sound_write(write);
+            }
+        }
         self.slots[slot_number].write(addr, data);
     }
 
@@ -334,16 +584,30 @@ impl Bus {
         if let SlotType::Rom(rom_slot) = &mut self.slots[slot as usize] {
             rom_slot.load(rom);
         } else {
-            self.slots[slot as usize] = SlotType::Rom(RomSlot::new(rom, 0x0000, 0x10000));
+            self.set_slot(slot, SlotType::Rom(RomSlot::new(rom, 0x0000, 0x10000)));
         }
     }
 
+    pub fn load_msx_music_rom(&mut self, slot: u8, rom: &[u8]) {
+        self.set_slot(slot, SlotType::MsxMusic(MsxMusicSlot::new(rom)));
+    }
+
+    pub fn load_scc_rom(&mut self, slot: u8, rom: &[u8], chip: SccChip) {
+        self.set_slot(slot, SlotType::Scc(SccSlot::new(rom, chip)));
+    }
+
     pub fn load_ram(&mut self, slot: u8) {
-        self.slots[slot as usize] = SlotType::Ram(RamSlot::new(0x0000, 0x10000));
+        self.set_slot(slot, SlotType::Ram(RamSlot::new(0x0000, 0x10000)));
     }
 
     pub fn load_empty(&mut self, slot: u8) {
-        self.slots[slot as usize] = SlotType::Empty;
+        self.set_slot(slot, SlotType::Empty);
+    }
+
+    /// Inserts a cartridge or memory into a primary slot
+    pub fn set_slot(&mut self, slot: u8, slot_type: SlotType) {
+        self.slots[slot as usize] = slot_type;
+        self.update_mixer_sources();
     }
 }
 
@@ -0,0 +1,282 @@
+use serde::{Deserialize, Serialize};
+
+use crate::tape_signal::TapeSignal;
+
+/// A cassette in the tape deck, held as a `.cas` image.
+///
+/// A CAS image is the data of every tape block with the sync tones left out: each
+/// block starts with the 8 byte `CAS_HEADER`, at an offset that is a multiple of 8,
+/// padded before with zeros. The first block of a file starts with ten type bytes and
+/// a six character name; the blocks after it hold the file's contents.
+///
+/// The position is a byte offset into the image, moved by the BIOS tape routines,
+/// which `CassetteDriver` replaces.
+///
+/// The deck can hold a `TapeSignal` instead, played into the cassette input for
+/// loaders that bypass those routines.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Cassette {
+    // The image, or None with the deck empty
+    data: Option<Vec<u8>>,
+    position: usize,
+    motor: bool,
+    signal: Option<TapeSignal>,
+}
+
+/// Marks the start of a block in a CAS image
+pub const CAS_HEADER: [u8; 8] = [0x1F, 0xA6, 0xDE, 0xBA, 0xCC, 0x13, 0x7D, 0x74];
+
+/// What a block of a CAS image holds
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TapeBlockKind {
+    /// The header of a tokenized BASIC program, from CSAVE
+    Basic,
+    /// The header of a text file, from SAVE"CAS:"
+    Ascii,
+    /// The header of a memory dump, from BSAVE"CAS:"
+    Binary,
+    /// The contents of the file before it, or a custom loader's data
+    Data,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TapeBlock {
+    /// Offset of the block header in the image
+    pub offset: usize,
+    pub kind: TapeBlockKind,
+    /// File name for file headers, without trailing spaces
+    pub name: String,
+}
+
+impl Cassette {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Puts a CAS image in the deck, rewound. An empty image is a blank tape.
+    pub fn insert(&mut self, data: Vec<u8>) -> Result<(), String> {
+        if !data.is_empty() && !data.starts_with(&CAS_HEADER) {
+            return Err("Not a CAS image: no block header at the start".to_string());
+        }
+        self.data = Some(data);
+        self.position = 0;
+        self.signal = None;
+        Ok(())
+    }
+
+    /// Puts a tape to play into the cassette input in the deck at CPU cycle `cycle`
+    pub fn insert_signal(&mut self, mut signal: TapeSignal, cycle: u64) {
+        self.eject();
+        signal.set_motor(self.motor, cycle);
+        self.signal = Some(signal);
+    }
+
+    pub fn eject(&mut self) {
+        self.data = None;
+        self.position = 0;
+        self.signal = None;
+    }
+
+    pub fn signal(&self) -> Option<&TapeSignal> {
+        self.signal.as_ref()
+    }
+
+    /// The image with whatever was recorded on it, e.g. to save it
+    pub fn data(&self) -> Option<&[u8]> {
+        self.data.as_deref()
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Winds the tape to a byte offset, e.g. the offset of a block
+    pub fn set_position(&mut self, position: usize) {
+        let len = self.data.as_ref().map_or(0, Vec::len);
+        self.position = position.min(len);
+    }
+
+    pub fn rewind(&mut self) {
+        self.position = 0;
+        if let Some(signal) = &mut self.signal {
+            signal.rewind();
+        }
+    }
+
+    pub fn motor(&self) -> bool {
+        self.motor
+    }
+
+    /// Follows the motor relay as it switches at CPU cycle `cycle`
+    pub fn set_motor(&mut self, on: bool, cycle: u64) {
+        self.motor = on;
+        if let Some(signal) = &mut self.signal {
+            signal.set_motor(on, cycle);
+        }
+    }
+
+    /// The cassette input at CPU cycle `cycle`, or None without a signal to play
+    pub fn input(&mut self, cycle: u64) -> Option<bool> {
+        Some(self.signal.as_mut()?.level(cycle))
+    }
+
+    /// Whether a signal is playing at CPU cycle `cycle`, i.e. the motor runs and the
+    /// tape has not ended
+    pub fn signal_playing(&self, cycle: u64) -> bool {
+        self.signal
+            .as_ref()
+            .is_some_and(|signal| signal.playing(cycle))
+    }
+
+    /// Every block on the tape, in order
+    pub fn blocks(&self) -> Vec<TapeBlock> {
+        let Some(data) = &self.data else {
+            return Vec::new();
+        };
+        block_offsets(data)
+            .map(|offset| {
+                let body = &data[offset + CAS_HEADER.len()..];
+                let kind = match body.get(..10) {
+                    Some(id) if id == [0xD3; 10] => TapeBlockKind::Basic,
+                    Some(id) if id == [0xEA; 10] => TapeBlockKind::Ascii,
+                    Some(id) if id == [0xD0; 10] => TapeBlockKind::Binary,
+                    _ => TapeBlockKind::Data,
+                };
+                let name = match kind {
+                    TapeBlockKind::Data => String::new(),
+                    _ => body
+                        .iter()
+                        .skip(10)
+                        .take(6)
+                        .map(|&byte| byte as char)
+                        .collect::<String>()
+                        .trim_end()
+                        .to_string(),
+                };
+                TapeBlock { offset, kind, name }
+            })
+            .collect()
+    }
+
+    /// TAPION: winds to the next block and skips its header. False at the end of the
+    /// tape or without one.
+    pub fn read_header(&mut self) -> bool {
+        let Some(data) = &self.data else {
+            return false;
+        };
+        let aligned = self.position.next_multiple_of(CAS_HEADER.len());
+        match block_offsets(data).find(|&offset| offset >= aligned) {
+            Some(offset) => {
+                self.position = offset + CAS_HEADER.len();
+                true
+            }
+            None => {
+                self.position = data.len();
+                false
+            }
+        }
+    }
+
+    /// TAPIN: the next byte, or None past the end of the tape
+    pub fn read_byte(&mut self) -> Option<u8> {
+        let byte = *self.data.as_ref()?.get(self.position)?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    /// TAPOON: starts a new block at the position, recording over the rest of the
+    /// tape. False without a tape.
+    pub fn write_header(&mut self) -> bool {
+        let Some(data) = &mut self.data else {
+            return false;
+        };
+        data.truncate(self.position);
+        data.resize(data.len().next_multiple_of(CAS_HEADER.len()), 0);
+        data.extend_from_slice(&CAS_HEADER);
+        self.position = data.len();
+        true
+    }
+
+    /// TAPOUT: records a byte at the position
+    pub fn write_byte(&mut self, byte: u8) -> bool {
+        let Some(data) = &mut self.data else {
+            return false;
+        };
+        data.truncate(self.position);
+        data.push(byte);
+        self.position = data.len();
+        true
+    }
+}
+
+fn block_offsets(data: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    data.chunks(CAS_HEADER.len())
+        .enumerate()
+        .filter(|(_, chunk)| *chunk == CAS_HEADER)
+        .map(|(index, _)| index * CAS_HEADER.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A BSAVE'd file: a binary header block named GAME and a data block
+    fn binary_file() -> Vec<u8> {
+        let mut data = CAS_HEADER.to_vec();
+        data.extend_from_slice(&[0xD0; 10]);
+        data.extend_from_slice(b"GAME  ");
+        data.extend_from_slice(&CAS_HEADER);
+        data.extend_from_slice(&[0x00, 0x90, 0x01, 0x90, 0x00, 0x90, 0xC9]);
+        data
+    }
+
+    #[test]
+    fn test_blocks_and_reading() {
+        let mut cassette = Cassette::new();
+        assert!(cassette.insert(vec![1, 2, 3]).is_err());
+        cassette.insert(binary_file()).unwrap();
+
+        let blocks = cassette.blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].kind, TapeBlockKind::Binary);
+        assert_eq!(blocks[0].name, "GAME");
+        assert_eq!(blocks[1].offset, 24);
+        assert_eq!(blocks[1].kind, TapeBlockKind::Data);
+
+        assert!(cassette.read_header());
+        assert_eq!(cassette.read_byte(), Some(0xD0));
+        // The next header is found from the middle of the block
+        assert!(cassette.read_header());
+        assert_eq!(cassette.position(), 32);
+        let bytes: Vec<_> = std::iter::from_fn(|| cassette.read_byte()).collect();
+        assert_eq!(bytes.len(), 7);
+        assert!(!cassette.read_header(), "end of tape");
+
+        cassette.rewind();
+        assert!(cassette.read_header());
+        assert_eq!(cassette.position(), 8);
+    }
+
+    #[test]
+    fn test_recording_pads_blocks() {
+        let mut cassette = Cassette::new();
+        assert!(!cassette.write_header(), "no tape");
+        cassette.insert(Vec::new()).unwrap();
+
+        assert!(cassette.write_header());
+        for byte in [0xD0; 10].iter().chain(b"GAME  ") {
+            cassette.write_byte(*byte);
+        }
+        cassette.write_header();
+        for byte in [0x00, 0x90, 0x01, 0x90, 0x00, 0x90, 0xC9] {
+            cassette.write_byte(byte);
+        }
+        assert_eq!(cassette.data().unwrap(), binary_file());
+
+        // Recording from the second block on replaces it
+        cassette.set_position(24);
+        cassette.write_header();
+        cassette.write_byte(0xFF);
+        assert_eq!(cassette.data().unwrap().len(), 33);
+    }
+}
@@ -0,0 +1,222 @@
+// Cassette Driver - implements the BIOS tape routines via CPU extensions
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::bus::Bus;
+use crate::cpu_extensions::{CpuExtensionHandler, CpuExtensionState};
+use crate::machine::Io;
+use crate::slot::RomSlot;
+
+/// BIOS jump table entries of the tape routines, with the extension replacing each
+const TAPE_ROUTINES: [(u16, u8, &str); TAPE_ROUTINE_COUNT] = [
+    (0x00E1, 0xF0, "TAPION"),
+    (0x00E4, 0xF1, "TAPIN"),
+    (0x00E7, 0xF2, "TAPIOF"),
+    (0x00EA, 0xF3, "TAPOON"),
+    (0x00ED, 0xF4, "TAPOUT"),
+    (0x00F0, 0xF5, "TAPOOF"),
+    (0x00F3, 0xF6, "STMOTR"),
+];
+const TAPE_ROUTINE_COUNT: usize = 7;
+
+/// The jump table entries a patch replaced, to put them back
+pub type BiosPatch = [[u8; 3]; TAPE_ROUTINE_COUNT];
+
+/// Serves the BIOS tape routines from the cassette in `Bus::cassette`, so that CLOAD,
+/// BLOAD"CAS:" and RUN"CAS:" read a CAS image at once instead of decoding audio.
+///
+/// Loaders that drive the tape input themselves bypass these routines.
+pub struct CassetteDriver {
+    bus: Rc<RefCell<Bus>>,
+}
+
+impl CassetteDriver {
+    /// Replaces the tape entries of the BIOS jump table with extension calls, returning
+    /// the entries replaced.
+    ///
+    /// Returns None, leaving the ROM alone, if it has no jump table there.
+    pub fn patch_bios(rom_slot: &mut RomSlot) -> Option<BiosPatch> {
+        let base = rom_slot.base as usize;
+        let is_jump = |address: u16| {
+            (address as usize)
+                .checked_sub(base)
+                .and_then(|offset| rom_slot.data.get(offset))
+                == Some(&0xC3)
+        };
+        if !TAPE_ROUTINES
+            .iter()
+            .all(|(address, _, _)| is_jump(*address))
+        {
+            tracing::warn!("No BIOS jump table to patch for tape support");
+            return None;
+        }
+
+        let mut original = [[0; 3]; TAPE_ROUTINE_COUNT];
+        for ((address, ext_num, name), entry) in TAPE_ROUTINES.into_iter().zip(&mut original) {
+            let offset = address as usize - base;
+            entry.copy_from_slice(&rom_slot.data[offset..offset + 3]);
+            rom_slot.data[offset..offset + 3].copy_from_slice(&[0xED, ext_num, 0xC9]);
+            tracing::info!(
+                "Patched {} at 0x{:04X} with extension 0x{:02X}",
+                name,
+                address,
+                ext_num
+            );
+        }
+        Some(original)
+    }
+
+    /// Puts back the jump table entries of `patch_bios`, so that the BIOS reads the
+    /// cassette input again
+    pub fn restore_bios(rom_slot: &mut RomSlot, original: &BiosPatch) {
+        let base = rom_slot.base as usize;
+        for ((address, _, _), entry) in TAPE_ROUTINES.iter().zip(original) {
+            let offset = *address as usize - base;
+            rom_slot.data[offset..offset + 3].copy_from_slice(entry);
+        }
+    }
+
+    /// Registers the handlers for the patched routines
+    pub fn setup(io: &Io, bus: Rc<RefCell<Bus>>) {
+        for (_, ext_num, _) in TAPE_ROUTINES {
+            let driver = CassetteDriver { bus: bus.clone() };
+            io.register_extension_handler(ext_num, Box::new(driver));
+        }
+    }
+
+    /// Drives the motor relay through PPI port C bit 4, which is low for on
+    fn set_motor(bus: &mut Bus, on: bool) {
+        bus.output(0xAB, 0x08 | !on as u8);
+    }
+}
+
+impl CpuExtensionHandler for CassetteDriver {
+    fn extension_begin(&mut self, state: &mut CpuExtensionState) -> bool {
+        let mut bus = self.bus.borrow_mut();
+        // Carry set is an I/O error for every tape routine
+        let ok = match state.ext_num {
+            0xF0 | 0xF3 => {
+                Self::set_motor(&mut bus, true);
+                if state.ext_num == 0xF0 {
+                    bus.cassette.read_header()
+                } else {
+                    bus.cassette.write_header()
+                }
+            }
+            0xF1 => match bus.cassette.read_byte() {
+                Some(byte) => {
+                    state.a = byte;
+                    true
+                }
+                None => false,
+            },
+            0xF2 | 0xF5 => {
+                Self::set_motor(&mut bus, false);
+                true
+            }
+            0xF4 => bus.cassette.write_byte(state.a),
+            0xF6 => {
+                let on = match state.a {
+                    0 => false,
+                    0xFF => !bus.cassette.motor(),
+                    _ => true,
+                };
+                Self::set_motor(&mut bus, on);
+                true
+            }
+            _ => return false,
+        };
+        state.set_carry_flag(!ok);
+        true
+    }
+
+    fn extension_finish(&mut self, _state: &mut CpuExtensionState) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::cassette::CAS_HEADER;
+    use crate::slot::SlotType;
+
+    fn state(ext_num: u8, a: u8) -> CpuExtensionState {
+        CpuExtensionState {
+            ext_num,
+            ext_pc: 0,
+            pc: 0,
+            sp: 0,
+            a,
+            f: 0,
+            bc: 0,
+            de: 0,
+            hl: 0,
+            ix: 0,
+            iy: 0,
+        }
+    }
+
+    #[test]
+    fn test_patch_bios() {
+        let mut bios = vec![0u8; 0x8000];
+        let mut blank = RomSlot::new(&bios, 0, 0x8000);
+        assert!(CassetteDriver::patch_bios(&mut blank).is_none());
+
+        for (address, _, _) in TAPE_ROUTINES {
+            bios[address as usize] = 0xC3;
+        }
+        let mut rom_slot = RomSlot::new(&bios, 0, 0x8000);
+        let original = CassetteDriver::patch_bios(&mut rom_slot).unwrap();
+        assert_eq!(rom_slot.data[0x00E1..0x00E4], [0xED, 0xF0, 0xC9]);
+        assert_eq!(rom_slot.data[0x00F3..0x00F6], [0xED, 0xF6, 0xC9]);
+
+        CassetteDriver::restore_bios(&mut rom_slot, &original);
+        assert_eq!(rom_slot.data, bios);
+    }
+
+    #[test]
+    fn test_tape_routines() {
+        let slots = [
+            SlotType::Empty,
+            SlotType::Empty,
+            SlotType::Empty,
+            SlotType::Empty,
+        ];
+        let bus = Rc::new(RefCell::new(Bus::new(
+            &slots,
+            Rc::new(RefCell::new(VecDeque::new())),
+        )));
+        let mut driver = CassetteDriver { bus: bus.clone() };
+
+        // TAPION without a tape fails
+        let mut tapion = state(0xF0, 0);
+        driver.extension_begin(&mut tapion);
+        assert!(tapion.carry_flag());
+
+        let mut image = CAS_HEADER.to_vec();
+        image.push(0x42);
+        bus.borrow_mut().cassette.insert(image).unwrap();
+        let mut tapion = state(0xF0, 0);
+        driver.extension_begin(&mut tapion);
+        assert!(!tapion.carry_flag());
+        assert_eq!(bus.borrow().ppi.register_c() & 0x10, 0, "motor on");
+
+        let mut tapin = state(0xF1, 0);
+        driver.extension_begin(&mut tapin);
+        assert_eq!((tapin.a, tapin.carry_flag()), (0x42, false));
+        driver.extension_begin(&mut tapin);
+        assert!(tapin.carry_flag(), "end of tape");
+
+        // STMOTR with 0xFF toggles the motor
+        driver.extension_begin(&mut state(0xF6, 0xFF));
+        assert!(
+            !bus.borrow().cassette.motor(),
+            "the relay follows PPI port C"
+        );
+        assert_eq!(bus.borrow().ppi.register_c() & 0x10, 0x10);
+    }
+}
@@ -0,0 +1,327 @@
+//! Building blocks shared by the Yamaha two-operator FM chips (YM2413, Y8950).
+
+use std::f32::consts::PI;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+/// Operator parameters, decoded from each chip's own register layout
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct OperatorPatch {
+    pub am: bool,
+    pub pm: bool,
+    pub sustained: bool,
+    pub key_scale_rate: bool,
+    pub multiple: u8,
+    pub key_scale_level: u8,
+    pub half_sine: bool,
+    pub attack: u8,
+    pub decay: u8,
+    pub sustain_level: u8,
+    pub release: u8,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub(crate) struct Operator {
+    pub phase: u32,
+    envelope: EnvelopeState,
+    envelope_phase: u32,
+    pub key_on: bool,
+    // Last two outputs, used for the modulator's self feedback
+    pub feedback: [f32; 2],
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    // Sustained tones hold their level until the key is released
+    SustainHold,
+    // Percussive tones keep decaying at the release rate
+    Sustain,
+    Release,
+    #[default]
+    Finished,
+}
+
+impl Operator {
+    pub fn set_key(&mut self, key_on: bool, attack: u8) {
+        self.key_on = key_on;
+        if key_on {
+            self.phase = 0;
+            self.envelope_phase = 0;
+            self.envelope = if attack == 15 {
+                EnvelopeState::Decay
+            } else {
+                EnvelopeState::Attack
+            };
+        } else if self.envelope != EnvelopeState::Finished {
+            if self.envelope == EnvelopeState::Attack {
+                // Release from the level the attack has reached
+                let level = ATTACK_CURVE[(self.envelope_phase >> ENVELOPE_SHIFT) as usize];
+                self.envelope_phase = (level as u32) << ENVELOPE_SHIFT;
+            }
+            self.envelope = EnvelopeState::Release;
+        }
+    }
+
+    /// Advances the phase and envelope generators by one native sample.
+    ///
+    /// `fnum` is a 10 bit F-number, as on the OPL; the OPLL's 9 bit one is shifted up.
+    /// `pm` is the vibrato offset in cents and `release` the rate used once the key is
+    /// released, which each chip picks differently.
+    pub fn advance(&mut self, patch: &OperatorPatch, fnum: u32, block: u32, pm: f32, release: u8) {
+        // Phase generator, a full cycle is 2^18
+        let mut increment = ((fnum * MULTIPLIERS[patch.multiple as usize]) << block) >> 3;
+        if patch.pm {
+            increment = (increment as f32 * (pm / 1200.0).exp2()) as u32;
+        }
+        self.phase = (self.phase + increment) & (PHASE_WIDTH - 1);
+
+        // Envelope generator
+        let key_rate = ((block << 1) | (fnum >> 9)) >> if patch.key_scale_rate { 0 } else { 2 };
+        let sustain_level = if patch.sustain_level == 15 {
+            ENVELOPE_WIDTH
+        } else {
+            (patch.sustain_level as u32 * 8) << ENVELOPE_SHIFT
+        };
+        match self.envelope {
+            EnvelopeState::Attack => {
+                self.envelope_phase += attack_increment(patch.attack, key_rate);
+                if self.envelope_phase >= ENVELOPE_WIDTH {
+                    self.envelope_phase = 0;
+                    self.envelope = EnvelopeState::Decay;
+                }
+            }
+            EnvelopeState::Decay => {
+                self.envelope_phase += decay_increment(patch.decay, key_rate);
+                if self.envelope_phase >= sustain_level {
+                    self.envelope_phase = sustain_level;
+                    self.envelope = if patch.sustained {
+                        EnvelopeState::SustainHold
+                    } else {
+                        EnvelopeState::Sustain
+                    };
+                }
+            }
+            EnvelopeState::SustainHold => {
+                if !patch.sustained {
+                    self.envelope = EnvelopeState::Sustain;
+                }
+            }
+            EnvelopeState::Sustain | EnvelopeState::Release => {
+                let rate = if self.envelope == EnvelopeState::Sustain {
+                    patch.release
+                } else {
+                    release
+                };
+                self.envelope_phase += decay_increment(rate, key_rate);
+                if self.envelope_phase >= ENVELOPE_WIDTH {
+                    self.envelope_phase = ENVELOPE_WIDTH;
+                    self.envelope = EnvelopeState::Finished;
+                }
+            }
+            EnvelopeState::Finished => self.envelope_phase = ENVELOPE_WIDTH,
+        }
+    }
+
+    /// Envelope attenuation in 0.375dB steps, or `None` once the operator is silent
+    pub fn envelope_level(&self) -> Option<u32> {
+        let level = match self.envelope {
+            EnvelopeState::Finished => return None,
+            EnvelopeState::Attack => {
+                ATTACK_CURVE[(self.envelope_phase >> ENVELOPE_SHIFT).min(127) as usize] as u32
+            }
+            _ => (self.envelope_phase >> ENVELOPE_SHIFT).min(127),
+        };
+        (level < 127).then_some(level)
+    }
+
+    /// Phase as a fraction of a cycle
+    pub fn phase(&self) -> f32 {
+        self.phase as f32 / PHASE_WIDTH as f32
+    }
+
+    /// The top 9 bits of the phase, which the rhythm section taps
+    pub fn phase_bits(&self) -> u32 {
+        self.phase >> (PHASE_BITS - 9)
+    }
+}
+
+/// Low frequency oscillators and noise generator, clocked once per native sample
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub(crate) struct Modulation {
+    am_phase: f32,
+    pm_phase: f32,
+    noise: u32,
+}
+
+impl Modulation {
+    pub fn advance(&mut self, native_rate: f32) {
+        self.am_phase = (self.am_phase + AM_FREQUENCY / native_rate) % 1.0;
+        self.pm_phase = (self.pm_phase + PM_FREQUENCY / native_rate) % 1.0;
+        if self.noise & 1 != 0 {
+            self.noise ^= 0x0080_0302;
+        }
+        self.noise >>= 1;
+    }
+
+    /// Tremolo attenuation in 0.375dB steps, a triangle from 0 to `depth`
+    pub fn am(&self, depth: f32) -> u32 {
+        let triangle = 1.0 - (2.0 * self.am_phase - 1.0).abs();
+        (triangle * depth) as u32
+    }
+
+    /// Vibrato in cents
+    pub fn pm(&self, depth_cents: f32) -> f32 {
+        (2.0 * PI * self.pm_phase).sin() * depth_cents
+    }
+
+    pub fn noise(&self) -> bool {
+        self.noise & 1 != 0
+    }
+}
+
+impl Default for Modulation {
+    fn default() -> Self {
+        Self {
+            am_phase: 0.0,
+            pm_phase: 0.0,
+            noise: 1,
+        }
+    }
+}
+
+/// One sample of a sine (or positive half sine) operator at the given attenuation
+pub(crate) fn wave(phase: f32, half_sine: bool, attenuation: u32) -> f32 {
+    let sample = SINE[(phase.rem_euclid(1.0) * SINE.len() as f32) as usize & (SINE.len() - 1)];
+    if sample < 0.0 && half_sine {
+        0.0
+    } else {
+        sample * decibels(attenuation)
+    }
+}
+
+/// Phase offset, in cycles, fed back from a modulator's last two outputs
+pub(crate) fn feedback_offset(feedback: u8, history: [f32; 2]) -> f32 {
+    if feedback == 0 {
+        0.0
+    } else {
+        // Feedback 1 modulates by pi/16, every step doubles it
+        (history[0] + history[1]) / 2.0 * (1 << (feedback - 1)) as f32 / 32.0
+    }
+}
+
+/// The hi-hat and top cymbal mix the phase bits of both operators into a metallic tone
+pub(crate) fn metallic(hat: &Operator, cymbal: &Operator) -> bool {
+    let bit = |phase: u32, bit: u32| phase >> bit & 1 != 0;
+    let (hat, cymbal) = (hat.phase_bits(), cymbal.phase_bits());
+    ((bit(hat, 1) ^ bit(hat, 8)) | bit(hat, 2)) ^ (bit(cymbal, 2) & !bit(cymbal, 4))
+}
+
+pub(crate) fn hi_hat(attenuation: u32, noise: bool, metallic: bool) -> f32 {
+    let level = if noise { 12 * 8 / 3 } else { 24 * 8 / 3 };
+    let sign = if metallic { -1.0 } else { 1.0 };
+    sign * decibels(attenuation + level)
+}
+
+pub(crate) fn snare_drum(attenuation: u32, noise: bool, hat: &Operator) -> f32 {
+    let level = if noise { 0 } else { 15 * 8 / 3 };
+    let sign = if hat.phase_bits() & 0x100 != 0 {
+        1.0
+    } else {
+        -1.0
+    };
+    sign * decibels(attenuation + level)
+}
+
+pub(crate) fn top_cymbal(attenuation: u32, metallic: bool) -> f32 {
+    let sign = if metallic { -1.0 } else { 1.0 };
+    sign * decibels(attenuation + 3 * 8 / 3)
+}
+
+fn attack_increment(rate: u8, key_rate: u32) -> u32 {
+    if rate == 0 {
+        return 0;
+    }
+    let (shift, low) = effective_rate(rate, key_rate);
+    (3 * (low + 4)) << (shift + 1)
+}
+
+fn decay_increment(rate: u8, key_rate: u32) -> u32 {
+    if rate == 0 {
+        return 0;
+    }
+    let (shift, low) = effective_rate(rate, key_rate);
+    (low + 4) << (shift - 1)
+}
+
+fn effective_rate(rate: u8, key_rate: u32) -> (u32, u32) {
+    ((rate as u32 + (key_rate >> 2)).min(15), key_rate & 3)
+}
+
+/// Key scale level attenuation in 0.375dB steps
+pub(crate) fn key_scale_level(level: u8, fnum: u32, block: u32) -> u32 {
+    if level == 0 {
+        return 0;
+    }
+    let decibels = KEY_SCALE_LEVELS[(fnum >> 6) as usize] - 6.0 * (7 - block) as f32;
+    if decibels <= 0.0 {
+        return 0;
+    }
+    (decibels / (1 << (3 - level)) as f32 / 0.375) as u32
+}
+
+/// Linear gain of an attenuation in 0.375dB steps
+pub(crate) fn decibels(attenuation: u32) -> f32 {
+    DECIBELS.get(attenuation as usize).copied().unwrap_or(0.0)
+}
+
+static SINE: Lazy<Vec<f32>> = Lazy::new(|| {
+    (0..1024)
+        .map(|i| (2.0 * PI * i as f32 / 1024.0).sin())
+        .collect()
+});
+
+static DECIBELS: Lazy<Vec<f32>> = Lazy::new(|| {
+    (0..512)
+        .map(|step| 10f32.powf(-(step as f32 * 0.375) / 20.0))
+        .collect()
+});
+
+/// The attack is exponential: maps the linear attack phase to an attenuation
+static ATTACK_CURVE: Lazy<Vec<u8>> = Lazy::new(|| {
+    (0..128)
+        .map(|i| {
+            if i == 0 {
+                127
+            } else {
+                (127.0 - 127.0 * (i as f32).ln() / 128f32.ln()).round() as u8
+            }
+        })
+        .collect()
+});
+
+/// Frequency multiplier times two, so the halved first entry stays an integer
+const MULTIPLIERS: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+/// Key scale level attenuation per top 4 bits of the F-number, in dB at block 7
+const KEY_SCALE_LEVELS: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25,
+    42.0,
+];
+
+const PHASE_BITS: u32 = 18;
+const PHASE_WIDTH: u32 = 1 << PHASE_BITS;
+/// The envelope phase has 7 bits of 0.375dB above `ENVELOPE_SHIFT` fractional bits
+const ENVELOPE_SHIFT: u32 = 15;
+const ENVELOPE_WIDTH: u32 = 128 << ENVELOPE_SHIFT;
+
+const AM_FREQUENCY: f32 = 3.6413;
+const PM_FREQUENCY: f32 = 6.4;
+
+/// A full scale modulator shifts the carrier phase by up to four cycles (8 pi)
+pub(crate) const MODULATION_DEPTH: f32 = 4.0;
+/// The chips produce one sample every 72 clocks
+pub(crate) const CLOCKS_PER_SAMPLE: u32 = 72;
+pub const NATIVE_RATE: f32 = crate::clock::CPU_CLOCK_HZ as f32 / CLOCKS_PER_SAMPLE as f32;
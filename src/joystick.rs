@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::mouse::Mouse;
+
+/// Directions and triggers of an MSX joystick, true while held
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JoystickState {
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+    pub trigger_a: bool,
+    pub trigger_b: bool,
+}
+
+impl JoystickState {
+    /// Builds a state from bits 0-5 set for up, down, left, right, trigger A and B
+    pub fn from_bits(bits: u8) -> Self {
+        let held = |button: JoystickButton| bits & button.mask() != 0;
+        Self {
+            up: held(JoystickButton::Up),
+            down: held(JoystickButton::Down),
+            left: held(JoystickButton::Left),
+            right: held(JoystickButton::Right),
+            trigger_a: held(JoystickButton::TriggerA),
+            trigger_b: held(JoystickButton::TriggerB),
+        }
+    }
+
+    /// Held buttons as bits 0-5, in the order of PSG register 14
+    pub fn bits(&self) -> u8 {
+        [
+            self.up,
+            self.down,
+            self.left,
+            self.right,
+            self.trigger_a,
+            self.trigger_b,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (bit, &held)| bits | (held as u8) << bit)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum JoystickButton {
+    Up,
+    Down,
+    Left,
+    Right,
+    TriggerA,
+    TriggerB,
+}
+
+impl JoystickButton {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "up" => Some(JoystickButton::Up),
+            "down" => Some(JoystickButton::Down),
+            "left" => Some(JoystickButton::Left),
+            "right" => Some(JoystickButton::Right),
+            "a" | "trigger-a" => Some(JoystickButton::TriggerA),
+            "b" | "trigger-b" => Some(JoystickButton::TriggerB),
+            _ => None,
+        }
+    }
+
+    /// Bit of the button in PSG register 14
+    pub fn mask(&self) -> u8 {
+        1 << *self as u8
+    }
+}
+
+/// What is plugged into a joystick port
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PortDevice {
+    #[default]
+    Joystick,
+    Mouse,
+}
+
+impl PortDevice {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "joystick" => Some(PortDevice::Joystick),
+            "mouse" => Some(PortDevice::Mouse),
+            _ => None,
+        }
+    }
+}
+
+/// Where host keys with a joystick binding go
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum KeyRouting {
+    /// Every key presses its matrix key; bindings are ignored
+    Keyboard,
+    /// Bound keys press their joystick button only
+    Joystick,
+    /// Bound keys press both, for games that read either, as the default arrow key and
+    /// Space bindings always did
+    #[default]
+    Both,
+}
+
+impl KeyRouting {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "keyboard" => Some(KeyRouting::Keyboard),
+            "joystick" => Some(KeyRouting::Joystick),
+            "both" => Some(KeyRouting::Both),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyRouting::Keyboard => "keyboard",
+            KeyRouting::Joystick => "joystick",
+            KeyRouting::Both => "both",
+        }
+    }
+}
+
+/// The two joystick ports, fed by the host API and by key bindings, or a mouse.
+///
+/// Key bindings only take effect when the key routing sends keys to the joysticks.
+/// A joystick button counts as held while either source holds it. Buttons with autofire pulse
+/// while held, on for `AUTOFIRE_FRAMES` video frames and off for as many, which is
+/// what the turbo switch of a joypad does.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Joysticks {
+    // Set through `set_state`, e.g. from a gamepad
+    states: [JoystickState; 2],
+    // Buttons held through key bindings, as register 14 bits
+    keys: [u8; 2],
+    bindings: HashMap<String, (usize, JoystickButton)>,
+    routing: KeyRouting,
+    // Register 14 bits of the buttons that fire repeatedly
+    autofire: [u8; 2],
+    frame: u32,
+    // Ports with a mouse instead of a joystick
+    mice: [Option<Mouse>; 2],
+}
+
+impl Joysticks {
+    pub fn new() -> Self {
+        let mut joysticks = Self {
+            states: [JoystickState::default(); 2],
+            keys: [0; 2],
+            bindings: HashMap::new(),
+            routing: KeyRouting::default(),
+            autofire: [0; 2],
+            frame: 0,
+            mice: [None, None],
+        };
+        joysticks.set_default_bindings();
+        joysticks
+    }
+
+    /// Releases every button; bindings, routing and autofire settings are kept
+    pub fn reset(&mut self) {
+        self.states = [JoystickState::default(); 2];
+        self.keys = [0; 2];
+        self.frame = 0;
+        for mouse in self.mice.iter_mut().flatten() {
+            *mouse = Mouse::new();
+        }
+    }
+
+    pub fn device(&self, port: usize) -> PortDevice {
+        match self.mice[port] {
+            Some(_) => PortDevice::Mouse,
+            None => PortDevice::Joystick,
+        }
+    }
+
+    pub fn set_device(&mut self, port: usize, device: PortDevice) {
+        if device != self.device(port) {
+            self.mice[port] = match device {
+                PortDevice::Joystick => None,
+                PortDevice::Mouse => Some(Mouse::new()),
+            };
+        }
+    }
+
+    /// Host mouse motion for every plugged in mouse, right and down being positive
+    pub fn move_mouse(&mut self, dx: i32, dy: i32) {
+        for mouse in self.mice.iter_mut().flatten() {
+            mouse.move_by(dx, dy);
+        }
+    }
+
+    pub fn set_mouse_buttons(&mut self, left: bool, right: bool) {
+        for mouse in self.mice.iter_mut().flatten() {
+            mouse.set_buttons(left, right);
+        }
+    }
+
+    /// Passes a write to PSG register 15 on to the devices, with each port's output pins
+    pub fn write_pins(&mut self, pins: [u8; 2], cycle: u64) {
+        for (mouse, pins) in self.mice.iter_mut().zip(pins) {
+            if let Some(mouse) = mouse {
+                mouse.write(pins, cycle);
+            }
+        }
+    }
+
+    pub fn state(&self, port: usize) -> JoystickState {
+        self.states[port]
+    }
+
+    pub fn set_state(&mut self, port: usize, state: JoystickState) {
+        self.states[port] = state;
+    }
+
+    pub fn routing(&self) -> KeyRouting {
+        self.routing
+    }
+
+    /// Changes where bound keys go, releasing the buttons they hold
+    pub fn set_routing(&mut self, routing: KeyRouting) {
+        self.routing = routing;
+        self.keys = [0; 2];
+    }
+
+    /// The arrow keys and Space drive port 1, as before joysticks could be configured
+    pub fn set_default_bindings(&mut self) {
+        self.bindings.clear();
+        for (key, button) in [
+            ("ArrowUp", JoystickButton::Up),
+            ("ArrowDown", JoystickButton::Down),
+            ("ArrowLeft", JoystickButton::Left),
+            ("ArrowRight", JoystickButton::Right),
+            ("Space", JoystickButton::TriggerA),
+        ] {
+            self.bind(key, 0, button);
+        }
+    }
+
+    /// Maps a host key code (as in `KeyboardEvent.code`) to a joystick button
+    pub fn bind(&mut self, key: &str, port: usize, button: JoystickButton) {
+        self.bindings.insert(key.to_string(), (port, button));
+    }
+
+    pub fn unbind(&mut self, key: &str) {
+        self.bindings.remove(key);
+    }
+
+    pub fn clear_bindings(&mut self) {
+        self.bindings.clear();
+        self.keys = [0; 2];
+    }
+
+    pub fn binding(&self, key: &str) -> Option<(usize, JoystickButton)> {
+        self.bindings.get(key).copied()
+    }
+
+    /// Presses the button bound to `key` as the routing allows; returns whether the
+    /// keyboard should see the key as well
+    pub fn key_down(&mut self, key: &str) -> bool {
+        match (self.routing, self.binding(key)) {
+            (KeyRouting::Keyboard, _) | (_, None) => true,
+            (routing, Some((port, button))) => {
+                self.keys[port] |= button.mask();
+                routing == KeyRouting::Both
+            }
+        }
+    }
+
+    /// Releases the button bound to `key`, whatever the routing, since it may have
+    /// changed while the key was held
+    pub fn key_up(&mut self, key: &str) {
+        if let Some((port, button)) = self.binding(key) {
+            self.keys[port] &= !button.mask();
+        }
+    }
+
+    pub fn autofire(&self, port: usize, button: JoystickButton) -> bool {
+        self.autofire[port] & button.mask() != 0
+    }
+
+    pub fn set_autofire(&mut self, port: usize, button: JoystickButton, enabled: bool) {
+        if enabled {
+            self.autofire[port] |= button.mask();
+        } else {
+            self.autofire[port] &= !button.mask();
+        }
+    }
+
+    /// Called at the end of every video frame to step autofire
+    pub fn end_frame(&mut self) {
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    /// Pins 1-4, 6 and 7 of a port as PSG register 14 bits 0-5, active low, with
+    /// bits 6 and 7 high
+    pub fn lines(&self, port: usize) -> u8 {
+        if let Some(mouse) = &self.mice[port] {
+            return mouse.lines();
+        }
+        let mut held = self.states[port].bits() | self.keys[port];
+        if (self.frame / AUTOFIRE_FRAMES) % 2 == 1 {
+            held &= !self.autofire[port];
+        }
+        !held
+    }
+}
+
+impl Default for Joysticks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Autofire half period in video frames, about 10 presses per second at 60Hz
+pub const AUTOFIRE_FRAMES: u32 = 3;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_bits_and_bindings() {
+        let state = JoystickState {
+            up: true,
+            trigger_b: true,
+            ..Default::default()
+        };
+        assert_eq!(state.bits(), 0b10_0001);
+        assert_eq!(JoystickState::from_bits(0b10_0001), state);
+
+        let mut joysticks = Joysticks::new();
+        joysticks.set_routing(KeyRouting::Joystick);
+        joysticks.set_state(1, state);
+        assert_eq!(joysticks.lines(1), 0xDE);
+        assert_eq!(joysticks.lines(0), 0xFF);
+
+        assert!(!joysticks.key_down("Space"), "taken by the joystick");
+        assert!(joysticks.key_down("KeyA"), "unbound");
+        assert_eq!(joysticks.lines(0), 0xEF);
+
+        // Key bindings and the host state combine on port 2
+        joysticks.bind("KeyA", 1, JoystickButton::Left);
+        joysticks.key_down("KeyA");
+        assert_eq!(joysticks.lines(1), 0xDA);
+        joysticks.key_up("Space");
+        assert_eq!(joysticks.lines(0), 0xFF);
+    }
+
+    #[test]
+    fn test_key_routing() {
+        let mut joysticks = Joysticks::new();
+        assert!(joysticks.key_down("ArrowUp"), "both by default");
+        assert_eq!(joysticks.lines(0), 0xFE);
+
+        // Switching releases the button, and a release after switching is harmless
+        joysticks.set_routing(KeyRouting::Keyboard);
+        assert_eq!(joysticks.lines(0), 0xFF);
+        joysticks.key_up("ArrowUp");
+        assert!(joysticks.key_down("ArrowUp"));
+        assert_eq!(joysticks.lines(0), 0xFF, "keyboard only");
+        assert_eq!(
+            KeyRouting::from_name("Joystick"),
+            Some(KeyRouting::Joystick)
+        );
+    }
+
+    #[test]
+    fn test_autofire_pulses_held_buttons() {
+        let mut joysticks = Joysticks::new();
+        joysticks.set_autofire(0, JoystickButton::TriggerA, true);
+        joysticks.set_state(
+            0,
+            JoystickState {
+                trigger_a: true,
+                right: true,
+                ..Default::default()
+            },
+        );
+
+        let mut pressed = Vec::new();
+        for _ in 0..AUTOFIRE_FRAMES * 4 {
+            let lines = joysticks.lines(0);
+            assert_eq!(lines & 0x08, 0, "right has no autofire");
+            pressed.push(lines & 0x10 == 0);
+            joysticks.end_frame();
+        }
+        let on = [true; AUTOFIRE_FRAMES as usize];
+        let off = [false; AUTOFIRE_FRAMES as usize];
+        assert_eq!(pressed, [on, off, on, off].concat());
+
+        joysticks.set_autofire(0, JoystickButton::TriggerA, false);
+        assert_eq!(joysticks.lines(0) & 0x10, 0);
+    }
+
+    #[test]
+    fn test_mouse_replaces_the_joystick() {
+        let mut joysticks = Joysticks::new();
+        joysticks.set_routing(KeyRouting::Joystick);
+        joysticks.key_down("ArrowUp");
+        joysticks.set_device(1, PortDevice::Mouse);
+        joysticks.move_mouse(-2, 0);
+        joysticks.set_mouse_buttons(false, true);
+
+        // Pin 8 of port 2 rises: the high nibble of X
+        joysticks.write_pins([0x00, 0x04], 100);
+        assert_eq!(joysticks.lines(1), 0xD0);
+        joysticks.write_pins([0x00, 0x00], 200);
+        assert_eq!(joysticks.lines(1), 0xD2);
+        assert_eq!(joysticks.lines(0), 0xFE, "port 1 keeps its joystick");
+
+        joysticks.set_device(1, PortDevice::Joystick);
+        assert_eq!(joysticks.lines(1), 0xFF);
+    }
+}
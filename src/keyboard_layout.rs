@@ -0,0 +1,695 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+/// Regional keyboards, which differ in the legends of matrix rows 0-5 and in a few
+/// extra keys
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum KeyboardLayout {
+    #[default]
+    International,
+    Japanese,
+    Uk,
+    German,
+    Spanish,
+    Brazilian,
+    Russian,
+    Korean,
+}
+
+/// A key of the MSX matrix, with the host key that presses it and its legends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayoutKey {
+    pub row: u8,
+    pub col: u8,
+    /// Host key code (`KeyboardEvent.code`) at the same place on a host keyboard of
+    /// the region; several host keys can press the same matrix key
+    pub code: Option<&'static str>,
+    pub normal: Option<char>,
+    pub shifted: Option<char>,
+}
+
+/// The matrix key and the modifiers that type a character
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyStroke {
+    pub row: u8,
+    pub col: u8,
+    pub shift: bool,
+    pub graph: bool,
+    pub code: bool,
+}
+
+impl KeyStroke {
+    /// Matrix positions to hold, modifiers first
+    pub fn keys(&self) -> Vec<(u8, u8)> {
+        [
+            (self.shift, SHIFT),
+            (self.graph, GRAPH),
+            (self.code, CODE),
+            (true, (self.row, self.col)),
+        ]
+        .iter()
+        .filter(|(held, _)| *held)
+        .map(|(_, key)| *key)
+        .collect()
+    }
+}
+
+pub const SHIFT: (u8, u8) = (6, 0);
+pub const GRAPH: (u8, u8) = (6, 2);
+pub const CODE: (u8, u8) = (6, 4);
+
+impl KeyboardLayout {
+    pub const ALL: [KeyboardLayout; 8] = [
+        KeyboardLayout::International,
+        KeyboardLayout::Japanese,
+        KeyboardLayout::Uk,
+        KeyboardLayout::German,
+        KeyboardLayout::Spanish,
+        KeyboardLayout::Brazilian,
+        KeyboardLayout::Russian,
+        KeyboardLayout::Korean,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "international" | "int" => Some(KeyboardLayout::International),
+            "japanese" | "jis" | "jp" => Some(KeyboardLayout::Japanese),
+            "uk" => Some(KeyboardLayout::Uk),
+            "german" | "din" | "de" => Some(KeyboardLayout::German),
+            "spanish" | "es" => Some(KeyboardLayout::Spanish),
+            "brazilian" | "hotbit" | "br" => Some(KeyboardLayout::Brazilian),
+            "russian" | "ru" => Some(KeyboardLayout::Russian),
+            "korean" | "kr" => Some(KeyboardLayout::Korean),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyboardLayout::International => "international",
+            KeyboardLayout::Japanese => "japanese",
+            KeyboardLayout::Uk => "uk",
+            KeyboardLayout::German => "german",
+            KeyboardLayout::Spanish => "spanish",
+            KeyboardLayout::Brazilian => "brazilian",
+            KeyboardLayout::Russian => "russian",
+            KeyboardLayout::Korean => "korean",
+        }
+    }
+
+    /// Picks the layout a main BIOS was made for from its ID bytes at 0x002B-0x002C.
+    ///
+    /// Brazilian machines identify as international, so the BIOS key table is looked
+    /// at for the ç key as well. Keyboards without a layout of their own, like the
+    /// French AZERTY one, fall back to international.
+    pub fn detect(bios: &[u8]) -> Self {
+        if bios.len() < 0x2D {
+            return KeyboardLayout::default();
+        }
+        let charset = bios[0x2B] & 0x0F;
+        let keyboard = bios[0x2C] & 0x0F;
+        match (charset, keyboard) {
+            (0, _) | (_, 0) => KeyboardLayout::Japanese,
+            (2, _) => KeyboardLayout::Korean,
+            (_, 3) => KeyboardLayout::Uk,
+            (_, 4) => KeyboardLayout::German,
+            (_, 5) => KeyboardLayout::Russian,
+            (_, 6) => KeyboardLayout::Spanish,
+            _ if has_cedilla_key(bios) => KeyboardLayout::Brazilian,
+            _ => KeyboardLayout::International,
+        }
+    }
+
+    pub fn keys(&self) -> &'static [LayoutKey] {
+        &LAYOUTS[*self as usize]
+    }
+
+    /// How to type `c` on this keyboard: a key legend, with Shift for the upper one,
+    /// or a GRAPH or CODE combination. `'\n'` is the Enter key.
+    pub fn stroke(&self, c: char) -> Option<KeyStroke> {
+        let keys = self.keys().iter();
+        let legend = keys
+            .filter_map(|key| {
+                let stroke = KeyStroke {
+                    row: key.row,
+                    col: key.col,
+                    ..Default::default()
+                };
+                match (key.normal, key.shifted) {
+                    (Some(normal), _) if normal == c => Some(stroke),
+                    (_, Some(shifted)) if shifted == c => Some(KeyStroke {
+                        shift: true,
+                        ..stroke
+                    }),
+                    _ => None,
+                }
+            })
+            .min_by_key(|stroke| stroke.shift);
+        legend.or_else(|| {
+            COMBOS[*self as usize]
+                .iter()
+                .find(|(legend, _)| *legend == c)
+                .map(|(_, stroke)| *stroke)
+        })
+    }
+}
+
+/// The BIOS key table starts with the digits row; on Brazilian machines the last key
+/// of row 1 is ç (0x87 in the MSX character set)
+fn has_cedilla_key(bios: &[u8]) -> bool {
+    bios.windows(10)
+        .position(|window| window == b"0123456789")
+        .and_then(|start| bios.get(start + 15))
+        == Some(&0x87)
+}
+
+static LAYOUTS: Lazy<Vec<Vec<LayoutKey>>> = Lazy::new(|| {
+    KeyboardLayout::ALL
+        .iter()
+        .map(|layout| {
+            let sections: &[&'static str] = match layout {
+                KeyboardLayout::International => &[INTERNATIONAL, LETTERS],
+                KeyboardLayout::Japanese => &[JAPANESE, LETTERS, JAPANESE_EXTRA],
+                KeyboardLayout::Uk => &[UK, INTERNATIONAL, LETTERS],
+                KeyboardLayout::German => &[GERMAN, GERMAN_LETTERS, LETTERS],
+                KeyboardLayout::Spanish => &[SPANISH, INTERNATIONAL, LETTERS],
+                KeyboardLayout::Brazilian => &[BRAZILIAN, LETTERS],
+                KeyboardLayout::Russian => &[RUSSIAN, RUSSIAN_LETTERS],
+                KeyboardLayout::Korean => &[KOREAN, INTERNATIONAL, LETTERS],
+            };
+            parse_layout(sections.iter().chain(&[CONTROL]))
+        })
+        .collect()
+});
+
+static COMBOS: Lazy<Vec<Vec<(char, KeyStroke)>>> = Lazy::new(|| {
+    KeyboardLayout::ALL
+        .iter()
+        .map(|layout| match layout {
+            KeyboardLayout::International | KeyboardLayout::Uk | KeyboardLayout::Brazilian => {
+                parse_combos(INTERNATIONAL_COMBOS)
+            }
+            _ => Vec::new(),
+        })
+        .collect()
+});
+
+fn parse_combos(section: &str) -> Vec<(char, KeyStroke)> {
+    section
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (modifiers, legend) = (fields.first()?, fields.get(3)?);
+            let stroke = KeyStroke {
+                row: fields[1].parse().expect("combo row"),
+                col: fields[2].parse().expect("combo column"),
+                shift: modifiers.contains("shift"),
+                graph: modifiers.contains("graph"),
+                code: modifiers.contains("code"),
+            };
+            Some((legend.chars().next()?, stroke))
+        })
+        .collect()
+}
+
+/// Parses layout sections. A matrix key or host key defined by an earlier section
+/// replaces its lines in later ones.
+fn parse_layout<'a>(sections: impl Iterator<Item = &'a &'static str>) -> Vec<LayoutKey> {
+    let mut keys: Vec<LayoutKey> = Vec::new();
+    for section in sections {
+        let defined = keys.len();
+        for line in section.lines() {
+            let fields: Vec<&'static str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let key = LayoutKey {
+                row: fields[0].parse().expect("layout row"),
+                col: fields[1].parse().expect("layout column"),
+                code: Some(fields[2]).filter(|code| *code != "--"),
+                normal: fields.get(3).and_then(|field| legend(field)),
+                shifted: fields.get(4).and_then(|field| legend(field)),
+            };
+            let replaced = keys[..defined].iter().any(|other| {
+                (other.row, other.col) == (key.row, key.col)
+                    || (key.code.is_some() && other.code == key.code)
+            });
+            if !replaced {
+                keys.push(key);
+            }
+        }
+    }
+    keys
+}
+
+fn legend(field: &str) -> Option<char> {
+    let mut chars = field.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => match field {
+            "space" => Some(' '),
+            "enter" => Some('\n'),
+            "tab" => Some('\t'),
+            _ => None,
+        },
+    }
+}
+
+// Each line is: row column host-code normal shifted, with `--` for none. Host codes
+// are `KeyboardEvent.code` values; lines without one only add a legend.
+
+const INTERNATIONAL: &str = r#"
+0 0 Digit0 0 )
+0 1 Digit1 1 !
+0 2 Digit2 2 @
+0 3 Digit3 3 #
+0 4 Digit4 4 $
+0 5 Digit5 5 %
+0 6 Digit6 6 ^
+0 7 Digit7 7 &
+1 0 Digit8 8 *
+1 1 Digit9 9 (
+1 2 Minus - _
+1 3 Equal = +
+1 4 Backslash \ |
+1 5 BracketLeft [ {
+1 6 BracketRight ] }
+1 7 Semicolon ; :
+2 0 Quote ' "
+2 1 Backquote ` ~
+2 2 Comma , <
+2 3 Period . >
+2 4 Slash / ?
+2 5 IntlRo -- --
+"#;
+
+const LETTERS: &str = r#"
+2 6 KeyA a A
+2 7 KeyB b B
+3 0 KeyC c C
+3 1 KeyD d D
+3 2 KeyE e E
+3 3 KeyF f F
+3 4 KeyG g G
+3 5 KeyH h H
+3 6 KeyI i I
+3 7 KeyJ j J
+4 0 KeyK k K
+4 1 KeyL l L
+4 2 KeyM m M
+4 3 KeyN n N
+4 4 KeyO o O
+4 5 KeyP p P
+4 6 KeyQ q Q
+4 7 KeyR r R
+5 0 KeyS s S
+5 1 KeyT t T
+5 2 KeyU u U
+5 3 KeyV v V
+5 4 KeyW w W
+5 5 KeyX x X
+5 6 KeyY y Y
+5 7 KeyZ z Z
+"#;
+
+// Rows 6-10 are the same everywhere: modifiers, function and editing keys, cursor
+// keys and the numeric keypad. GRAPH and CODE sit either side of the space bar.
+const CONTROL: &str = r#"
+6 0 ShiftLeft
+6 0 ShiftRight
+6 1 ControlLeft
+6 1 ControlRight
+6 2 AltLeft
+6 3 CapsLock
+6 4 AltRight
+6 5 F1
+6 6 F2
+6 7 F3
+7 0 F4
+7 1 F5
+7 2 Escape
+7 3 Tab tab
+7 4 Pause
+7 4 F8
+7 5 Backspace
+7 6 F7
+7 6 ScrollLock
+7 7 Enter enter
+7 7 NumpadEnter
+8 0 Space space
+8 1 Home
+8 2 Insert
+8 3 Delete
+8 4 ArrowLeft
+8 5 ArrowUp
+8 6 ArrowDown
+8 7 ArrowRight
+9 0 NumpadMultiply
+9 1 NumpadAdd
+9 2 NumpadDivide
+9 3 Numpad0
+9 4 Numpad1
+9 5 Numpad2
+9 6 Numpad3
+9 7 Numpad4
+10 0 Numpad5
+10 1 Numpad6
+10 2 Numpad7
+10 3 Numpad8
+10 4 Numpad9
+10 5 NumpadSubtract
+10 6 NumpadComma
+10 7 NumpadDecimal
+"#;
+
+// JIS: the yen key, @ and [ right of P, : and ] on the home row, and _ (ro) next to
+// the right shift. Yen is the MSX backslash.
+const JAPANESE: &str = r#"
+0 0 Digit0 0 --
+0 1 Digit1 1 !
+0 2 Digit2 2 "
+0 3 Digit3 3 #
+0 4 Digit4 4 $
+0 5 Digit5 5 %
+0 6 Digit6 6 &
+0 7 Digit7 7 '
+1 0 Digit8 8 (
+1 1 Digit9 9 )
+1 2 Minus - =
+1 3 Equal ^ ~
+1 4 IntlYen ¥ |
+1 4 -- \
+1 5 BracketLeft @ `
+1 6 BracketRight [ {
+1 7 Semicolon ; +
+2 0 Quote : *
+2 1 Backslash ] }
+2 2 Comma , <
+2 3 Period . >
+2 4 Slash / ?
+2 5 IntlRo _ _
+"#;
+
+// The Kana lock is the CODE key; row 11 has the execute and cancel keys
+const JAPANESE_EXTRA: &str = r#"
+6 4 KanaMode
+6 4 AltRight
+11 1 NonConvert
+11 3 Convert
+"#;
+
+const UK: &str = r#"
+0 3 Digit3 3 £
+"#;
+
+// DIN: QWERTZ, umlauts on the right, dead accent keys next to Backspace and left of 1
+const GERMAN: &str = r#"
+0 0 Digit0 0 =
+0 1 Digit1 1 !
+0 2 Digit2 2 "
+0 3 Digit3 3 §
+0 4 Digit4 4 $
+0 5 Digit5 5 %
+0 6 Digit6 6 &
+0 7 Digit7 7 /
+1 0 Digit8 8 (
+1 1 Digit9 9 )
+1 2 Minus ß ?
+1 3 Equal -- --
+1 4 Backslash # '
+1 5 BracketLeft ü Ü
+1 6 BracketRight + *
+1 7 Semicolon ö Ö
+2 0 Quote ä Ä
+2 1 IntlBackslash < >
+2 2 Comma , ;
+2 3 Period . :
+2 4 Slash - _
+2 5 Backquote -- --
+"#;
+
+const GERMAN_LETTERS: &str = r#"
+5 6 KeyY z Z
+5 7 KeyZ y Y
+"#;
+
+// Ñ takes the place of the semicolon key, which moves next to it
+const SPANISH: &str = r#"
+1 7 Semicolon ñ Ñ
+2 1 Backquote ; :
+"#;
+
+// Hotbit and Expert: ç, dead accents and the ABNT punctuation keys
+const BRAZILIAN: &str = r#"
+0 0 Digit0 0 )
+0 1 Digit1 1 !
+0 2 Digit2 2 @
+0 3 Digit3 3 #
+0 4 Digit4 4 $
+0 5 Digit5 5 %
+0 6 Digit6 6 "
+0 7 Digit7 7 &
+1 0 Digit8 8 *
+1 1 Digit9 9 (
+1 2 Minus - _
+1 3 Equal = +
+1 4 IntlBackslash \ ^
+1 5 BracketLeft -- --
+1 6 Backquote -- '
+1 7 Semicolon ç Ç
+2 0 Quote -- --
+2 1 BracketRight [ ]
+2 2 Comma , ;
+2 3 Period . :
+2 4 IntlRo / ?
+2 5 Slash < >
+"#;
+
+// JCUKEN: the Latin legends follow the Cyrillic letters they share a key with, so
+// the host keys of a Russian keyboard land on other matrix positions
+const RUSSIAN: &str = r#"
+0 0 Digit0 0 )
+0 1 Digit1 1 !
+0 2 Digit2 2 @
+0 3 Digit3 3 #
+0 4 Digit4 4 $
+0 5 Digit5 5 %
+0 6 Digit6 6 ^
+0 7 Digit7 7 &
+1 0 Digit8 8 *
+1 1 Digit9 9 (
+1 2 Minus - _
+1 3 Equal = +
+1 4 Quote \ |
+1 5 KeyI [ {
+1 6 KeyO ] }
+1 7 BracketRight ; :
+2 0 Backslash ' "
+2 1 Backquote ` ~
+2 2 Slash , <
+2 3 Period . >
+2 4 IntlBackslash / ?
+2 5 IntlRo -- --
+"#;
+
+const RUSSIAN_LETTERS: &str = r#"
+2 6 KeyF a A
+2 7 Comma b B
+3 0 KeyW c C
+3 1 KeyL d D
+3 2 KeyT e E
+3 3 KeyA f F
+3 4 KeyU g G
+3 5 BracketLeft h H
+3 6 KeyB i I
+3 7 KeyQ j J
+4 0 KeyR k K
+4 1 KeyK l L
+4 2 KeyV m M
+4 3 KeyY n N
+4 4 KeyJ o O
+4 5 KeyG p P
+4 6 KeyZ q Q
+4 7 KeyH r R
+5 0 KeyC s S
+5 1 KeyN t T
+5 2 KeyE u U
+5 3 Semicolon v V
+5 4 KeyD w W
+5 5 KeyM x X
+5 6 KeyS y Y
+5 7 KeyP z Z
+"#;
+
+// Won in place of the backslash, and the Hangul key as CODE
+const KOREAN: &str = r#"
+1 4 Backslash ₩ |
+1 4 -- \
+6 4 Lang1
+6 4 AltRight
+"#;
+
+// Characters of the international GRAPH and CODE layers that Unicode has, as in the
+// key tables of the Hotbit BIOS: modifiers row column character
+const INTERNATIONAL_COMBOS: &str = r#"
+graph 0 1 ¼
+graph 0 2 ½
+graph 0 4 ∩
+graph 0 6 ⌠
+graph 0 7 √
+graph 1 0 ∞
+graph 1 3 ±
+graph 2 2 ≤
+graph 2 3 ≥
+graph+shift 0 2 ²
+graph+shift 0 3 ⁿ
+graph+shift 0 6 ⌡
+graph+shift 1 3 ≡
+graph+shift 2 1 ≈
+graph+shift 2 2 «
+graph+shift 2 3 »
+graph+shift 2 4 ÷
+graph+shift 2 6 ■
+graph+shift 3 0 ·
+graph+shift 4 7 ⌐
+graph+shift 5 5 ∙
+graph+shift 5 6 ¬
+graph+shift 5 7 °
+code 0 0 δ
+code 0 1 ƒ
+code 0 4 ¢
+code 0 5 ÿ
+code 0 6 α
+code 0 7 ß
+code 1 0 τ
+code 1 2 ε
+code 1 3 Θ
+code 1 5 φ
+code 2 0 ª
+code 2 1 σ
+code 2 2 Σ
+code 2 5 º
+code 2 6 æ
+code 3 1 ñ
+code 3 3 Ñ
+code 3 4 ¿
+code 3 7 π
+code 4 0 Φ
+code 4 1 Ω
+code 4 5 µ
+code 4 6 ¡
+code 4 7 £
+code 5 0 Æ
+code 5 1 ¥
+code 5 2 Γ
+code 5 4 ₧
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(layout: KeyboardLayout, code: &str) -> Option<(u8, u8)> {
+        layout
+            .keys()
+            .iter()
+            .find(|key| key.code == Some(code))
+            .map(|key| (key.row, key.col))
+    }
+
+    #[test]
+    fn test_every_layout_covers_the_matrix() {
+        for layout in KeyboardLayout::ALL {
+            let keys = layout.keys();
+            for row in 0..=10 {
+                for col in 0..8 {
+                    assert!(
+                        keys.iter().any(|key| (key.row, key.col) == (row, col)),
+                        "{} has no key at row {} column {}",
+                        layout.name(),
+                        row,
+                        col
+                    );
+                }
+            }
+            let mut codes: Vec<_> = keys.iter().filter_map(|key| key.code).collect();
+            codes.sort();
+            let count = codes.len();
+            codes.dedup();
+            assert_eq!(
+                codes.len(),
+                count,
+                "{} binds a host key twice",
+                layout.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_regional_keys() {
+        let legends = |layout: KeyboardLayout, row, col| {
+            let key = layout
+                .keys()
+                .iter()
+                .find(|key| (key.row, key.col) == (row, col) && key.code.is_some())
+                .unwrap();
+            (key.normal, key.shifted)
+        };
+        assert_eq!(
+            legends(KeyboardLayout::Japanese, 2, 0),
+            (Some(':'), Some('*'))
+        );
+        assert_eq!(legends(KeyboardLayout::Uk, 0, 3), (Some('3'), Some('£')));
+        assert_eq!(
+            legends(KeyboardLayout::Spanish, 1, 7),
+            (Some('ñ'), Some('Ñ'))
+        );
+        assert_eq!(
+            legends(KeyboardLayout::Spanish, 1, 2),
+            (Some('-'), Some('_'))
+        );
+        assert_eq!(
+            legends(KeyboardLayout::Brazilian, 1, 7),
+            (Some('ç'), Some('Ç'))
+        );
+
+        assert_eq!(position(KeyboardLayout::Japanese, "IntlYen"), Some((1, 4)));
+        assert_eq!(position(KeyboardLayout::Japanese, "KanaMode"), Some((6, 4)));
+        assert_eq!(position(KeyboardLayout::German, "KeyY"), Some((5, 6)));
+        assert_eq!(
+            legends(KeyboardLayout::German, 5, 6),
+            (Some('z'), Some('Z'))
+        );
+        assert_eq!(position(KeyboardLayout::Russian, "KeyQ"), Some((3, 7)));
+        assert_eq!(position(KeyboardLayout::International, "IntlYen"), None);
+    }
+
+    #[test]
+    fn test_detect_from_bios_id_bytes() {
+        let detect = |charset: u8, keyboard: u8, table: &[u8]| {
+            let mut bios = vec![0u8; 0x8000];
+            bios[0x2B] = charset;
+            bios[0x2C] = keyboard;
+            bios[0x1000..0x1000 + table.len()].copy_from_slice(table);
+            KeyboardLayout::detect(&bios)
+        };
+        let international = b"0123456789-=\\[];";
+        assert_eq!(detect(0x00, 0x00, international), KeyboardLayout::Japanese);
+        assert_eq!(
+            detect(0x21, 0x01, international),
+            KeyboardLayout::International
+        );
+        assert_eq!(
+            detect(0x21, 0x02, international),
+            KeyboardLayout::International,
+            "AZERTY"
+        );
+        assert_eq!(detect(0x11, 0x03, international), KeyboardLayout::Uk);
+        assert_eq!(detect(0x11, 0x04, international), KeyboardLayout::German);
+        assert_eq!(detect(0x11, 0x06, international), KeyboardLayout::Spanish);
+        assert_eq!(detect(0x02, 0x01, international), KeyboardLayout::Korean);
+
+        let hotbit = b"0123456789-=\\\xff\xff\x87";
+        assert_eq!(detect(0x11, 0x01, hotbit), KeyboardLayout::Brazilian);
+    }
+}
@@ -1,4 +1,7 @@
+pub mod audio_sync;
 pub mod bus;
+pub mod cassette;
+pub mod cassette_driver;
 pub mod clock;
 pub mod cpu_extensions;
 pub mod disk_drive;
@@ -6,16 +9,33 @@ pub mod disk_driver;
 pub mod disk_error;
 pub mod disk_rom_manager;
 pub mod dsk_image;
+pub mod fm;
 pub mod instruction;
 pub mod internal_state;
+pub mod joystick;
 pub mod keyboard;
+pub mod keyboard_layout;
 pub mod machine;
+pub mod mixer;
+pub mod mouse;
+pub mod one_bit;
+pub mod opll;
+pub mod palette;
 pub mod ppi;
 pub mod psg;
 pub mod renderer;
+pub mod resampler;
+pub mod ring_buffer;
+pub mod scc;
 pub mod slot;
+pub mod tape_signal;
+pub mod typing;
 pub mod utils;
 pub mod vdp;
+pub mod vdp_debug;
+pub mod vgm;
+pub mod wav;
+pub mod y8950;
 
 use std::sync::Once;
 
@@ -23,10 +43,12 @@ pub use internal_state::{InternalState, ReportState};
 use js_sys::Float32Array;
 pub use machine::MachineBuilder;
 pub use machine::{Machine, ProgramEntry};
-pub use renderer::Renderer;
+pub use palette::Palette;
+pub use renderer::{OutputMode, Renderer};
 use tracing_wasm::WASMLayerConfigBuilder;
 pub use utils::{compare_slices, hexdump, partial_hexdump};
 pub use vdp::TMS9918;
+pub use vdp_debug::VdpImage;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsValue;
 
@@ -36,6 +58,8 @@ pub fn get_machine(rom_data: &[u8]) -> Machine {
         .empty_slot()
         .empty_slot()
         .ram_slot(0x0000, 0x10000)
+        .keyboard_layout(keyboard_layout::KeyboardLayout::detect(rom_data))
+        .psg_model(psg::PsgModel::detect(rom_data))
         .build()
 }
 
@@ -79,6 +103,8 @@ pub fn get_machine_with_rom(bios_rom_data: &[u8], slot1_rom_data: &[u8]) -> Mach
         .rom_slot(slot1_rom_data, base_addr as u16, size) // Slot 1: Disk ROM
         .empty_slot() // Slot 2: Empty
         .ram_slot(0x0000, 0x10000) // Slot 3: RAM
+        .keyboard_layout(keyboard_layout::KeyboardLayout::detect(bios_rom_data))
+        .psg_model(psg::PsgModel::detect(bios_rom_data))
         .build()
 }
 
@@ -172,6 +198,71 @@ impl JsMachine {
         renderer.screen_buffer.to_vec()
     }
 
+    /// Current frame as RGBA8888 pixels in the selected palette and output mode
+    /// (see `screenWidth`/`screenHeight` for its dimensions)
+    #[wasm_bindgen(js_name = screenRgba)]
+    pub fn screen_rgba(&self) -> Vec<u8> {
+        let mut bus = self.0.bus.borrow_mut();
+        bus.vdp.pulse();
+        let mut renderer = Renderer::new(&bus.vdp);
+        renderer.draw();
+        renderer.compose_rgba(self.0.output_mode, &self.0.palette)
+    }
+
+    #[wasm_bindgen(getter = screenWidth)]
+    pub fn screen_width(&self) -> usize {
+        self.0.output_mode.dimensions().0
+    }
+
+    #[wasm_bindgen(getter = screenHeight)]
+    pub fn screen_height(&self) -> usize {
+        self.0.output_mode.dimensions().1
+    }
+
+    /// Selects "cropped" (256x192 active area) or "overscan" (full raster with borders)
+    #[wasm_bindgen(js_name = setOutputMode)]
+    pub fn set_output_mode(&mut self, mode: &str) -> Result<(), JsValue> {
+        let mode = OutputMode::from_name(mode)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown output mode: {}", mode)))?;
+        self.0.set_output_mode(mode);
+        Ok(())
+    }
+
+    /// Current palette as 16 RGBA entries (64 bytes)
+    #[wasm_bindgen(getter)]
+    pub fn palette(&self) -> Vec<u8> {
+        self.0.palette.to_bytes()
+    }
+
+    #[wasm_bindgen(getter = paletteName)]
+    pub fn palette_name(&self) -> String {
+        self.0.palette.name().to_string()
+    }
+
+    #[wasm_bindgen(js_name = paletteNames)]
+    pub fn palette_names() -> Vec<JsValue> {
+        Palette::NAMES
+            .iter()
+            .map(|name| JsValue::from_str(name))
+            .collect()
+    }
+
+    #[wasm_bindgen(js_name = setPalette)]
+    pub fn set_palette(&mut self, name: &str) -> Result<(), JsValue> {
+        let palette = Palette::from_name(name)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown palette: {}", name)))?;
+        self.0.set_palette(palette);
+        Ok(())
+    }
+
+    /// Installs a user palette from 16 RGB (48 bytes) or RGBA (64 bytes) entries
+    #[wasm_bindgen(js_name = setCustomPalette)]
+    pub fn set_custom_palette(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        let palette = Palette::from_bytes(data).map_err(|e| JsValue::from_str(&e))?;
+        self.0.set_palette(palette);
+        Ok(())
+    }
+
     #[wasm_bindgen(getter)]
     pub fn vram(&self) -> Vec<u8> {
         self.0.bus.borrow().vdp.vram.to_vec()
@@ -182,6 +273,38 @@ impl JsMachine {
         format!("{:?}", self.0.bus.borrow().vdp.display_mode)
     }
 
+    /// Table addresses, registers and sprite attributes as JSON
+    #[wasm_bindgen(js_name = vdpTables)]
+    pub fn vdp_tables(&self) -> Result<String, JsValue> {
+        let tables = vdp_debug::VdpTables::from_vdp(&self.0.bus.borrow().vdp);
+        serde_json::to_string(&tables).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    #[wasm_bindgen(js_name = vdpPatternSheet)]
+    pub fn vdp_pattern_sheet(&self) -> VdpImage {
+        vdp_debug::pattern_sheet(&self.0.bus.borrow().vdp)
+    }
+
+    #[wasm_bindgen(js_name = vdpNameTable)]
+    pub fn vdp_name_table(&self) -> VdpImage {
+        vdp_debug::name_table_map(&self.0.bus.borrow().vdp)
+    }
+
+    #[wasm_bindgen(js_name = vdpSpriteSheet)]
+    pub fn vdp_sprite_sheet(&self) -> VdpImage {
+        vdp_debug::sprite_sheet(&self.0.bus.borrow().vdp)
+    }
+
+    #[wasm_bindgen(js_name = setAccurateVramTiming)]
+    pub fn set_accurate_vram_timing(&mut self, enabled: bool) {
+        self.0.set_accurate_vram_timing(enabled);
+    }
+
+    #[wasm_bindgen(getter = vramTimingViolations)]
+    pub fn vram_timing_violations(&self) -> f64 {
+        self.0.vram_timing_violations() as f64
+    }
+
     #[wasm_bindgen(js_name=keyDown)]
     pub fn key_down(&mut self, key: String) {
         self.0.bus.borrow_mut().key_down(key);
@@ -192,29 +315,397 @@ impl JsMachine {
         self.0.bus.borrow_mut().key_up(key);
     }
 
+    /// Overrides the keyboard layout picked from the BIOS: "international", "japanese",
+    /// "uk", "german", "spanish", "brazilian", "russian" or "korean"
+    #[wasm_bindgen(js_name = setKeyboardLayout)]
+    pub fn set_keyboard_layout(&mut self, name: &str) -> Result<(), JsValue> {
+        let layout = keyboard_layout::KeyboardLayout::from_name(name)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown keyboard layout: {}", name)))?;
+        self.0.set_keyboard_layout(layout);
+        Ok(())
+    }
+
+    #[wasm_bindgen(getter = keyboardLayout)]
+    pub fn keyboard_layout(&self) -> String {
+        self.0.keyboard_layout().name().to_string()
+    }
+
+    /// Types pasted text into the machine, a character at a time over the next frames
+    pub fn paste(&mut self, text: &str) -> Result<(), JsValue> {
+        self.0.type_text(text).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Whether pasted text is still being typed
+    #[wasm_bindgen(getter)]
+    pub fn typing(&self) -> bool {
+        self.0.typing()
+    }
+
+    #[wasm_bindgen(js_name = cancelPaste)]
+    pub fn cancel_paste(&mut self) {
+        self.0.cancel_typing();
+    }
+
+    /// Sets the buttons held on joystick port 0 or 1, e.g. from the Gamepad API: bits
+    /// 0-5 are up, down, left, right, trigger A and trigger B, set while held
+    #[wasm_bindgen(js_name = setJoystick)]
+    pub fn set_joystick(&mut self, port: usize, buttons: u8) -> Result<(), JsValue> {
+        self.0
+            .set_joystick(port, joystick::JoystickState::from_bits(buttons))
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Where keys with a joystick binding go: "keyboard", "joystick" or "both" (the
+    /// default)
+    #[wasm_bindgen(js_name = setKeyRouting)]
+    pub fn set_key_routing(&mut self, name: &str) -> Result<(), JsValue> {
+        let routing = joystick::KeyRouting::from_name(name)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown key routing: {}", name)))?;
+        self.0.set_key_routing(routing);
+        Ok(())
+    }
+
+    #[wasm_bindgen(getter = keyRouting)]
+    pub fn key_routing(&self) -> String {
+        self.0.key_routing().name().to_string()
+    }
+
+    /// Plugs a "joystick" or a "mouse" into port 0 or 1
+    #[wasm_bindgen(js_name = setPortDevice)]
+    pub fn set_port_device(&mut self, port: usize, device: &str) -> Result<(), JsValue> {
+        let device = joystick::PortDevice::from_name(device)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown port device: {}", device)))?;
+        self.0
+            .set_port_device(port, device)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Relative mouse motion, e.g. `movementX`/`movementY` of a pointer locked mouse
+    /// event, with the state of the left and right buttons
+    #[wasm_bindgen(js_name = mouseInput)]
+    pub fn mouse_input(&mut self, dx: i32, dy: i32, left: bool, right: bool) {
+        self.0.mouse_input(dx, dy, left, right);
+    }
+
+    /// Binds a key code to a joystick button: "up", "down", "left", "right", "a" or "b"
+    #[wasm_bindgen(js_name = bindJoystickKey)]
+    pub fn bind_joystick_key(
+        &mut self,
+        key: &str,
+        port: usize,
+        button: &str,
+    ) -> Result<(), JsValue> {
+        self.0
+            .bind_joystick_key(key, port, joystick_button(button)?)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    #[wasm_bindgen(js_name = unbindJoystickKey)]
+    pub fn unbind_joystick_key(&mut self, key: &str) {
+        self.0.unbind_joystick_key(key);
+    }
+
+    #[wasm_bindgen(js_name = clearJoystickBindings)]
+    pub fn clear_joystick_bindings(&mut self) {
+        self.0.clear_joystick_bindings();
+    }
+
+    #[wasm_bindgen(js_name = setAutofire)]
+    pub fn set_autofire(
+        &mut self,
+        port: usize,
+        button: &str,
+        enabled: bool,
+    ) -> Result<(), JsValue> {
+        self.0
+            .set_autofire(port, joystick_button(button)?, enabled)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Produces `sample_count` frames at the host rate: mono samples, or interleaved
+    /// left/right pairs when stereo output is enabled
     #[wasm_bindgen(js_name=generateAudioSamples)]
     pub fn generate_audio_samples(&mut self, sample_count: usize) -> Float32Array {
-        let mut samples = Vec::with_capacity(sample_count);
         let mut bus = self.0.bus.borrow_mut();
-
-        // If we don't have enough samples, run the emulation to generate more
-        while !bus.psg.has_samples(sample_count) {
-            // Release the borrow before stepping the machine
-            drop(bus);
-            // Step the machine for a small number of cycles to generate more samples
-            self.0.step_for(1000);
-            bus = self.0.bus.borrow_mut();
+        let stereo = bus.mixer.is_stereo();
+        let channels = if stereo { 2 } else { 1 };
+        let mut samples = Vec::with_capacity(sample_count * channels);
+
+        // Without A/V sync, run the emulation until there are enough samples
+        if !bus.mixer.audio_sync() {
+            let available = sample_count.min(bus.mixer.buffer_capacity());
+            while !bus.mixer.has_samples(available) {
+                // Release the borrow before stepping the machine
+                drop(bus);
+                // Step the machine for a small number of cycles to generate more samples
+                self.0.step_for(1000);
+                bus = self.0.bus.borrow_mut();
+            }
         }
 
-        // Collect samples from the PSG buffer
-        for _ in 0..sample_count {
-            samples.push(bus.psg.get_audio_sample());
+        // Collect samples from the mixer's buffer
+        for [left, right] in bus.mixer.take_frames(sample_count) {
+            if stereo {
+                samples.push(left);
+                samples.push(right);
+            } else {
+                samples.push(left);
+            }
         }
 
         // Convert to JavaScript Float32Array
         Float32Array::from(&samples[..])
     }
-    
+
+    /// 1 for mono output, 2 for interleaved stereo
+    #[wasm_bindgen(getter = audioChannels)]
+    pub fn audio_channels(&self) -> u8 {
+        if self.0.bus.borrow().mixer.is_stereo() {
+            2
+        } else {
+            1
+        }
+    }
+
+    #[wasm_bindgen(js_name = setStereo)]
+    pub fn set_stereo(&mut self, enabled: bool) {
+        self.0.bus.borrow_mut().set_stereo(enabled);
+    }
+
+    /// Pan of PSG tone channel 0-2 (A-C), from -1 (left) to 1 (right)
+    #[wasm_bindgen(js_name = setChannelPan)]
+    pub fn set_channel_pan(&mut self, channel: usize, pan: f32) -> Result<(), JsValue> {
+        self.0
+            .bus
+            .borrow_mut()
+            .psg
+            .set_pan(channel, pan)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Gain of a sound source: "psg", "keyclick", "cassette", "scc", "opll" or
+    /// "msx-audio"; 1 is its natural level
+    #[wasm_bindgen(js_name = setSourceGain)]
+    pub fn set_source_gain(&mut self, source: &str, gain: f32) -> Result<(), JsValue> {
+        self.0.set_source_gain(audio_source(source)?, gain);
+        Ok(())
+    }
+
+    /// Stereo placement of a sound source, from -1 (left) to 1 (right)
+    #[wasm_bindgen(js_name = setSourcePan)]
+    pub fn set_source_pan(&mut self, source: &str, pan: f32) -> Result<(), JsValue> {
+        self.0.set_source_pan(audio_source(source)?, pan);
+        Ok(())
+    }
+
+    /// Names of the sound sources the mixer currently pulls from
+    #[wasm_bindgen(getter = audioSources)]
+    pub fn audio_sources(&self) -> Vec<String> {
+        let bus = self.0.bus.borrow();
+        bus.mixer
+            .sources()
+            .iter()
+            .map(|source| source.name().to_string())
+            .collect()
+    }
+
+    #[wasm_bindgen(js_name = setKeyClick)]
+    pub fn set_key_click(&mut self, enabled: bool) {
+        self.0.set_key_click(enabled);
+    }
+
+    #[wasm_bindgen(getter = keyClick)]
+    pub fn key_click(&self) -> bool {
+        self.0.bus.borrow().key_click.enabled()
+    }
+
+    /// Whether the Kana LED is lit, as driven by PSG register 15 bit 7
+    #[wasm_bindgen(getter = kanaLed)]
+    pub fn kana_led(&self) -> bool {
+        self.0.bus.borrow().psg.kana_led()
+    }
+
+    /// Select the PSG chip: "ay-3-8910" or "ym2149", overriding the one picked from the BIOS
+    #[wasm_bindgen(js_name = setPsgModel)]
+    pub fn set_psg_model(&mut self, name: &str) -> Result<(), JsValue> {
+        let model = psg::PsgModel::from_name(name)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown PSG model: {}", name)))?;
+        self.0.set_psg_model(model);
+        Ok(())
+    }
+
+    #[wasm_bindgen(getter = psgModel)]
+    pub fn psg_model(&self) -> String {
+        self.0.bus.borrow().psg.model().name().to_string()
+    }
+
+    /// Inserts an MSX-MUSIC ROM, or a 64KB FM-PAC ROM, together with its YM2413
+    #[wasm_bindgen(js_name = loadMsxMusicRom)]
+    pub fn load_msx_music_rom(&mut self, slot: u8, data: &[u8]) -> Result<(), JsValue> {
+        if slot > 3 {
+            return Err(JsValue::from_str(&format!("Invalid slot: {}", slot)));
+        }
+        self.0.load_msx_music_rom(slot, data);
+        Ok(())
+    }
+
+    /// Adds the MSX-AUDIO chip with `ramKb` KB (32-256) of sample RAM, or removes it with 0
+    #[wasm_bindgen(js_name = setMsxAudio)]
+    pub fn set_msx_audio(&mut self, ram_kb: u32) -> Result<(), JsValue> {
+        self.0
+            .set_msx_audio(ram_kb)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Starts recording sound chip register writes; `stopVgmLog` returns the VGM file
+    #[wasm_bindgen(js_name = startVgmLog)]
+    pub fn start_vgm_log(&mut self) {
+        self.0.start_vgm_log();
+    }
+
+    #[wasm_bindgen(js_name = stopVgmLog)]
+    pub fn stop_vgm_log(&mut self) -> Result<Vec<u8>, JsValue> {
+        self.0.stop_vgm_log().map_err(|e| JsValue::from_str(&e))
+    }
+
+    #[wasm_bindgen(getter = vgmLogging)]
+    pub fn vgm_logging(&self) -> bool {
+        self.0.vgm_logging()
+    }
+
+    /// Starts recording the audio output as WAV at the "host" or "native" rate, for
+    /// `frames` video frames or until `stopWavCapture` when 0
+    #[wasm_bindgen(js_name = startWavCapture)]
+    pub fn start_wav_capture(&mut self, rate: &str, frames: u32) -> Result<(), JsValue> {
+        let rate = wav::CaptureRate::from_name(rate)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown capture rate: {}", rate)))?;
+        let frames = (frames > 0).then_some(frames);
+        self.0.start_wav_capture(rate, frames);
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = stopWavCapture)]
+    pub fn stop_wav_capture(&mut self) -> Result<Vec<u8>, JsValue> {
+        self.0.stop_wav_capture().map_err(|e| JsValue::from_str(&e))
+    }
+
+    #[wasm_bindgen(getter = wavCapturing)]
+    pub fn wav_capturing(&self) -> bool {
+        self.0.wav_capturing()
+    }
+
+    #[wasm_bindgen(js_name = loadMsxAudioBios)]
+    pub fn load_msx_audio_bios(&mut self, slot: u8, data: &[u8]) -> Result<(), JsValue> {
+        if slot > 3 {
+            return Err(JsValue::from_str(&format!("Invalid slot: {}", slot)));
+        }
+        self.0.load_msx_audio_bios(slot, data);
+        Ok(())
+    }
+
+    /// Feeds the MSX-AUDIO ADPCM recorder, at the chip's native rate of about 49.7kHz
+    #[wasm_bindgen(js_name = pushMsxAudioInput)]
+    pub fn push_msx_audio_input(&mut self, samples: &[f32]) -> Result<(), JsValue> {
+        match self.0.bus.borrow_mut().msx_audio.as_mut() {
+            Some(msx_audio) => {
+                msx_audio.push_adpcm_input(samples);
+                Ok(())
+            }
+            None => Err(JsValue::from_str("MSX-AUDIO is not enabled")),
+        }
+    }
+
+    /// Inserts a Konami SCC cartridge; `chip` is "scc" or "scc-i" for SCC+ support
+    #[wasm_bindgen(js_name = loadSccRom)]
+    pub fn load_scc_rom(&mut self, slot: u8, data: &[u8], chip: &str) -> Result<(), JsValue> {
+        if slot > 3 {
+            return Err(JsValue::from_str(&format!("Invalid slot: {}", slot)));
+        }
+        let chip = scc::SccChip::from_name(chip)
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown SCC chip: {}", chip)))?;
+        self.0.load_scc_rom(slot, data, chip);
+        Ok(())
+    }
+
+    /// Mute a PSG voice: "a", "b", "c", "noise" or "envelope"
+    #[wasm_bindgen(js_name = setPsgMute)]
+    pub fn set_psg_mute(&mut self, voice: &str, muted: bool) -> Result<(), JsValue> {
+        let voice = psg_voice(voice)?;
+        self.0.bus.borrow_mut().psg.set_muted(voice, muted);
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = setPsgSolo)]
+    pub fn set_psg_solo(&mut self, voice: &str, soloed: bool) -> Result<(), JsValue> {
+        let voice = psg_voice(voice)?;
+        self.0.bus.borrow_mut().psg.set_soloed(voice, soloed);
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = setPsgScope)]
+    pub fn set_psg_scope(&mut self, enabled: bool) {
+        self.0.bus.borrow_mut().psg.set_scope_enabled(enabled);
+    }
+
+    /// Waveform of a PSG voice over the last frame, at the PSG's native rate
+    #[wasm_bindgen(js_name = psgScope)]
+    pub fn psg_scope(&self, voice: &str) -> Result<Float32Array, JsValue> {
+        let voice = psg_voice(voice)?;
+        Ok(Float32Array::from(self.0.bus.borrow().psg.scope(voice)))
+    }
+
+    /// PSG registers as they were at the end of the frame captured by `psgScope`
+    #[wasm_bindgen(getter = psgScopeRegisters)]
+    pub fn psg_scope_registers(&self) -> Vec<u8> {
+        self.0.bus.borrow().psg.scope_registers().to_vec()
+    }
+
+    #[wasm_bindgen(getter = psgRegisters)]
+    pub fn psg_registers(&self) -> Vec<u8> {
+        self.0.bus.borrow().psg.registers().to_vec()
+    }
+
+    /// Host sample rate that `generateAudioSamples` produces, e.g. 44100 or 48000
+    #[wasm_bindgen(js_name = setAudioSampleRate)]
+    pub fn set_audio_sample_rate(&mut self, rate: u32) -> Result<(), JsValue> {
+        self.0
+            .set_audio_sample_rate(rate)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    #[wasm_bindgen(getter = audioSampleRate)]
+    pub fn audio_sample_rate(&self) -> u32 {
+        self.0.bus.borrow().mixer.output_sample_rate()
+    }
+
+    /// With A/V sync on (the default) `stepFrame` or `step_for` run the emulation and
+    /// `generateAudioSamples` only drains the buffer; with it off, audio pulls run the
+    /// machine
+    #[wasm_bindgen(js_name = setAudioSync)]
+    pub fn set_audio_sync(&mut self, enabled: bool) {
+        self.0.set_audio_sync(enabled);
+    }
+
+    #[wasm_bindgen(getter = audioSync)]
+    pub fn audio_sync(&self) -> bool {
+        self.0.bus.borrow().mixer.audio_sync()
+    }
+
+    /// Target audio latency in milliseconds, 20-250
+    #[wasm_bindgen(js_name = setAudioLatency)]
+    pub fn set_audio_latency(&mut self, latency_ms: f32) -> Result<(), JsValue> {
+        self.0
+            .set_audio_latency(latency_ms)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Buffer fill level, latency, clock drift and underrun/overrun counters as JSON
+    #[wasm_bindgen(js_name = audioStats)]
+    pub fn audio_stats(&self) -> Result<String, JsValue> {
+        let stats = self.0.bus.borrow().mixer.audio_stats();
+        serde_json::to_string(&stats).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
     #[wasm_bindgen(js_name=hasDiskSystem)]
     pub fn has_disk_system(&self) -> bool {
         self.0.has_disk_system()
@@ -238,6 +729,80 @@ impl JsMachine {
             .map_err(|e| JsValue::from_str(&e))
     }
     
+    /// Puts a CAS image in the tape deck; an empty array is a blank tape
+    #[wasm_bindgen(js_name = insertTape)]
+    pub fn insert_tape(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        self.0
+            .insert_tape(data.to_vec())
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    #[wasm_bindgen(js_name = ejectTape)]
+    pub fn eject_tape(&mut self) {
+        self.0.eject_tape();
+    }
+
+    /// Puts a WAV recording, or a CAS image to encode as audio, in the tape deck to
+    /// play into the cassette input, for loaders that bypass the BIOS tape routines
+    #[wasm_bindgen(js_name = insertTapeSignal)]
+    pub fn insert_tape_signal(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        self.0
+            .insert_tape_signal(data)
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    #[wasm_bindgen(js_name = rewindTape)]
+    pub fn rewind_tape(&mut self) {
+        self.0.rewind_tape();
+    }
+
+    /// Seconds played of a tape signal, or undefined without one
+    #[wasm_bindgen(getter = tapeSignalPosition)]
+    pub fn tape_signal_position(&self) -> Option<f64> {
+        self.0.tape_signal_secs().map(|(position, _)| position)
+    }
+
+    /// Length of a tape signal in seconds, or undefined without one
+    #[wasm_bindgen(getter = tapeSignalLength)]
+    pub fn tape_signal_length(&self) -> Option<f64> {
+        self.0.tape_signal_secs().map(|(_, length)| length)
+    }
+
+    /// Whether emulation runs faster while a tape signal plays, on by default
+    #[wasm_bindgen(getter = tapeFastForward)]
+    pub fn tape_fast_forward(&self) -> bool {
+        self.0.tape_fast_forward()
+    }
+
+    #[wasm_bindgen(setter = tapeFastForward)]
+    pub fn set_tape_fast_forward(&mut self, enabled: bool) {
+        self.0.set_tape_fast_forward(enabled);
+    }
+
+    /// Tape position as a byte offset into the CAS image
+    #[wasm_bindgen(getter = tapePosition)]
+    pub fn tape_position(&self) -> usize {
+        self.0.tape_position()
+    }
+
+    #[wasm_bindgen(setter = tapePosition)]
+    pub fn set_tape_position(&mut self, position: usize) {
+        self.0.set_tape_position(position);
+    }
+
+    /// The blocks on the tape as JSON: offset, kind ("Basic", "Ascii", "Binary" or
+    /// "Data") and file name
+    #[wasm_bindgen(js_name = tapeBlocks)]
+    pub fn tape_blocks(&self) -> Result<String, JsValue> {
+        serde_json::to_string(&self.0.tape_blocks()).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// The tape as a CAS image with anything recorded on it, to save
+    #[wasm_bindgen(js_name = tapeData)]
+    pub fn tape_data(&self) -> Option<Vec<u8>> {
+        self.0.tape_data()
+    }
+
     #[wasm_bindgen(js_name=enableDiskSystem)]
     pub fn enable_disk_system(&mut self) -> Result<(), JsValue> {
         // Disk system is automatically enabled when a disk ROM is detected
@@ -249,3 +814,18 @@ impl JsMachine {
         }
     }
 }
+
+fn joystick_button(name: &str) -> Result<joystick::JoystickButton, JsValue> {
+    joystick::JoystickButton::from_name(name)
+        .ok_or_else(|| JsValue::from_str(&format!("Unknown joystick button: {}", name)))
+}
+
+fn audio_source(name: &str) -> Result<mixer::AudioSource, JsValue> {
+    mixer::AudioSource::from_name(name)
+        .ok_or_else(|| JsValue::from_str(&format!("Unknown audio source: {}", name)))
+}
+
+fn psg_voice(name: &str) -> Result<psg::PsgVoice, JsValue> {
+    psg::PsgVoice::from_name(name)
+        .ok_or_else(|| JsValue::from_str(&format!("Unknown PSG voice: {}", name)))
+}
@@ -3,12 +3,25 @@ use std::{cell::RefCell, collections::VecDeque, fmt, rc::Rc};
 use z80::{Z80_io, Z80};
 
 use crate::{
+    audio_sync::{MAX_LATENCY_MS, MIN_LATENCY_MS},
     bus::{Bus, MemorySegment},
+    cassette::TapeBlock,
+    cassette_driver::{BiosPatch, CassetteDriver},
     clock::{Clock, ClockEvent, CPU_CYCLES_PER_SCANLINE, SCANLINES_PER_FRAME},
     cpu_extensions::{CpuExtensionHandler, CpuExtensionState},
+    joystick::{JoystickButton, JoystickState, KeyRouting, PortDevice},
+    keyboard_layout::KeyboardLayout,
+    mixer::AudioSource,
+    palette::Palette,
     partial_hexdump,
-    slot::{RamSlot, RomSlot, SlotType},
+    psg::PsgModel,
+    renderer::OutputMode,
+    scc::SccChip,
+    slot::{MsxMusicSlot, RamSlot, RomSlot, SccSlot, SlotType},
+    tape_signal::TapeSignal,
     vdp::TMS9918,
+    wav::CaptureRate,
+    y8950,
 };
 
 pub struct Machine {
@@ -19,8 +32,20 @@ pub struct Machine {
     pub cycles: usize,
     pub frame_ready: bool,
     pub disk_drive: Option<crate::disk_drive::SharedDiskDrive>,
+    pub palette: Palette,
+    pub output_mode: OutputMode,
+    // Devices currently asserting the interrupt line
+    irq_sources: u8,
+    // The BIOS tape routines replaced while a CAS image is in the deck
+    tape_patch: Option<BiosPatch>,
+    // Whether to run faster while a tape signal plays
+    tape_fast_forward: bool,
 }
 
+/// Frames run per frame, and cycles per cycle in `step_for`, while a tape signal plays
+/// with fast-forward on, so that a 1200 baud load takes seconds instead of minutes
+pub const FAST_FORWARD_FRAMES: u32 = 8;
+
 impl Machine {
     pub fn new(slots: &[SlotType]) -> Self {
         tracing::trace!("Initializing MSX with slots: {:?}", slots);
@@ -37,6 +62,11 @@ impl Machine {
             cycles: 0,
             frame_ready: false,
             disk_drive: None,
+            palette: Palette::default(),
+            output_mode: OutputMode::default(),
+            irq_sources: 0,
+            tape_patch: None,
+            tape_fast_forward: true,
         };
 
         // Check if slot 1 has a disk ROM and set up disk system if so
@@ -53,6 +83,16 @@ impl Machine {
         self.bus.borrow_mut().load_rom(slot, data);
     }
 
+    /// Inserts an MSX-MUSIC or FM-PAC cartridge with its YM2413
+    pub fn load_msx_music_rom(&mut self, slot: u8, data: &[u8]) {
+        self.bus.borrow_mut().load_msx_music_rom(slot, data);
+    }
+
+    /// Inserts a Konami SCC mapper cartridge
+    pub fn load_scc_rom(&mut self, slot: u8, data: &[u8], chip: SccChip) {
+        self.bus.borrow_mut().load_scc_rom(slot, data, chip);
+    }
+
     pub fn load_ram(&mut self, slot: u8) {
         self.bus.borrow_mut().load_ram(slot);
     }
@@ -111,29 +151,267 @@ impl Machine {
         self.bus.borrow().vdp.clone()
     }
 
+    /// Select the palette used for RGBA screen output
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Select between the cropped active area and the full raster with borders
+    pub fn set_output_mode(&mut self, mode: OutputMode) {
+        self.output_mode = mode;
+    }
+
+    /// Select the PSG chip the machine is fitted with
+    pub fn set_psg_model(&mut self, model: PsgModel) {
+        self.bus.borrow_mut().psg.set_model(model);
+    }
+
+    pub fn keyboard_layout(&self) -> KeyboardLayout {
+        self.bus.borrow().ppi.keyboard.layout()
+    }
+
+    /// Changes which matrix key each host key presses; held keys are released
+    pub fn set_keyboard_layout(&mut self, layout: KeyboardLayout) {
+        self.bus.borrow_mut().ppi.keyboard.set_layout(layout);
+    }
+
+    /// Types `text` on the keyboard as a person would, with the modifiers the active
+    /// layout needs, a character every few frames; line breaks press Enter
+    pub fn type_text(&mut self, text: &str) -> Result<(), String> {
+        self.bus.borrow_mut().ppi.keyboard.type_text(text)
+    }
+
+    /// Whether `type_text` still has characters to type
+    pub fn typing(&self) -> bool {
+        self.bus.borrow().ppi.keyboard.typing()
+    }
+
+    pub fn cancel_typing(&mut self) {
+        self.bus.borrow_mut().ppi.keyboard.cancel_typing();
+    }
+
+    /// Sets the buttons held on joystick port 0 or 1, e.g. from a gamepad
+    pub fn set_joystick(&mut self, port: usize, state: JoystickState) -> Result<(), String> {
+        check_joystick_port(port)?;
+        self.bus.borrow_mut().set_joystick(port, state);
+        Ok(())
+    }
+
+    pub fn key_routing(&self) -> KeyRouting {
+        self.bus.borrow().joysticks.routing()
+    }
+
+    /// Chooses whether host keys with a joystick binding press the joystick, the
+    /// keyboard or both
+    pub fn set_key_routing(&mut self, routing: KeyRouting) {
+        self.bus.borrow_mut().set_key_routing(routing);
+    }
+
+    /// Plugs a joystick or a mouse into port 0 or 1
+    pub fn set_port_device(&mut self, port: usize, device: PortDevice) -> Result<(), String> {
+        check_joystick_port(port)?;
+        let mut bus = self.bus.borrow_mut();
+        bus.joysticks.set_device(port, device);
+        bus.update_joysticks();
+        Ok(())
+    }
+
+    /// Relative host mouse motion in pixels, right and down being positive, and the
+    /// button state, for a mouse plugged into either port
+    pub fn mouse_input(&mut self, dx: i32, dy: i32, left: bool, right: bool) {
+        self.bus.borrow_mut().mouse_input(dx, dy, left, right);
+    }
+
+    /// Makes a host key (a `KeyboardEvent.code`) press a joystick button
+    pub fn bind_joystick_key(
+        &mut self,
+        key: &str,
+        port: usize,
+        button: JoystickButton,
+    ) -> Result<(), String> {
+        check_joystick_port(port)?;
+        self.bus.borrow_mut().joysticks.bind(key, port, button);
+        Ok(())
+    }
+
+    pub fn unbind_joystick_key(&mut self, key: &str) {
+        let mut bus = self.bus.borrow_mut();
+        bus.joysticks.key_up(key);
+        bus.joysticks.unbind(key);
+        bus.update_joysticks();
+    }
+
+    pub fn clear_joystick_bindings(&mut self) {
+        let mut bus = self.bus.borrow_mut();
+        bus.joysticks.clear_bindings();
+        bus.update_joysticks();
+    }
+
+    /// Makes a joystick button fire repeatedly while held
+    pub fn set_autofire(
+        &mut self,
+        port: usize,
+        button: JoystickButton,
+        enabled: bool,
+    ) -> Result<(), String> {
+        check_joystick_port(port)?;
+        let mut bus = self.bus.borrow_mut();
+        bus.joysticks.set_autofire(port, button, enabled);
+        bus.update_joysticks();
+        Ok(())
+    }
+
+    /// Enable or disable the key click sound; the PPI still drives the line
+    pub fn set_key_click(&mut self, enabled: bool) {
+        self.bus.borrow_mut().key_click.set_enabled(enabled);
+    }
+
+    /// Gain of a sound source in the mix, 1.0 being its natural level
+    pub fn set_source_gain(&mut self, source: AudioSource, gain: f32) {
+        self.bus.borrow_mut().mixer.set_gain(source, gain);
+    }
+
+    /// Stereo placement of a sound source, from -1.0 (left) to 1.0 (right)
+    pub fn set_source_pan(&mut self, source: AudioSource, pan: f32) {
+        self.bus.borrow_mut().mixer.set_pan(source, pan);
+    }
+
+    /// Set the host sample rate the mixed output is resampled to
+    pub fn set_audio_sample_rate(&mut self, rate: u32) -> Result<(), String> {
+        if !(8000..=192000).contains(&rate) {
+            return Err(format!("Unsupported audio sample rate: {} Hz", rate));
+        }
+        self.bus.borrow_mut().mixer.set_output_sample_rate(rate);
+        Ok(())
+    }
+
+    /// Lets the video clock run the emulation while the audio output follows it through
+    /// dynamic rate control; when off, audio pulls run the machine instead
+    pub fn set_audio_sync(&mut self, enabled: bool) {
+        self.bus.borrow_mut().mixer.set_audio_sync(enabled);
+    }
+
+    /// Audio latency the A/V sync keeps the host buffer at
+    pub fn set_audio_latency(&mut self, latency_ms: f32) -> Result<(), String> {
+        if !(MIN_LATENCY_MS..=MAX_LATENCY_MS).contains(&latency_ms) {
+            return Err(format!(
+                "Audio latency must be between {} and {} ms",
+                MIN_LATENCY_MS, MAX_LATENCY_MS
+            ));
+        }
+        let mut bus = self.bus.borrow_mut();
+        bus.mixer.set_target_latency_ms(latency_ms);
+        Ok(())
+    }
+
+    /// Adds the MSX-AUDIO (Y8950) chip on ports 0xC0-0xC1 with `ram_kb` KB of ADPCM
+    /// sample RAM, or removes it when `ram_kb` is 0
+    pub fn set_msx_audio(&mut self, ram_kb: u32) -> Result<(), String> {
+        let ram_size = match ram_kb {
+            0 => None,
+            _ => {
+                let ram_size = ram_kb as usize * 1024;
+                if !(y8950::MIN_RAM_SIZE..=y8950::MAX_RAM_SIZE).contains(&ram_size)
+                    || !ram_size.is_power_of_two()
+                {
+                    return Err(format!(
+                        "Unsupported MSX-AUDIO sample RAM size: {} KB",
+                        ram_kb
+                    ));
+                }
+                Some(ram_size)
+            }
+        };
+        self.bus.borrow_mut().set_msx_audio(ram_size);
+        // The old chip's interrupt goes with it, including one still queued
+        self.process_messages();
+        self.set_irq_source(IRQ_AUDIO, false);
+        Ok(())
+    }
+
+    /// Inserts the MSX-AUDIO BIOS ROM, from 0x4000 when it fits in 32KB
+    pub fn load_msx_audio_bios(&mut self, slot: u8, data: &[u8]) {
+        let (base, size) = if data.len() <= 0x8000 {
+            (0x4000, 0x8000)
+        } else {
+            (0x0000, 0x10000)
+        };
+        self.bus
+            .borrow_mut()
+            .set_slot(slot, SlotType::Rom(RomSlot::new(data, base, size)));
+    }
+
+    /// Starts recording PSG, SCC, MSX-MUSIC and MSX-AUDIO register writes for a VGM file
+    pub fn start_vgm_log(&mut self) {
+        let cycle = self.clock.total_cycles();
+        self.bus.borrow_mut().start_vgm_log(cycle);
+    }
+
+    /// Stops the VGM recording and returns the file
+    pub fn stop_vgm_log(&mut self) -> Result<Vec<u8>, String> {
+        let cycle = self.clock.total_cycles();
+        self.bus
+            .borrow_mut()
+            .stop_vgm_log(cycle)
+            .ok_or_else(|| "No VGM recording is running".to_string())
+    }
+
+    pub fn vgm_logging(&self) -> bool {
+        self.bus.borrow().vgm_logging()
+    }
+
+    /// Starts recording the audio output to a 16-bit WAV file, at the host or the native
+    /// rate, for `frames` video frames or until stopped
+    pub fn start_wav_capture(&mut self, rate: CaptureRate, frames: Option<u32>) {
+        self.bus.borrow_mut().mixer.start_capture(rate, frames);
+    }
+
+    /// Stops the audio capture and returns the WAV file
+    pub fn stop_wav_capture(&mut self) -> Result<Vec<u8>, String> {
+        self.bus
+            .borrow_mut()
+            .mixer
+            .stop_capture()
+            .ok_or_else(|| "No audio capture is running".to_string())
+    }
+
+    /// Whether a started audio capture is still recording
+    pub fn wav_capturing(&self) -> bool {
+        self.bus
+            .borrow()
+            .mixer
+            .capture()
+            .is_some_and(|capture| capture.recording())
+    }
+
+    /// Enable or disable the minimum access interval check on port 0x98 writes and reads
+    pub fn set_accurate_vram_timing(&mut self, enabled: bool) {
+        let mut bus = self.bus.borrow_mut();
+        bus.vdp.accurate_timing = enabled;
+        bus.vdp.last_vram_access = None;
+    }
+
+    /// Number of VRAM accesses dropped because they came too fast
+    pub fn vram_timing_violations(&self) -> u64 {
+        self.bus.borrow().vdp.vram_timing_violations
+    }
+
+    /// Runs `n` cycles, or `FAST_FORWARD_FRAMES` times as many while a tape signal
+    /// plays with fast-forward on
     pub fn step_for(&mut self, n: usize) {
+        // Counted in fractions of a cycle, so that fast-forwarded cycles take less
+        let budget = n * FAST_FORWARD_FRAMES as usize;
         let mut cycles_executed = 0;
 
-        while cycles_executed < n {
+        while cycles_executed < budget {
+            let fast_forward = self.tape_fast_forward && self.tape_signal_playing();
+
             // Process any pending messages first
-            while let Some(message) = self.queue.borrow_mut().pop_front() {
-                match message {
-                    Message::EnableInterrupts => {
-                        // tracing::debug!("[Machine] Asserting IRQ");
-                        self.cpu.assert_irq(0);
-                    }
-                    Message::DisableInterrupts => {
-                        // tracing::debug!("[Machine] Clearing IRQ");
-                        self.cpu.clr_irq();
-                    }
-                    Message::CpuStep => {
-                        // This shouldn't happen in the queue
-                    }
-                    Message::DebugPC => {
-                        tracing::info!("Cycles: {} PC: {:04X}", self.cycles, self.cpu.pc);
-                    }
-                }
-            }
+            self.process_messages();
+
+            self.bus
+                .borrow_mut()
+                .set_cpu_cycle(self.clock.total_cycles());
 
             // Execute CPU instruction
             let cycles_taken = self.cpu.step();
@@ -178,7 +456,44 @@ impl Machine {
             }
 
             self.cycles += cycles_taken as usize;
-            cycles_executed += cycles_taken as usize;
+            cycles_executed += if fast_forward {
+                cycles_taken as usize
+            } else {
+                cycles_taken as usize * FAST_FORWARD_FRAMES as usize
+            };
+        }
+    }
+
+    fn process_messages(&mut self) {
+        loop {
+            let Some(message) = self.queue.borrow_mut().pop_front() else {
+                break;
+            };
+            match message {
+                Message::EnableInterrupts => self.set_irq_source(IRQ_VDP, true),
+                Message::DisableInterrupts => self.set_irq_source(IRQ_VDP, false),
+                Message::AudioInterrupt(active) => self.set_irq_source(IRQ_AUDIO, active),
+                Message::CpuStep => {
+                    // This shouldn't happen in the queue
+                }
+                Message::DebugPC => {
+                    tracing::info!("Cycles: {} PC: {:04X}", self.cycles, self.cpu.pc);
+                }
+            }
+        }
+    }
+
+    /// The interrupt line is wired-OR: it stays asserted while any device holds it
+    fn set_irq_source(&mut self, source: u8, active: bool) {
+        if active {
+            self.irq_sources |= source;
+        } else {
+            self.irq_sources &= !source;
+        }
+        if self.irq_sources != 0 {
+            self.cpu.assert_irq(0);
+        } else {
+            self.cpu.clr_irq();
         }
     }
 
@@ -193,6 +508,7 @@ impl Machine {
                     bus.vdp.set_vblank(true);
                     if bus.vdp.is_interrupt_enabled() {
                         // tracing::debug!("[Machine] VBlank interrupt enabled, asserting IRQ");
+                        self.irq_sources |= IRQ_VDP;
                         self.cpu.assert_irq(0);
                     } else {
                         // tracing::debug!("[Machine] VBlank interrupt disabled");
@@ -215,6 +531,7 @@ impl Machine {
                 }
                 ClockEvent::FrameEnd => {
                     self.frame_ready = true;
+                    self.bus.borrow_mut().end_frame();
                     tracing::trace!(
                         "Frame {} completed, total cycles: {}",
                         self.clock.frame_count(),
@@ -225,7 +542,18 @@ impl Machine {
         }
     }
 
+    /// Runs a frame, or `FAST_FORWARD_FRAMES` while a tape signal plays with
+    /// fast-forward on
     pub fn step_frame(&mut self) {
+        self.run_frame();
+        let mut frames = 1;
+        while frames < FAST_FORWARD_FRAMES && self.tape_fast_forward && self.tape_signal_playing() {
+            self.run_frame();
+            frames += 1;
+        }
+    }
+
+    fn run_frame(&mut self) {
         self.frame_ready = false;
         let cycles_per_frame = (SCANLINES_PER_FRAME * CPU_CYCLES_PER_SCANLINE) as usize;
         let target_cycles = self.cycles + cycles_per_frame;
@@ -233,24 +561,11 @@ impl Machine {
         // Run CPU for one complete frame worth of cycles
         while self.cycles < target_cycles {
             // Process any pending messages
-            while let Some(message) = self.queue.borrow_mut().pop_front() {
-                match message {
-                    Message::EnableInterrupts => {
-                        // tracing::debug!("[Machine] Asserting IRQ");
-                        self.cpu.assert_irq(0);
-                    }
-                    Message::DisableInterrupts => {
-                        // tracing::debug!("[Machine] Clearing IRQ");
-                        self.cpu.clr_irq();
-                    }
-                    Message::CpuStep => {
-                        // This shouldn't happen in the queue
-                    }
-                    Message::DebugPC => {
-                        tracing::info!("Cycles: {} PC: {:04X}", self.cycles, self.cpu.pc);
-                    }
-                }
-            }
+            self.process_messages();
+
+            self.bus
+                .borrow_mut()
+                .set_cpu_cycle(self.clock.total_cycles());
 
             // Execute CPU instruction and get actual cycle count
             let cycles_taken = self.cpu.step();
@@ -421,6 +736,94 @@ impl Machine {
             )
         }
     }
+
+    /// Puts a CAS image in the tape deck; an empty one is a blank tape to record on.
+    ///
+    /// The tape routines of the BIOS in slot 0 are patched to read and write it.
+    pub fn insert_tape(&mut self, data: Vec<u8>) -> Result<(), String> {
+        if self.tape_patch.is_none() {
+            let patch = match self.bus.borrow_mut().get_slot_mut(0) {
+                SlotType::Rom(rom_slot) => CassetteDriver::patch_bios(rom_slot),
+                _ => None,
+            };
+            let Some(patch) = patch else {
+                return Err("No BIOS in slot 0 to patch for tape support".to_string());
+            };
+            CassetteDriver::setup(&self.cpu.io, self.bus.clone());
+            self.tape_patch = Some(patch);
+        }
+        self.bus.borrow_mut().cassette.insert(data)
+    }
+
+    /// Puts a tape in the deck to play into the cassette input, for loaders that
+    /// bypass the BIOS tape routines: a WAV recording, or a CAS image that is
+    /// encoded the way the BIOS would have written it.
+    ///
+    /// Any patch of the BIOS tape routines is undone, so that the BIOS reads the
+    /// signal as well.
+    pub fn insert_tape_signal(&mut self, data: &[u8]) -> Result<(), String> {
+        let signal = if data.starts_with(b"RIFF") {
+            TapeSignal::from_wav(data)?
+        } else {
+            TapeSignal::from_cas(data)?
+        };
+        if let Some(patch) = self.tape_patch.take() {
+            if let SlotType::Rom(rom_slot) = self.bus.borrow_mut().get_slot_mut(0) {
+                CassetteDriver::restore_bios(rom_slot, &patch);
+            }
+        }
+        let cycle = self.clock.total_cycles();
+        self.bus.borrow_mut().cassette.insert_signal(signal, cycle);
+        Ok(())
+    }
+
+    pub fn eject_tape(&mut self) {
+        self.bus.borrow_mut().cassette.eject();
+    }
+
+    /// Winds the tape back to the start, CAS image or signal
+    pub fn rewind_tape(&mut self) {
+        self.bus.borrow_mut().cassette.rewind();
+    }
+
+    /// Where a playing tape signal is and how long it lasts, in seconds
+    pub fn tape_signal_secs(&self) -> Option<(f64, f64)> {
+        let bus = self.bus.borrow();
+        let signal = bus.cassette.signal()?;
+        let position = signal.position_secs(self.clock.total_cycles());
+        Some((position, signal.length_secs()))
+    }
+
+    fn tape_signal_playing(&self) -> bool {
+        let cycle = self.clock.total_cycles();
+        self.bus.borrow().cassette.signal_playing(cycle)
+    }
+
+    pub fn tape_fast_forward(&self) -> bool {
+        self.tape_fast_forward
+    }
+
+    pub fn set_tape_fast_forward(&mut self, enabled: bool) {
+        self.tape_fast_forward = enabled;
+    }
+
+    /// Winds the tape to a byte offset in the image, such as a block's offset
+    pub fn set_tape_position(&mut self, position: usize) {
+        self.bus.borrow_mut().cassette.set_position(position);
+    }
+
+    pub fn tape_position(&self) -> usize {
+        self.bus.borrow().cassette.position()
+    }
+
+    pub fn tape_blocks(&self) -> Vec<TapeBlock> {
+        self.bus.borrow().cassette.blocks()
+    }
+
+    /// The tape in the deck as a CAS image, including anything recorded on it
+    pub fn tape_data(&self) -> Option<Vec<u8>> {
+        self.bus.borrow().cassette.data().map(<[u8]>::to_vec)
+    }
 }
 
 impl Default for Machine {
@@ -447,6 +850,11 @@ impl Default for Machine {
             cycles: 0,
             frame_ready: false,
             disk_drive: None,
+            palette: Palette::default(),
+            output_mode: OutputMode::default(),
+            irq_sources: 0,
+            tape_patch: None,
+            tape_fast_forward: true,
         }
     }
 }
@@ -454,6 +862,9 @@ impl Default for Machine {
 #[derive(Default)]
 pub struct MachineBuilder {
     slots: Vec<SlotType>,
+    psg_model: PsgModel,
+    msx_audio_ram_kb: u32,
+    keyboard_layout: KeyboardLayout,
 }
 
 impl MachineBuilder {
@@ -472,11 +883,37 @@ impl MachineBuilder {
         self
     }
 
+    pub fn msx_music_slot(&mut self, data: &[u8]) -> &mut Self {
+        self.slots.push(SlotType::MsxMusic(MsxMusicSlot::new(data)));
+        self
+    }
+
+    pub fn scc_slot(&mut self, data: &[u8], chip: SccChip) -> &mut Self {
+        self.slots.push(SlotType::Scc(SccSlot::new(data, chip)));
+        self
+    }
+
     pub fn empty_slot(&mut self) -> &mut Self {
         self.slots.push(SlotType::Empty);
         self
     }
 
+    pub fn psg_model(&mut self, model: PsgModel) -> &mut Self {
+        self.psg_model = model;
+        self
+    }
+
+    /// Adds MSX-AUDIO with `ram_kb` KB of sample RAM
+    pub fn msx_audio(&mut self, ram_kb: u32) -> &mut Self {
+        self.msx_audio_ram_kb = ram_kb;
+        self
+    }
+
+    pub fn keyboard_layout(&mut self, layout: KeyboardLayout) -> &mut Self {
+        self.keyboard_layout = layout;
+        self
+    }
+
     pub fn build(&self) -> Machine {
         if self.slots.len() != 4 {
             panic!(
@@ -485,7 +922,13 @@ impl MachineBuilder {
             );
         }
 
-        Machine::new(&self.slots)
+        let mut machine = Machine::new(&self.slots);
+        machine.set_psg_model(self.psg_model);
+        machine.set_keyboard_layout(self.keyboard_layout);
+        if let Err(error) = machine.set_msx_audio(self.msx_audio_ram_kb) {
+            panic!("MachineBuilder: {}", error);
+        }
+        machine
     }
 }
 
@@ -510,10 +953,22 @@ impl fmt::Display for ProgramEntry {
     }
 }
 
-#[derive(Debug)]
+fn check_joystick_port(port: usize) -> Result<(), String> {
+    if port > 1 {
+        return Err(format!("Invalid joystick port: {} (must be 0 or 1)", port));
+    }
+    Ok(())
+}
+
+const IRQ_VDP: u8 = 0x01;
+const IRQ_AUDIO: u8 = 0x02;
+
+#[derive(Debug, PartialEq)]
 pub enum Message {
     EnableInterrupts,
     DisableInterrupts,
+    /// The MSX-AUDIO chip raised or released its interrupt
+    AudioInterrupt(bool),
     CpuStep,
     DebugPC,
 }
@@ -0,0 +1,594 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audio_sync::AudioSync,
+    clock::CPU_CLOCK_HZ,
+    psg::{PSG_SAMPLE_DIVIDER, SAMPLE_RATE},
+    resampler::Resampler,
+    ring_buffer::SampleRing,
+    wav::{CaptureRate, WavCapture},
+};
+
+/// Sound sources the mixer can pull from
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum AudioSource {
+    Psg,
+    KeyClick,
+    Cassette,
+    Scc,
+    Opll,
+    MsxAudio,
+}
+
+impl AudioSource {
+    pub const ALL: [AudioSource; 6] = [
+        AudioSource::Psg,
+        AudioSource::KeyClick,
+        AudioSource::Cassette,
+        AudioSource::Scc,
+        AudioSource::Opll,
+        AudioSource::MsxAudio,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "psg" => Some(AudioSource::Psg),
+            "keyclick" | "key-click" => Some(AudioSource::KeyClick),
+            "cassette" => Some(AudioSource::Cassette),
+            "scc" => Some(AudioSource::Scc),
+            "opll" | "msx-music" => Some(AudioSource::Opll),
+            "msx-audio" | "y8950" => Some(AudioSource::MsxAudio),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioSource::Psg => "psg",
+            AudioSource::KeyClick => "keyclick",
+            AudioSource::Cassette => "cassette",
+            AudioSource::Scc => "scc",
+            AudioSource::Opll => "opll",
+            AudioSource::MsxAudio => "msx-audio",
+        }
+    }
+}
+
+/// Host side audio buffer state, for frontends to report glitches
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct AudioStats {
+    pub sample_rate: u32,
+    pub buffered: usize,
+    pub underruns: u64,
+    pub overruns: u64,
+    /// Buffered audio ahead of the host, in milliseconds
+    pub latency_ms: f32,
+    pub target_latency_ms: f32,
+    /// Estimated drift of the emulated clock against the audio device
+    pub drift_ppm: f32,
+}
+
+/// Gain and stereo placement of a source, kept while the source is not registered
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+struct SourceSettings {
+    gain: f32,
+    pan: f32,
+}
+
+impl Default for SourceSettings {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            pan: 0.0,
+        }
+    }
+}
+
+/// A registered source and its rate conversion state
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+struct MixerChannel {
+    source: AudioSource,
+    // The source's native rate, as CPU cycles per sample
+    cycles_per_sample: u32,
+    // CPU cycles since the last native sample was pulled
+    phase: u32,
+    previous: [f32; 2],
+    current: [f32; 2],
+}
+
+/// Mixes the sound sources of the machine into one host rate stream.
+///
+/// Every mix step covers 32 CPU cycles (~112kHz). Each registered source is pulled at its
+/// own native rate, given as CPU cycles per sample, and slower sources are interpolated
+/// up to the mix rate. The sum then goes through a DC blocker and a peak limiter before
+/// it is resampled to the host rate, so the stream stays in step with emulated time.
+///
+/// Sources return levels in PSG channel units, where one tone channel peaks at 0.28.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Mixer {
+    channels: Vec<MixerChannel>,
+    settings: [SourceSettings; 6],
+    stereo: bool,
+    clock_divider: u32,
+    dc_input: [f32; 2],
+    dc_output: [f32; 2],
+    limiter_gain: f32,
+    // Converts the mix rate output to the host rate
+    resampler: Resampler,
+    // Host rate samples waiting to be played
+    sample_buffer: SampleRing,
+    sync: AudioSync,
+    // Output being recorded to a WAV file
+    capture: Option<WavCapture>,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self {
+            channels: Vec::new(),
+            settings: [SourceSettings::default(); 6],
+            stereo: false,
+            clock_divider: 0,
+            dc_input: [0.0; 2],
+            dc_output: [0.0; 2],
+            limiter_gain: 1.0,
+            resampler: Resampler::new(MIX_RATE, DEFAULT_OUTPUT_RATE),
+            sample_buffer: SampleRing::default(),
+            sync: AudioSync::new(),
+            capture: None,
+        }
+    }
+
+    /// Clears the signal path; registrations, settings and a running capture are kept
+    pub fn reset(&mut self) {
+        for channel in self.channels.iter_mut() {
+            channel.phase = 0;
+            channel.previous = [0.0; 2];
+            channel.current = [0.0; 2];
+        }
+        self.clock_divider = 0;
+        self.dc_input = [0.0; 2];
+        self.dc_output = [0.0; 2];
+        self.limiter_gain = 1.0;
+        self.resampler.reset();
+        self.sample_buffer.clear();
+        self.sync.reset();
+    }
+
+    /// Adds a source pulled every `cycles_per_sample` CPU cycles, if it is not registered
+    pub fn register(&mut self, source: AudioSource, cycles_per_sample: u32) {
+        if self.is_registered(source) {
+            return;
+        }
+        self.channels.push(MixerChannel {
+            source,
+            cycles_per_sample: cycles_per_sample.max(1),
+            phase: 0,
+            previous: [0.0; 2],
+            current: [0.0; 2],
+        });
+    }
+
+    pub fn unregister(&mut self, source: AudioSource) {
+        self.channels.retain(|channel| channel.source != source);
+    }
+
+    pub fn is_registered(&self, source: AudioSource) -> bool {
+        self.channels.iter().any(|channel| channel.source == source)
+    }
+
+    pub fn sources(&self) -> Vec<AudioSource> {
+        self.channels.iter().map(|channel| channel.source).collect()
+    }
+
+    /// Native sample rate of a registered source, in Hz
+    pub fn source_rate(&self, source: AudioSource) -> Option<f32> {
+        self.channels
+            .iter()
+            .find(|channel| channel.source == source)
+            .map(|channel| CPU_CLOCK_HZ as f32 / channel.cycles_per_sample as f32)
+    }
+
+    pub fn gain(&self, source: AudioSource) -> f32 {
+        self.settings[source as usize].gain
+    }
+
+    /// Linear gain of a source, 1.0 being its natural level and 0.0 silence
+    pub fn set_gain(&mut self, source: AudioSource, gain: f32) {
+        self.settings[source as usize].gain = gain.max(0.0);
+    }
+
+    pub fn pan(&self, source: AudioSource) -> f32 {
+        self.settings[source as usize].pan
+    }
+
+    /// Stereo placement of a source, from -1.0 (left) through 0.0 (centre) to 1.0 (right)
+    pub fn set_pan(&mut self, source: AudioSource, pan: f32) {
+        self.settings[source as usize].pan = pan.clamp(-1.0, 1.0);
+    }
+
+    pub fn is_stereo(&self) -> bool {
+        self.stereo
+    }
+
+    /// In mono both sides carry the average of the sources and the pans are ignored
+    pub fn set_stereo(&mut self, stereo: bool) {
+        self.stereo = stereo;
+    }
+
+    /// CPU cycles clocked into the mix step that is not yet complete
+    pub fn clock_phase(&self) -> u32 {
+        self.clock_divider
+    }
+
+    /// Runs the mixer for `cycles` CPU cycles, calling `pull` whenever a source is due
+    /// for its next native sample
+    pub fn clock(&mut self, cycles: u32, mut pull: impl FnMut(AudioSource) -> [f32; 2]) {
+        self.clock_divider += cycles;
+
+        while self.clock_divider >= MIX_DIVIDER {
+            self.clock_divider -= MIX_DIVIDER;
+
+            let mut mix = [0.0; 2];
+            for channel in self.channels.iter_mut() {
+                channel.phase += MIX_DIVIDER;
+                while channel.phase >= channel.cycles_per_sample {
+                    channel.phase -= channel.cycles_per_sample;
+                    channel.previous = channel.current;
+                    channel.current = pull(channel.source);
+                }
+
+                let [left, right] = if channel.cycles_per_sample <= MIX_DIVIDER {
+                    channel.current
+                } else {
+                    // Linear interpolation between the last two native samples
+                    let fraction = channel.phase as f32 / channel.cycles_per_sample as f32;
+                    let [previous, current] = [channel.previous, channel.current];
+                    [
+                        previous[0] + (current[0] - previous[0]) * fraction,
+                        previous[1] + (current[1] - previous[1]) * fraction,
+                    ]
+                };
+
+                let settings = self.settings[channel.source as usize];
+                if self.stereo {
+                    mix[0] += left * settings.gain * (1.0 - settings.pan).min(1.0);
+                    mix[1] += right * settings.gain * (1.0 + settings.pan).min(1.0);
+                } else {
+                    let mono = (left + right) / 2.0 * settings.gain;
+                    mix[0] += mono;
+                    mix[1] += mono;
+                }
+            }
+
+            let frame = self.master(mix);
+
+            if let Some(capture) = self.capture.as_mut() {
+                if capture.rate() == CaptureRate::Native {
+                    capture.push(frame);
+                }
+            }
+
+            self.resampler.push(frame);
+            while let Some(frame) = self.resampler.next_frame() {
+                // The resampler's ringing can overshoot the limiter a little
+                let frame = frame.map(|sample| sample.clamp(-1.0, 1.0));
+                self.sample_buffer.push(frame);
+            }
+        }
+    }
+
+    /// Output gain, DC blocking and limiting of the summed sources
+    fn master(&mut self, mix: [f32; 2]) -> [f32; 2] {
+        let mut frame = [0.0; 2];
+        for (side, level) in mix.into_iter().enumerate() {
+            let input = level * OUTPUT_GAIN;
+            frame[side] = input - self.dc_input[side] + DC_BLOCK_POLE * self.dc_output[side];
+            self.dc_input[side] = input;
+            self.dc_output[side] = frame[side];
+        }
+
+        // Peaks above the threshold pull the gain down at once, then it recovers slowly
+        let peak = frame[0].abs().max(frame[1].abs()) * self.limiter_gain;
+        if peak > LIMITER_THRESHOLD {
+            self.limiter_gain *= LIMITER_THRESHOLD / peak;
+        } else {
+            self.limiter_gain += (1.0 - self.limiter_gain) * LIMITER_RELEASE;
+        }
+        frame.map(|sample| (sample * self.limiter_gain).clamp(-1.0, 1.0))
+    }
+
+    // Get next audio frame (left, right) from the buffer, at the host rate
+    pub fn get_audio_frame(&mut self) -> [f32; 2] {
+        self.sample_buffer.pop()
+    }
+
+    /// Hands `count` host rate frames to the audio callback, and to a host rate capture.
+    ///
+    /// With A/V sync on, this also steers the resampler ratio towards the target latency,
+    /// returns silence while the buffer fills up, and drops a backlog left by a host
+    /// that stopped pulling.
+    pub fn take_frames(&mut self, count: usize) -> Vec<[f32; 2]> {
+        let frames = self.next_frames(count);
+        if let Some(capture) = self.capture.as_mut() {
+            if capture.rate() == CaptureRate::Host {
+                for frame in &frames {
+                    capture.push(*frame);
+                }
+            }
+        }
+        frames
+    }
+
+    fn next_frames(&mut self, count: usize) -> Vec<[f32; 2]> {
+        if self.sync.enabled() {
+            let rate = self.resampler.output_rate();
+            let target = self.sync.target_frames(rate);
+            let buffered = self.sample_buffer.len();
+            if self.sync.playing() && buffered > target * 2 + count {
+                self.sample_buffer.skip(buffered - target);
+            }
+
+            let was_playing = self.sync.playing();
+            let ratio = self.sync.update(self.sample_buffer.len(), count, rate);
+            self.resampler.set_ratio(ratio);
+            if !was_playing && !self.sync.playing() {
+                // Still filling up
+                return vec![[0.0; 2]; count];
+            }
+        }
+        (0..count).map(|_| self.sample_buffer.pop()).collect()
+    }
+
+    // Check if we have enough frames in the buffer
+    pub fn has_samples(&self, count: usize) -> bool {
+        self.sample_buffer.len() >= count
+    }
+
+    /// Number of host rate frames the buffer can hold
+    pub fn buffer_capacity(&self) -> usize {
+        self.sample_buffer.capacity()
+    }
+
+    /// Host sample rate the output is resampled to
+    pub fn output_sample_rate(&self) -> u32 {
+        self.resampler.output_rate()
+    }
+
+    pub fn set_output_sample_rate(&mut self, rate: u32) {
+        self.resampler.set_output_rate(rate);
+        self.sample_buffer.clear();
+        self.sync.reset();
+    }
+
+    pub fn audio_sync(&self) -> bool {
+        self.sync.enabled()
+    }
+
+    /// Whether the video clock runs the emulation and audio follows it, or audio pulls
+    /// run the machine until enough samples exist
+    pub fn set_audio_sync(&mut self, enabled: bool) {
+        self.sync.set_enabled(enabled);
+        self.resampler.set_ratio(1.0);
+    }
+
+    pub fn target_latency_ms(&self) -> f32 {
+        self.sync.target_latency_ms()
+    }
+
+    pub fn set_target_latency_ms(&mut self, latency_ms: f32) {
+        self.sync.set_target_latency_ms(latency_ms);
+    }
+
+    pub fn audio_stats(&self) -> AudioStats {
+        let sample_rate = self.resampler.output_rate();
+        AudioStats {
+            sample_rate,
+            buffered: self.sample_buffer.len(),
+            underruns: self.sample_buffer.underruns,
+            overruns: self.sample_buffer.overruns,
+            latency_ms: self.sample_buffer.len() as f32 * 1000.0 / sample_rate as f32,
+            target_latency_ms: self.sync.target_latency_ms(),
+            drift_ppm: self.sync.drift_ppm(),
+        }
+    }
+
+    pub fn reset_audio_stats(&mut self) {
+        self.sample_buffer.reset_stats();
+    }
+
+    /// Starts recording the output at the host or the mix rate, in the current stereo
+    /// mode, for `frames` video frames or until stopped
+    pub fn start_capture(&mut self, rate: CaptureRate, frames: Option<u32>) {
+        let sample_rate = match rate {
+            CaptureRate::Host => self.resampler.output_rate(),
+            CaptureRate::Native => MIX_RATE,
+        };
+        self.capture = Some(WavCapture::new(rate, sample_rate, self.stereo, frames));
+    }
+
+    /// Ends the capture and returns the WAV file, if a capture was started
+    pub fn stop_capture(&mut self) -> Option<Vec<u8>> {
+        self.capture.take().map(|capture| capture.to_wav())
+    }
+
+    pub fn capture(&self) -> Option<&WavCapture> {
+        self.capture.as_ref()
+    }
+
+    /// Called at the end of every video frame
+    pub fn end_frame(&mut self) {
+        if let Some(capture) = self.capture.as_mut() {
+            capture.end_frame();
+        }
+    }
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CPU cycles per mix step, the PSG's native sample period
+pub const MIX_DIVIDER: u32 = PSG_SAMPLE_DIVIDER;
+pub const MIX_RATE: u32 = SAMPLE_RATE;
+pub const DEFAULT_OUTPUT_RATE: u32 = 44100;
+
+/// Scales PSG channel units to the output range; three full volume tone channels
+/// reach about 1.1 peak to peak
+pub const OUTPUT_GAIN: f32 = 0.66 * 2.0;
+
+// One-pole high-pass around 9Hz at the mix rate
+const DC_BLOCK_POLE: f32 = 0.9995;
+const LIMITER_THRESHOLD: f32 = 0.9;
+// Gain recovery per mix step, about 50ms to get back to unity
+const LIMITER_RELEASE: f32 = 0.0002;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(step: u32) -> [f32; 2] {
+        let level = if step % 64 < 32 { 0.28 } else { 0.0 };
+        [level, level]
+    }
+
+    #[test]
+    fn test_sources_at_their_own_rates() {
+        let mut mixer = Mixer::new();
+        mixer.register(AudioSource::Psg, MIX_DIVIDER);
+        mixer.register(AudioSource::Opll, 72);
+        mixer.register(AudioSource::Opll, 72);
+        assert_eq!(mixer.sources(), vec![AudioSource::Psg, AudioSource::Opll]);
+
+        let mut pulls = [0; 2];
+        mixer.clock(72 * 32 * 10, |source| {
+            pulls[(source == AudioSource::Opll) as usize] += 1;
+            [0.0; 2]
+        });
+        assert_eq!(pulls, [72 * 10, 32 * 10]);
+
+        mixer.unregister(AudioSource::Opll);
+        assert!(!mixer.is_registered(AudioSource::Opll));
+        assert!((mixer.source_rate(AudioSource::Psg).unwrap() - MIX_RATE as f32).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_dc_blocking_and_limiter() {
+        let mut mixer = Mixer::new();
+        mixer.register(AudioSource::Scc, MIX_DIVIDER);
+
+        // A constant level settles to silence
+        mixer.clock(MIX_DIVIDER * 100_000, |_| [0.3, 0.3]);
+        let frame = mixer.master([0.3, 0.3]);
+        assert!(frame[0].abs() < 1e-3, "DC left: {}", frame[0]);
+
+        // A loud square wave never exceeds the limiter threshold
+        let mut mixer = Mixer::new();
+        mixer.register(AudioSource::Scc, MIX_DIVIDER);
+        mixer.set_gain(AudioSource::Scc, 8.0);
+        let mut step = 0;
+        mixer.clock(MIX_DIVIDER * 10_000, |_| {
+            step += 1;
+            square(step)
+        });
+        let mut peak: f32 = 0.0;
+        while mixer.has_samples(1) {
+            peak = peak.max(mixer.get_audio_frame()[0].abs());
+        }
+        assert!(peak > 0.5 && peak <= 1.0, "peak {}", peak);
+    }
+
+    #[test]
+    fn test_gain_and_pan() {
+        let mut mixer = Mixer::new();
+        mixer.set_stereo(true);
+        mixer.register(AudioSource::KeyClick, MIX_DIVIDER);
+        mixer.set_pan(AudioSource::KeyClick, -1.0);
+        mixer.set_gain(AudioSource::KeyClick, 0.5);
+        mixer.clock(MIX_DIVIDER, |_| [0.2, 0.2]);
+        let frame = mixer.dc_input;
+        assert!((frame[0] - 0.1 * OUTPUT_GAIN).abs() < 1e-6);
+        assert_eq!(frame[1], 0.0, "hard left");
+
+        mixer.set_stereo(false);
+        mixer.clock(MIX_DIVIDER, |_| [0.2, 0.0]);
+        assert_eq!(mixer.dc_input[0], mixer.dc_input[1], "mono ignores the pan");
+    }
+
+    #[test]
+    fn test_host_capture_matches_output() {
+        let mut mixer = Mixer::new();
+        mixer.register(AudioSource::Psg, MIX_DIVIDER);
+        mixer.set_target_latency_ms(50.0);
+        mixer.start_capture(CaptureRate::Host, Some(1));
+        let mut step = 0;
+        let mut tone = |_| {
+            step += 1;
+            square(step)
+        };
+
+        // Silence while the buffer fills, then the tone, then what is left after a
+        // backlog is dropped, as the audio callback gets them
+        let mut played = Vec::new();
+        mixer.clock(MIX_DIVIDER * 3000, &mut tone);
+        played.extend(mixer.take_frames(1024));
+        mixer.clock(MIX_DIVIDER * 3000, &mut tone);
+        played.extend(mixer.take_frames(1024));
+        mixer.clock(MIX_DIVIDER * 20_000, &mut tone);
+        let backlog = mixer.audio_stats().buffered;
+        played.extend(mixer.take_frames(1024));
+        assert!(
+            mixer.audio_stats().buffered < backlog - 1024,
+            "backlog skipped"
+        );
+        mixer.end_frame();
+        mixer.take_frames(1024);
+
+        let wav = mixer.stop_capture().unwrap();
+        let captured: Vec<i16> = wav[44..]
+            .chunks(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        let played: Vec<i16> = played
+            .iter()
+            .map(|frame| crate::wav::to_pcm(frame[0]))
+            .collect();
+        assert_eq!(captured[0], 0, "warm-up silence");
+        assert!(captured.iter().any(|&sample| sample != 0));
+        assert_eq!(captured, played, "stopped after one video frame");
+    }
+
+    #[test]
+    fn test_audio_sync_fills_before_playing() {
+        let mut mixer = Mixer::new();
+        mixer.register(AudioSource::Psg, MIX_DIVIDER);
+        mixer.set_target_latency_ms(50.0);
+        let mut step = 0;
+        let mut tone = |_| {
+            step += 1;
+            square(step)
+        };
+
+        // 50ms is 2205 host frames, about 5600 mix steps
+        mixer.clock(MIX_DIVIDER * 3000, &mut tone);
+        let buffered = mixer.audio_stats().buffered;
+        let silent = mixer.take_frames(1024);
+        assert!(silent.iter().all(|frame| *frame == [0.0; 2]));
+        assert_eq!(mixer.audio_stats().buffered, buffered, "nothing consumed");
+
+        mixer.clock(MIX_DIVIDER * 3000, &mut tone);
+        let played = mixer.take_frames(1024);
+        assert!(played.iter().any(|frame| *frame != [0.0; 2]));
+        let stats = mixer.audio_stats();
+        assert_eq!(stats.underruns, 0);
+        assert!((stats.latency_ms - 1000.0 * stats.buffered as f32 / 44100.0).abs() < 1e-3);
+
+        // Without sync the buffer is simply drained
+        mixer.set_audio_sync(false);
+        let left = mixer.audio_stats().buffered;
+        mixer.take_frames(left + 1);
+        assert_eq!(mixer.audio_stats().underruns, 1);
+    }
+}
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+
+use crate::clock::CPU_CLOCK_HZ;
+
+/// An MSX mouse on a joystick port.
+///
+/// Software reads the motion since the last read as two signed bytes, X then Y, a
+/// nibble at a time on pins 1-4: each edge of pin 8 (driven through PSG register 15)
+/// moves on to the next nibble, and the rising edge that starts a new read latches the
+/// motion collected so far. The buttons are the two triggers. Positive values mean left
+/// and up, the opposite of the host's screen coordinates.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Mouse {
+    // Motion not yet latched, in MSX units
+    pending: [i32; 2],
+    // Motion being read out
+    latched: [i8; 2],
+    phase: MousePhase,
+    // CPU cycle of the last write to register 15
+    last_write: u64,
+    left: bool,
+    right: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+enum MousePhase {
+    XHigh,
+    XLow,
+    YHigh,
+    #[default]
+    YLow,
+}
+
+impl Mouse {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds host motion, right and down being positive
+    pub fn move_by(&mut self, dx: i32, dy: i32) {
+        self.pending[0] -= dx;
+        self.pending[1] -= dy;
+    }
+
+    pub fn set_buttons(&mut self, left: bool, right: bool) {
+        self.left = left;
+        self.right = right;
+    }
+
+    /// Called on every write to PSG register 15 with the port's output pins (bit 2 is
+    /// pin 8) and the CPU cycle of the write
+    pub fn write(&mut self, pins: u8, cycle: u64) {
+        if cycle.saturating_sub(self.last_write) > TIMEOUT_CYCLES {
+            // The mouse gave up on the last read and waits for a new one
+            self.phase = MousePhase::YLow;
+        }
+        self.last_write = cycle;
+
+        let strobe = pins & 0x04 != 0;
+        self.phase = match (self.phase, strobe) {
+            (MousePhase::XHigh, false) => MousePhase::XLow,
+            (MousePhase::XLow, true) => MousePhase::YHigh,
+            (MousePhase::YHigh, false) => MousePhase::YLow,
+            (MousePhase::YLow, true) => {
+                self.latched = self.pending.map(|delta| delta.clamp(-127, 127) as i8);
+                self.pending = [0; 2];
+                MousePhase::XHigh
+            }
+            (phase, _) => phase,
+        };
+    }
+
+    /// Pins 1-4, 6 and 7 as PSG register 14 bits 0-5: the current nibble and the
+    /// buttons, active low
+    pub fn lines(&self) -> u8 {
+        let [x, y] = self.latched.map(|delta| delta as u8);
+        let nibble = match self.phase {
+            MousePhase::XHigh => x >> 4,
+            MousePhase::XLow => x & 0x0F,
+            MousePhase::YHigh => y >> 4,
+            MousePhase::YLow => y & 0x0F,
+        };
+        let buttons = (!self.left as u8) << 4 | (!self.right as u8) << 5;
+        0xC0 | buttons | nibble
+    }
+}
+
+/// Writes further apart than this start a new read; 3ms, as some drivers are slow
+const TIMEOUT_CYCLES: u64 = CPU_CLOCK_HZ as u64 * 3 / 1000;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads X and Y the way the BIOS does, toggling pin 8 before each nibble
+    fn read(mouse: &mut Mouse, cycle: &mut u64) -> (i8, i8) {
+        let mut nibbles = Vec::new();
+        for pins in [0x04, 0x00, 0x04, 0x00] {
+            *cycle += 100;
+            mouse.write(pins, *cycle);
+            nibbles.push(mouse.lines() & 0x0F);
+        }
+        (
+            (nibbles[0] << 4 | nibbles[1]) as i8,
+            (nibbles[2] << 4 | nibbles[3]) as i8,
+        )
+    }
+
+    #[test]
+    fn test_nibble_protocol() {
+        let mut mouse = Mouse::new();
+        let mut cycle = 0;
+        mouse.move_by(5, -3);
+        assert_eq!(read(&mut mouse, &mut cycle), (-5, 3));
+        assert_eq!(read(&mut mouse, &mut cycle), (0, 0), "motion is consumed");
+
+        mouse.move_by(-400, 20);
+        assert_eq!(read(&mut mouse, &mut cycle), (127, -20), "clamped");
+
+        mouse.set_buttons(true, false);
+        assert_eq!(mouse.lines() & 0x30, 0x20, "left button on pin 6");
+    }
+
+    #[test]
+    fn test_timeout_restarts_a_read() {
+        let mut mouse = Mouse::new();
+        let mut cycle = 0;
+        mouse.move_by(-1, 0);
+        // An interrupted read: the high X nibble only
+        mouse.write(0x04, 100);
+        mouse.write(0x00, 200);
+
+        mouse.move_by(-1, 0);
+        cycle += 200 + TIMEOUT_CYCLES + 1;
+        assert_eq!(read(&mut mouse, &mut cycle), (1, 0));
+    }
+}
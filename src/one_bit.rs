@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+
+use crate::psg::PSG_SAMPLE_DIVIDER;
+
+/// A 1-bit audio line driven by a PPI port C bit: the key click or the cassette output.
+///
+/// Edges are placed on the CPU cycle of the instruction that wrote port C, and every
+/// PSG sample averages the line over its 32 cycles, so short pulses keep their energy
+/// instead of snapping to sample boundaries. The average then goes through the analogue
+/// path of the machine: a low-pass for the output filter and a DC blocker for the
+/// coupling capacitor, so a line left high does not offset the rest of the mix.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct OneBitOutput {
+    volume: f32,
+    enabled: bool,
+    level: bool,
+    // First CPU cycle of the PSG sample being accumulated
+    window_start: u64,
+    // CPU cycle of the last edge
+    last_edge: u64,
+    // Cycles the line spent high in the current sample
+    high_cycles: u32,
+    low_pass: f32,
+    dc_input: f32,
+    dc_output: f32,
+}
+
+impl OneBitOutput {
+    pub fn new(volume: f32) -> Self {
+        Self {
+            volume,
+            enabled: true,
+            level: false,
+            window_start: 0,
+            last_edge: 0,
+            high_cycles: 0,
+            low_pass: 0.0,
+            dc_input: 0.0,
+            dc_output: 0.0,
+        }
+    }
+
+    /// Drops the line low and settles the filters; the volume and toggle are kept
+    pub fn reset(&mut self) {
+        *self = Self {
+            enabled: self.enabled,
+            window_start: self.window_start,
+            ..Self::new(self.volume)
+        };
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Silences the line without touching its state, so enabling it again does not pop
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn level(&self) -> bool {
+        self.level
+    }
+
+    /// Moves the line to `level` at CPU cycle `cycle`
+    pub fn set_level(&mut self, level: bool, cycle: u64) {
+        if level == self.level {
+            return;
+        }
+        let cycle = cycle.clamp(
+            self.window_start,
+            self.window_start + PSG_SAMPLE_DIVIDER as u64,
+        );
+        if self.level {
+            self.high_cycles += (cycle - self.last_edge.max(self.window_start)) as u32;
+        }
+        self.last_edge = cycle;
+        self.level = level;
+    }
+
+    /// Lines the sample windows up with the mixer, whose current step began at `cycle`.
+    ///
+    /// This only changes anything after the CPU cycle count or the mixer was reset.
+    pub fn align(&mut self, cycle: u64) {
+        if cycle != self.window_start {
+            self.window_start = cycle;
+            self.last_edge = cycle;
+            self.high_cycles = 0;
+        }
+    }
+
+    /// Closes the current PSG sample window and returns the filtered output level.
+    ///
+    /// The level is signed and centred on zero once the DC blocker settles, in the same
+    /// units as the PSG channel levels.
+    pub fn next_sample(&mut self) -> f32 {
+        let window_end = self.window_start + PSG_SAMPLE_DIVIDER as u64;
+        if self.level {
+            self.high_cycles += (window_end - self.last_edge.max(self.window_start)) as u32;
+        }
+        let coverage = self.high_cycles.min(PSG_SAMPLE_DIVIDER) as f32 / PSG_SAMPLE_DIVIDER as f32;
+        self.high_cycles = 0;
+        self.window_start = window_end;
+
+        self.low_pass += (coverage - self.low_pass) * LOW_PASS;
+        let output = self.low_pass - self.dc_input + DC_BLOCK_POLE * self.dc_output;
+        self.dc_input = self.low_pass;
+        self.dc_output = output;
+
+        if self.enabled {
+            output * self.volume
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The key click is as loud as a PSG tone channel at full volume
+pub const KEY_CLICK_VOLUME: f32 = 0.28;
+/// The cassette output only leaks into the sound output at a low level
+pub const CASSETTE_OUT_VOLUME: f32 = 0.07;
+
+// One-pole low-pass around 10kHz at the PSG sample rate
+const LOW_PASS: f32 = 0.43;
+// One-pole high-pass around 10Hz at the PSG sample rate
+const DC_BLOCK_POLE: f32 = 0.9994;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edges_within_a_sample_and_dc_blocking() {
+        let mut line = OneBitOutput::new(1.0);
+        line.set_level(true, 8);
+        line.set_level(false, 24);
+        let half = line.next_sample();
+        assert!(
+            (half - 0.5 * LOW_PASS).abs() < 1e-6,
+            "half a window high: {}",
+            half
+        );
+
+        // A line left high settles back to silence
+        line.set_level(true, 32);
+        let first = line.next_sample();
+        let settled = (0..100_000).map(|_| line.next_sample()).last().unwrap();
+        assert!(first > 0.5);
+        assert!(settled.abs() < 1e-3, "DC left: {}", settled);
+
+        line.set_enabled(false);
+        line.set_level(false, 32 * 100_002);
+        assert_eq!(line.next_sample(), 0.0);
+    }
+
+    #[test]
+    fn test_align_after_reset() {
+        let mut line = OneBitOutput::new(1.0);
+        line.align(1000);
+        line.set_level(true, 1016);
+        line.next_sample();
+        assert_eq!(line.window_start, 1032);
+
+        line.align(1032);
+        assert_eq!(line.high_cycles, 0);
+        line.reset();
+        assert!(!line.level());
+        assert_eq!(line.window_start, 1032);
+    }
+}
@@ -0,0 +1,342 @@
+use serde::{Deserialize, Serialize};
+
+use crate::fm::{self, Modulation, Operator, OperatorPatch, MODULATION_DEPTH, NATIVE_RATE};
+
+/// Yamaha YM2413 (OPLL) FM sound chip, the heart of MSX-MUSIC and the FM-PAC.
+///
+/// Nine two-operator channels play one of 15 built-in instruments or the user
+/// instrument in registers 0x00-0x07. In rhythm mode channels 7-9 become the bass
+/// drum, snare, tom, top cymbal and hi-hat. The chip produces one sample every 72
+/// clocks (about 49.7kHz), which the mixer interpolates up to its own rate.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Ym2413 {
+    registers: Vec<u8>,
+    address: u8,
+    // Two per channel, the modulator first
+    operators: Vec<Operator>,
+    modulation: Modulation,
+}
+
+impl Ym2413 {
+    pub fn new() -> Self {
+        Self {
+            registers: vec![0; 0x40],
+            address: 0,
+            operators: vec![Operator::default(); 18],
+            modulation: Modulation::default(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Port 0x7C: selects the register for the next data write
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value & 0x3F;
+    }
+
+    /// Port 0x7D: writes the selected register
+    pub fn write_data(&mut self, value: u8) {
+        let register = self.address as usize;
+        let previous = self.registers[register];
+        self.registers[register] = value;
+
+        match register {
+            0x0E => {
+                // Rhythm mode and the five drum keys
+                if value & 0x20 == 0 && previous & 0x20 != 0 {
+                    for operator in 12..18 {
+                        self.set_key(operator, false);
+                    }
+                }
+                self.update_rhythm_keys();
+            }
+            0x20..=0x28 => {
+                let channel = register - 0x20;
+                let key_on = value & 0x10 != 0;
+                if channel >= 6 && self.rhythm_mode() {
+                    self.update_rhythm_keys();
+                } else if (previous ^ value) & 0x10 != 0 {
+                    self.set_key(channel * 2, key_on);
+                    self.set_key(channel * 2 + 1, key_on);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// Register that the next data write goes to
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    fn rhythm_mode(&self) -> bool {
+        self.registers[0x0E] & 0x20 != 0
+    }
+
+    fn update_rhythm_keys(&mut self) {
+        if !self.rhythm_mode() {
+            return;
+        }
+        let keys = self.registers[0x0E];
+        let channel_key = |channel: usize| self.registers[0x20 + channel] & 0x10 != 0;
+        let bass_drum = keys & 0x10 != 0 || channel_key(6);
+        let snare_hat = channel_key(7);
+        let tom_cymbal = channel_key(8);
+        let operator_keys = [
+            (12, bass_drum),
+            (13, bass_drum),
+            (14, keys & 0x01 != 0 || snare_hat),
+            (15, keys & 0x08 != 0 || snare_hat),
+            (16, keys & 0x04 != 0 || tom_cymbal),
+            (17, keys & 0x02 != 0 || tom_cymbal),
+        ];
+        for (operator, key_on) in operator_keys {
+            if self.operators[operator].key_on != key_on {
+                self.set_key(operator, key_on);
+            }
+        }
+    }
+
+    fn set_key(&mut self, operator: usize, key_on: bool) {
+        let attack = self.operator_patch(operator).attack;
+        self.operators[operator].set_key(key_on, attack);
+    }
+
+    fn instrument(&self, channel: usize) -> [u8; 8] {
+        if channel >= 6 && self.rhythm_mode() {
+            return INSTRUMENTS[15 + channel - 6];
+        }
+        match self.registers[0x30 + channel] >> 4 {
+            0 => self.registers[0..8].try_into().unwrap(),
+            instrument => INSTRUMENTS[instrument as usize - 1],
+        }
+    }
+
+    fn operator_patch(&self, operator: usize) -> OperatorPatch {
+        let patch = self.instrument(operator / 2);
+        let carrier = operator & 1 == 1;
+        let index = carrier as usize;
+        OperatorPatch {
+            am: patch[index] & 0x80 != 0,
+            pm: patch[index] & 0x40 != 0,
+            sustained: patch[index] & 0x20 != 0,
+            key_scale_rate: patch[index] & 0x10 != 0,
+            multiple: patch[index] & 0x0F,
+            key_scale_level: patch[2 + index] >> 6,
+            half_sine: patch[3] & if carrier { 0x10 } else { 0x08 } != 0,
+            attack: patch[4 + index] >> 4,
+            decay: patch[4 + index] & 0x0F,
+            sustain_level: patch[6 + index] >> 4,
+            release: patch[6 + index] & 0x0F,
+        }
+    }
+
+    /// F-number, in the 10 bit units shared with the OPL, and block
+    fn frequency(&self, channel: usize) -> (u32, u32) {
+        let fnum = self.registers[0x10 + channel] as u32
+            | ((self.registers[0x20 + channel] as u32 & 1) << 8);
+        let block = (self.registers[0x20 + channel] as u32 >> 1) & 0x07;
+        (fnum << 1, block)
+    }
+
+    /// Advances the chip by one sample at its native rate and returns the output level,
+    /// signed and in PSG channel level units
+    pub fn next_sample(&mut self) -> f32 {
+        self.generate() * CHANNEL_MAX_VOLUME
+    }
+
+    /// Computes one sample at the chip's native rate, one unit per full scale channel
+    fn generate(&mut self) -> f32 {
+        self.modulation.advance(NATIVE_RATE);
+        let pm = self.modulation.pm(PM_DEPTH_CENTS);
+        for operator in 0..18 {
+            let channel = operator / 2;
+            let patch = self.operator_patch(operator);
+            let (fnum, block) = self.frequency(channel);
+            let release = if self.registers[0x20 + channel] & 0x20 != 0 {
+                // The sustain flag slows the release down
+                5
+            } else if patch.sustained {
+                patch.release
+            } else {
+                7
+            };
+            self.operators[operator].advance(&patch, fnum, block, pm, release);
+        }
+
+        let melodic_channels = if self.rhythm_mode() { 6 } else { 9 };
+        let mut output = 0.0;
+        for channel in 0..melodic_channels {
+            let modulation = self.modulator_output(channel * 2);
+            output += self.operator_output(channel * 2 + 1, modulation * MODULATION_DEPTH);
+        }
+        if self.rhythm_mode() {
+            output += self.rhythm_output() * 2.0;
+        }
+        output
+    }
+
+    /// Attenuation in 0.375dB steps, or `None` when the operator is silent
+    fn attenuation(&self, operator: usize, total_level: u32) -> Option<u32> {
+        let envelope = self.operators[operator].envelope_level()?;
+        let patch = self.operator_patch(operator);
+        let (fnum, block) = self.frequency(operator / 2);
+        let mut attenuation =
+            envelope + total_level + fm::key_scale_level(patch.key_scale_level, fnum, block);
+        if patch.am {
+            attenuation += self.modulation.am(AM_DEPTH);
+        }
+        Some(attenuation)
+    }
+
+    fn modulator_output(&mut self, operator: usize) -> f32 {
+        let instrument = self.instrument(operator / 2);
+        let total_level = (instrument[2] & 0x3F) as u32 * 2;
+        let state = &self.operators[operator];
+        let phase = state.phase() + fm::feedback_offset(instrument[3] & 0x07, state.feedback);
+        let output = match self.attenuation(operator, total_level) {
+            Some(attenuation) => {
+                fm::wave(phase, self.operator_patch(operator).half_sine, attenuation)
+            }
+            None => 0.0,
+        };
+        let state = &mut self.operators[operator];
+        state.feedback = [state.feedback[1], output];
+        output
+    }
+
+    fn operator_output(&self, operator: usize, modulation: f32) -> f32 {
+        let volume = (self.registers[0x30 + operator / 2] & 0x0F) as u32 * 8;
+        match self.attenuation(operator, volume) {
+            Some(attenuation) => fm::wave(
+                self.operators[operator].phase() + modulation,
+                self.operator_patch(operator).half_sine,
+                attenuation,
+            ),
+            None => 0.0,
+        }
+    }
+
+    fn rhythm_output(&mut self) -> f32 {
+        let noise = self.modulation.noise();
+        let mut output = 0.0;
+
+        // Bass drum: a normal FM pair on channel 7
+        let modulation = self.modulator_output(12);
+        output += self.operator_output(13, modulation * MODULATION_DEPTH);
+
+        // The hi-hat, snare and cymbal are built from the phase bits of the hi-hat
+        // and cymbal operators and the noise generator
+        let (hat, cymbal) = (&self.operators[14], &self.operators[17]);
+        let metallic = fm::metallic(hat, cymbal);
+
+        let hat_volume = (self.registers[0x37] >> 4) as u32 * 8;
+        if let Some(attenuation) = self.attenuation(14, hat_volume) {
+            output += fm::hi_hat(attenuation, noise, metallic);
+        }
+
+        let snare_volume = (self.registers[0x37] & 0x0F) as u32 * 8;
+        if let Some(attenuation) = self.attenuation(15, snare_volume) {
+            output += fm::snare_drum(attenuation, noise, hat);
+        }
+
+        let tom_volume = (self.registers[0x38] >> 4) as u32 * 8;
+        if let Some(attenuation) = self.attenuation(16, tom_volume) {
+            output += fm::wave(self.operators[16].phase(), false, attenuation);
+        }
+
+        let cymbal_volume = (self.registers[0x38] & 0x0F) as u32 * 8;
+        if let Some(attenuation) = self.attenuation(17, cymbal_volume) {
+            output += fm::top_cymbal(attenuation, metallic);
+        }
+        output
+    }
+}
+
+impl Default for Ym2413 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Built-in instruments 1-15 followed by the bass drum, snare/hi-hat and tom/cymbal
+// patches, as dumped from real chips
+const INSTRUMENTS: [[u8; 8]; 18] = [
+    [0x71, 0x61, 0x1E, 0x17, 0xD0, 0x78, 0x00, 0x17], // Violin
+    [0x13, 0x41, 0x1A, 0x0D, 0xD8, 0xF7, 0x23, 0x13], // Guitar
+    [0x13, 0x01, 0x99, 0x00, 0xF2, 0xC4, 0x11, 0x23], // Piano
+    [0x31, 0x61, 0x0E, 0x07, 0xA8, 0x64, 0x70, 0x27], // Flute
+    [0x32, 0x21, 0x1E, 0x06, 0xE0, 0x76, 0x00, 0x28], // Clarinet
+    [0x31, 0x22, 0x16, 0x05, 0xE0, 0x71, 0x00, 0x18], // Oboe
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x10, 0x07], // Trumpet
+    [0x23, 0x21, 0x2D, 0x14, 0xA2, 0x72, 0x00, 0x07], // Organ
+    [0x61, 0x61, 0x1B, 0x06, 0x64, 0x65, 0x10, 0x17], // Horn
+    [0x41, 0x61, 0x0B, 0x18, 0x85, 0xF7, 0x71, 0x07], // Synthesizer
+    [0x13, 0x01, 0x83, 0x11, 0xFA, 0xE4, 0x10, 0x04], // Harpsichord
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12], // Vibraphone
+    [0x61, 0x50, 0x0C, 0x05, 0xC2, 0xF5, 0x20, 0x42], // Synthesizer bass
+    [0x01, 0x01, 0x55, 0x03, 0xC9, 0x95, 0x03, 0x02], // Acoustic bass
+    [0x61, 0x41, 0x89, 0x03, 0xF1, 0xE4, 0x40, 0x13], // Electric guitar
+    [0x01, 0x01, 0x18, 0x0F, 0xDF, 0xF8, 0x6A, 0x6D], // Bass drum
+    [0x01, 0x01, 0x00, 0x00, 0xC8, 0xD8, 0xA7, 0x68], // Hi-hat, snare drum
+    [0x05, 0x01, 0x00, 0x00, 0xF8, 0xAA, 0x59, 0x55], // Tom-tom, top cymbal
+];
+
+/// 4.875dB in 0.375dB steps
+const AM_DEPTH: f32 = 13.0;
+const PM_DEPTH_CENTS: f32 = 13.75;
+
+/// Level of one full scale channel, in PSG channel level units
+const CHANNEL_MAX_VOLUME: f32 = 0.05;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(opll: &mut Ym2413, register: u8, value: u8) {
+        opll.write_address(register);
+        opll.write_data(value);
+    }
+
+    fn peak(opll: &mut Ym2413, samples: usize) -> f32 {
+        (0..samples)
+            .map(|_| opll.next_sample().abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn test_key_on_plays_and_key_off_releases() {
+        let mut opll = Ym2413::new();
+        assert_eq!(peak(&mut opll, 1000), 0.0);
+
+        // Piano at full volume, A4 (F-number 288, block 4)
+        write(&mut opll, 0x30, 0x30);
+        write(&mut opll, 0x10, 0x20);
+        write(&mut opll, 0x20, 0x18 | 0x01);
+        let playing = peak(&mut opll, 4000);
+        assert!(playing > CHANNEL_MAX_VOLUME * 0.5, "peak {}", playing);
+
+        write(&mut opll, 0x20, 0x08 | 0x01);
+        peak(&mut opll, 200_000);
+        assert_eq!(peak(&mut opll, 1000), 0.0, "released note fades out");
+    }
+
+    #[test]
+    fn test_rhythm_mode_drums() {
+        let mut opll = Ym2413::new();
+        write(&mut opll, 0x16, 0x20);
+        write(&mut opll, 0x26, 0x05);
+        write(&mut opll, 0x36, 0x00);
+        write(&mut opll, 0x0E, 0x20);
+        assert_eq!(peak(&mut opll, 1000), 0.0, "no drum keyed on yet");
+
+        write(&mut opll, 0x0E, 0x30);
+        assert!(peak(&mut opll, 4000) > 0.0, "bass drum sounds");
+    }
+}
@@ -0,0 +1,206 @@
+use serde::{Deserialize, Serialize};
+
+/// Colour palettes used to turn the VDP's 4-bit colour indices into RGBA8888 pixels.
+///
+/// Index 0 is "transparent" on the TMS9918; the palettes map it to black, and
+/// `Renderer::to_rgba` resolves it to the backdrop colour before the lookup.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum Palette {
+    /// TMS9918A (NTSC) colours as commonly measured from the composite output
+    #[default]
+    Tms9918Ntsc,
+    /// TMS9929A (PAL) colours, derived from the datasheet's Y/R-Y/B-Y levels
+    Tms9929Pal,
+    /// V9938 power-on palette (3 bits per component)
+    V9938,
+    /// User supplied table of 16 RGB triplets
+    Custom([[u8; 3]; 16]),
+}
+
+impl Palette {
+    /// Names accepted by `Palette::from_name`, in the order they are usually presented
+    pub const NAMES: [&'static str; 3] = ["tms9918", "tms9929", "v9938"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "tms9918" | "tms9918a" | "ntsc" => Some(Palette::Tms9918Ntsc),
+            "tms9929" | "tms9929a" | "pal" => Some(Palette::Tms9929Pal),
+            "v9938" => Some(Palette::V9938),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Palette::Tms9918Ntsc => "tms9918",
+            Palette::Tms9929Pal => "tms9929",
+            Palette::V9938 => "v9938",
+            Palette::Custom(_) => "custom",
+        }
+    }
+
+    /// Builds a custom palette from 16 packed RGB (48 bytes) or RGBA (64 bytes) entries
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let stride = match data.len() {
+            48 => 3,
+            64 => 4,
+            len => {
+                return Err(format!(
+                    "Invalid palette size: {} bytes (expected 48 for RGB or 64 for RGBA)",
+                    len
+                ))
+            }
+        };
+
+        let mut colors = [[0u8; 3]; 16];
+        for (i, color) in colors.iter_mut().enumerate() {
+            color.copy_from_slice(&data[i * stride..i * stride + 3]);
+        }
+
+        Ok(Palette::Custom(colors))
+    }
+
+    /// Returns the 16 palette entries as RGBA8888 (alpha is always opaque)
+    pub fn colors(&self) -> [[u8; 4]; 16] {
+        let rgb = match self {
+            Palette::Tms9918Ntsc => TMS9918_NTSC,
+            Palette::Tms9929Pal => tms9929_pal(),
+            Palette::V9938 => v9938_default(),
+            Palette::Custom(colors) => *colors,
+        };
+
+        let mut rgba = [[0u8; 4]; 16];
+        for (dst, src) in rgba.iter_mut().zip(rgb.iter()) {
+            *dst = [src[0], src[1], src[2], 0xFF];
+        }
+        rgba
+    }
+
+    /// Palette as a flat RGBA byte array (16 * 4 bytes), for frontends
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.colors().iter().flatten().copied().collect()
+    }
+}
+
+const TMS9918_NTSC: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00], // 0 Transparent
+    [0x00, 0x00, 0x00], // 1 Black
+    [0x21, 0xC8, 0x42], // 2 Medium Green
+    [0x5E, 0xDC, 0x78], // 3 Light Green
+    [0x54, 0x55, 0xED], // 4 Dark Blue
+    [0x7D, 0x76, 0xFC], // 5 Light Blue
+    [0xD4, 0x52, 0x4D], // 6 Dark Red
+    [0x42, 0xEB, 0xF5], // 7 Cyan
+    [0xFC, 0x55, 0x54], // 8 Medium Red
+    [0xFF, 0x79, 0x78], // 9 Light Red
+    [0xD4, 0xC1, 0x54], // 10 Dark Yellow
+    [0xE6, 0xCE, 0x80], // 11 Light Yellow
+    [0x21, 0xB0, 0x3B], // 12 Dark Green
+    [0xC9, 0x5B, 0xBA], // 13 Magenta
+    [0xCC, 0xCC, 0xCC], // 14 Gray
+    [0xFF, 0xFF, 0xFF], // 15 White
+];
+
+// TMS9929A output levels (Y, R-Y, B-Y) from the datasheet, normalised so that 0.47 is zero chroma
+const TMS9929_YPBPR: [(f32, f32, f32); 16] = [
+    (0.00, 0.47, 0.47), // 0 Transparent
+    (0.00, 0.47, 0.47), // 1 Black
+    (0.53, 0.07, 0.20), // 2 Medium Green
+    (0.67, 0.17, 0.27), // 3 Light Green
+    (0.40, 0.40, 1.00), // 4 Dark Blue
+    (0.53, 0.43, 0.93), // 5 Light Blue
+    (0.47, 0.83, 0.30), // 6 Dark Red
+    (0.73, 0.00, 0.70), // 7 Cyan
+    (0.53, 0.93, 0.27), // 8 Medium Red
+    (0.67, 0.93, 0.27), // 9 Light Red
+    (0.73, 0.57, 0.07), // 10 Dark Yellow
+    (0.80, 0.57, 0.17), // 11 Light Yellow
+    (0.47, 0.13, 0.23), // 12 Dark Green
+    (0.53, 0.73, 0.67), // 13 Magenta
+    (0.80, 0.47, 0.47), // 14 Gray
+    (1.00, 0.47, 0.47), // 15 White
+];
+
+fn tms9929_pal() -> [[u8; 3]; 16] {
+    let mut colors = [[0u8; 3]; 16];
+    for (color, &(y, r_y, b_y)) in colors.iter_mut().zip(TMS9929_YPBPR.iter()) {
+        let pr = r_y - 0.47;
+        let pb = b_y - 0.47;
+
+        // ITU-R BT.601 YPbPr -> RGB
+        let r = y + 1.402 * pr;
+        let b = y + 1.772 * pb;
+        let g = (y - 0.299 * r - 0.114 * b) / 0.587;
+
+        *color = [to_u8(r), to_u8(g), to_u8(b)];
+    }
+    colors
+}
+
+// V9938 power-on palette, 3-bit R, G, B per entry
+const V9938_RGB333: [[u8; 3]; 16] = [
+    [0, 0, 0],
+    [0, 0, 0],
+    [1, 6, 1],
+    [3, 7, 3],
+    [1, 1, 7],
+    [2, 3, 7],
+    [5, 1, 1],
+    [2, 6, 7],
+    [7, 1, 1],
+    [7, 3, 3],
+    [6, 6, 1],
+    [6, 6, 4],
+    [1, 4, 1],
+    [6, 2, 5],
+    [5, 5, 5],
+    [7, 7, 7],
+];
+
+fn v9938_default() -> [[u8; 3]; 16] {
+    let mut colors = [[0u8; 3]; 16];
+    for (color, rgb) in colors.iter_mut().zip(V9938_RGB333.iter()) {
+        for (dst, &level) in color.iter_mut().zip(rgb.iter()) {
+            *dst = ((level as u32 * 255) / 7) as u8;
+        }
+    }
+    colors
+}
+
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_palettes() {
+        let ntsc = Palette::Tms9918Ntsc.colors();
+        assert_eq!(ntsc[15], [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(ntsc[4], [0x54, 0x55, 0xED, 0xFF]);
+
+        let pal = Palette::Tms9929Pal.colors();
+        assert_eq!(pal[1], [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(pal[15], [0xFF, 0xFF, 0xFF, 0xFF]);
+        // Dark blue must be dominated by its blue component
+        assert!(pal[4][2] > pal[4][0] && pal[4][2] > pal[4][1]);
+
+        let v9938 = Palette::V9938.colors();
+        assert_eq!(v9938[14], [0xB6, 0xB6, 0xB6, 0xFF]);
+        assert_eq!(v9938[15], [0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_custom_palette() {
+        let mut data = vec![0u8; 48];
+        data[15 * 3..].copy_from_slice(&[1, 2, 3]);
+        let palette = Palette::from_bytes(&data).unwrap();
+        assert_eq!(palette.colors()[15], [1, 2, 3, 0xFF]);
+        assert_eq!(palette.to_bytes().len(), 64);
+
+        assert!(Palette::from_bytes(&[0; 10]).is_err());
+        assert_eq!(Palette::from_name("PAL"), Some(Palette::Tms9929Pal));
+    }
+}
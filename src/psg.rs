@@ -1,18 +1,24 @@
 #![allow(dead_code)]
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+use crate::clock::CPU_CLOCK_HZ;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct AY38910 {
     registers: [u8; 16],
     selected_register: u8,
+    model: PsgModel,
     channel: AudioChannel,
-    clock_divider: u32,
-    sample_counter: u32,
-    // Buffer for audio samples
-    sample_buffer: Vec<f32>,
-    // Joystick state (0xFF means no buttons pressed)
-    pub joystick_port_a: u8,
-    pub joystick_port_b: u8,
+    // Stereo output with a per channel pan, otherwise both sides carry the mono mix
+    stereo: bool,
+    pans: [f32; 3],
+    // Per voice waveforms of the last frame, for oscilloscope views
+    scope: ScopeTap,
+    // Pins 1-4, 6 and 7 of joystick ports 1 and 2 as register 14 bits 0-5, active low
+    // (0xFF means no buttons pressed)
+    pub joystick_ports: [u8; 2],
+    // Register 14 bit 7, the level of the tape signal with one playing
+    pub cassette_input: bool,
 }
 
 impl AY38910 {
@@ -20,12 +26,13 @@ impl AY38910 {
         let mut psg = Self {
             registers: [0; 16],
             selected_register: 0,
+            model: PsgModel::default(),
             channel: AudioChannel::new(),
-            clock_divider: 0,
-            sample_counter: 0,
-            sample_buffer: Vec::with_capacity(4096),
-            joystick_port_a: 0xFF, // All bits set = no buttons pressed
-            joystick_port_b: 0xFF, // All bits set = no buttons pressed
+            stereo: false,
+            pans: ABC_STEREO_PANS,
+            scope: ScopeTap::default(),
+            joystick_ports: [0xFF; 2], // All bits set = no buttons pressed
+            cassette_input: true,
         };
 
         // Initialize register 7 (mixer) to 0xFF (all channels disabled by default)
@@ -39,100 +46,178 @@ impl AY38910 {
         self.registers = [0; 16];
         self.selected_register = 0;
         self.channel.reset();
-        self.clock_divider = 0;
-        self.sample_counter = 0;
-        self.sample_buffer.clear();
-        self.joystick_port_a = 0xFF;
-        self.joystick_port_b = 0xFF;
-    }
-
-    // Handle joystick button presses
-    pub fn joystick_key_down(&mut self, key: String) {
-        // Map keyboard keys to joystick bits
-        // Space is typically mapped to the fire button (bit 4)
-        match key.as_str() {
-            "Space" => self.joystick_port_a &= !(1 << 4), // Clear bit 4 (fire button)
-            "ArrowUp" => self.joystick_port_a &= !(1 << 0), // Clear bit 0 (up)
-            "ArrowDown" => self.joystick_port_a &= !(1 << 1), // Clear bit 1 (down)
-            "ArrowLeft" => self.joystick_port_a &= !(1 << 2), // Clear bit 2 (left)
-            "ArrowRight" => self.joystick_port_a &= !(1 << 3), // Clear bit 3 (right)
-            _ => {}                                       // Ignore other keys
-        }
-        tracing::info!(
-            "[PSG] Joystick key down: {}, state: {:08b}",
-            key,
-            self.joystick_port_a
-        );
-    }
-
-    // Handle joystick button releases
-    pub fn joystick_key_up(&mut self, key: String) {
-        match key.as_str() {
-            "Space" => self.joystick_port_a |= 1 << 4, // Set bit 4 (fire button)
-            "ArrowUp" => self.joystick_port_a |= 1 << 0, // Set bit 0 (up)
-            "ArrowDown" => self.joystick_port_a |= 1 << 1, // Set bit 1 (down)
-            "ArrowLeft" => self.joystick_port_a |= 1 << 2, // Set bit 2 (left)
-            "ArrowRight" => self.joystick_port_a |= 1 << 3, // Set bit 3 (right)
-            _ => {}                                    // Ignore other keys
-        }
-        tracing::info!(
-            "[PSG] Joystick key up: {}, state: {:08b}",
-            key,
-            self.joystick_port_a
-        );
-    }
-
-    // Get next audio sample from the buffer
-    pub fn get_audio_sample(&mut self) -> f32 {
-        if !self.sample_buffer.is_empty() {
-            self.sample_buffer.remove(0)
+        self.scope.clear();
+        self.joystick_ports = [0xFF; 2];
+    }
+
+    pub fn is_stereo(&self) -> bool {
+        self.stereo
+    }
+
+    /// Switch between mono output and stereo output using the channel pans
+    pub fn set_stereo(&mut self, stereo: bool) {
+        self.stereo = stereo;
+        self.update_pans();
+    }
+
+    pub fn pans(&self) -> [f32; 3] {
+        self.pans
+    }
+
+    /// Pan of tone channel 0-2 (A-C), from -1.0 (left) through 0.0 (centre) to 1.0 (right)
+    pub fn set_pan(&mut self, channel: usize, pan: f32) -> Result<(), String> {
+        let Some(slot) = self.pans.get_mut(channel) else {
+            return Err(format!("Invalid PSG channel: {}", channel));
+        };
+        *slot = pan.clamp(-1.0, 1.0);
+        self.update_pans();
+        Ok(())
+    }
+
+    fn update_pans(&mut self) {
+        let pans = if self.stereo { self.pans } else { [0.0; 3] };
+        for (i, pan) in pans.iter().enumerate() {
+            self.channel.vol_pan_l[i] = (1.0 - pan).min(1.0);
+            self.channel.vol_pan_r[i] = (1.0 + pan).min(1.0);
+        }
+    }
+
+    pub fn model(&self) -> PsgModel {
+        self.model
+    }
+
+    /// Switch the DAC table and envelope resolution to the given chip
+    pub fn set_model(&mut self, model: PsgModel) {
+        self.model = model;
+        self.channel.volume_curve = model.volume_curve();
+        for reg in 8..=10 {
+            self.update_channel_from_register(reg, self.registers[reg as usize]);
+        }
+    }
+
+    pub fn registers(&self) -> [u8; 16] {
+        self.registers
+    }
+
+    /// I/O port B as its pins see it: the register 15 latch while register 7 bit 7
+    /// makes it an output, otherwise floating high
+    fn port_b(&self) -> u8 {
+        if self.registers[7] & 0x80 != 0 {
+            self.registers[15]
         } else {
-            0.0
+            0xFF
         }
     }
 
-    // Check if we have enough samples in the buffer
-    pub fn has_samples(&self, count: usize) -> bool {
-        self.sample_buffer.len() >= count
+    /// Joystick port (0 or 1) that register 14 reads, selected by register 15 bit 6
+    pub fn joystick_select(&self) -> usize {
+        (self.port_b() >> 6 & 1) as usize
     }
 
-    pub fn clock(&mut self, cycles: u32) {
-        // PSG runs at CPU_CLOCK / 8 = ~447kHz for internal updates
-        // PSG generates samples at CPU_CLOCK / 32 = ~112kHz
-        const PSG_CLOCK_DIVIDER: u32 = 8;
-        const PSG_SAMPLE_DIVIDER: u32 = 32;
+    /// Output pins of a joystick port as driven by register 15: bit 0 is pin 6, bit 1
+    /// pin 7 and bit 2 pin 8
+    pub fn output_pins(&self, port: usize) -> u8 {
+        let value = self.port_b();
+        let shift = if port == 0 { 0 } else { 2 };
+        (value >> shift & 0x03) | (value >> (4 + port) & 1) << 2
+    }
 
-        self.clock_divider += cycles;
+    /// The Kana (or Hangul) LED is lit while register 15 bit 7 is low
+    pub fn kana_led(&self) -> bool {
+        self.port_b() & 0x80 == 0
+    }
 
-        // Generate samples at PSG native rate (112kHz)
-        while self.clock_divider >= PSG_SAMPLE_DIVIDER {
-            self.clock_divider -= PSG_SAMPLE_DIVIDER;
+    /// Register 14: the selected joystick port, with the keyboard layout bit left high
+    /// and the cassette input in bit 7.
+    ///
+    /// Pins 6 and 7 are wired to open collector outputs of register 15 as well, so a
+    /// trigger reads as pressed while its output is driven low; that is how devices on
+    /// those pins are strobed.
+    fn read_port_a(&self) -> u8 {
+        let port = self.joystick_select();
+        let outputs = self.output_pins(port) & 0x03;
+        let cassette_input = (self.cassette_input as u8) << 7;
+        (self.joystick_ports[port] & 0x3F | 0x40 | cassette_input) & (0xCF | outputs << 4)
+    }
 
-            // Generate next PSG sample
-            let samples = self.channel.next_sample();
+    /// Register that the next write to port 0xA1 goes to
+    pub fn selected_register(&self) -> u8 {
+        self.selected_register
+    }
+
+    pub fn is_muted(&self, voice: PsgVoice) -> bool {
+        self.channel.muted[voice as usize]
+    }
+
+    pub fn set_muted(&mut self, voice: PsgVoice, muted: bool) {
+        self.channel.muted[voice as usize] = muted;
+    }
+
+    pub fn is_soloed(&self, voice: PsgVoice) -> bool {
+        self.channel.soloed[voice as usize]
+    }
+
+    /// While any voice is soloed, only soloed voices are heard
+    pub fn set_soloed(&mut self, voice: PsgVoice, soloed: bool) {
+        self.channel.soloed[voice as usize] = soloed;
+    }
+
+    /// Start or stop recording per voice waveforms; the mixed output is not affected
+    pub fn set_scope_enabled(&mut self, enabled: bool) {
+        self.scope.enabled = enabled;
+        self.scope.clear();
+    }
+
+    /// Waveform of a voice over the last complete frame, at the native sample rate.
+    /// Tone channels hold their output level before mute/solo and panning, noise is the
+    /// generator output (0 or 1) and the envelope its level from 0 to 1.
+    pub fn scope(&self, voice: PsgVoice) -> &[f32] {
+        &self.scope.last_frame[voice as usize]
+    }
+
+    /// Register values latched at the end of the last frame, alongside `scope`
+    pub fn scope_registers(&self) -> [u8; 16] {
+        self.scope.registers
+    }
 
-            // Convert to float in range -1.0 to 1.0
-            let raw_value = samples[0] as f32 / 255.0;
-            let mono_sample = (raw_value * 0.66 * 2.0) - 1.0;
+    /// Called at the end of every video frame to publish the scope buffers
+    pub fn end_frame(&mut self) {
+        if self.scope.enabled {
+            self.scope.registers = self.registers;
+            for (last, current) in self
+                .scope
+                .last_frame
+                .iter_mut()
+                .zip(self.scope.current.iter_mut())
+            {
+                std::mem::swap(last, current);
+                current.clear();
+            }
+        }
+    }
 
-            self.sample_buffer.push(mono_sample);
+    /// Produces one sample at the native rate, CPU clock / 32: the left and right levels
+    /// of the tone channels after mute, solo and panning, in channel level units
+    pub fn next_frame(&mut self) -> [f32; 2] {
+        let frame = self.channel.next_sample();
 
-            // Prevent buffer from growing too large
-            if self.sample_buffer.len() > 8192 {
-                self.sample_buffer.drain(0..4096);
+        if self.scope.enabled {
+            let levels = self.channel.voice_levels();
+            for (buffer, level) in self.scope.current.iter_mut().zip(levels) {
+                buffer.push(level);
             }
         }
+
+        frame
     }
 
     pub fn read(&mut self, port: u8) -> u8 {
         match port {
             0xA0 => self.selected_register,
             0xA1 | 0xA2 => {
-                // For register 14 (0x0E), return joystick port A state
+                // Register 14 is the input port; register 15 reads back its latch
                 if self.selected_register == 14 {
-                    self.joystick_port_a
-                } else if self.selected_register == 15 {
-                    self.joystick_port_b
+                    self.read_port_a()
                 } else {
                     self.registers[self.selected_register as usize]
                 }
@@ -154,14 +239,6 @@ impl AY38910 {
         }
     }
 
-    pub fn set_pulse_signal(&mut self, active: bool) {
-        self.channel.pulse_signal = active;
-        if active {
-            self.channel.pulse_signal_on_clocks = self.sample_counter as u8;
-            self.channel.current_sample_p = 1.0;
-        }
-    }
-
     fn update_channel_from_register(&mut self, reg: u8, value: u8) {
         match reg {
             // Channel A tone period
@@ -218,14 +295,77 @@ impl AY38910 {
                 self.channel.attack_e = (value & 0x04) != 0;
                 self.channel.alternate_e = (value & 0x02) != 0;
                 self.channel.hold_e = (value & 0x01) != 0;
-                self.channel
-                    .cycle_envelope(self.channel.alternate_e, self.channel.hold_e);
+                self.channel.restart_envelope();
             }
             _ => {}
         }
     }
 }
 
+impl Default for AY38910 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The sound sources of the PSG that can be muted, soloed and traced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PsgVoice {
+    ToneA,
+    ToneB,
+    ToneC,
+    Noise,
+    Envelope,
+}
+
+impl PsgVoice {
+    pub const ALL: [PsgVoice; 5] = [
+        PsgVoice::ToneA,
+        PsgVoice::ToneB,
+        PsgVoice::ToneC,
+        PsgVoice::Noise,
+        PsgVoice::Envelope,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "a" | "tonea" => Some(PsgVoice::ToneA),
+            "b" | "toneb" => Some(PsgVoice::ToneB),
+            "c" | "tonec" => Some(PsgVoice::ToneC),
+            "noise" => Some(PsgVoice::Noise),
+            "envelope" => Some(PsgVoice::Envelope),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PsgVoice::ToneA => "a",
+            PsgVoice::ToneB => "b",
+            PsgVoice::ToneC => "c",
+            PsgVoice::Noise => "noise",
+            PsgVoice::Envelope => "envelope",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+struct ScopeTap {
+    enabled: bool,
+    current: [Vec<f32>; 5],
+    last_frame: [Vec<f32>; 5],
+    registers: [u8; 16],
+}
+
+impl ScopeTap {
+    fn clear(&mut self) {
+        for buffer in self.current.iter_mut().chain(self.last_frame.iter_mut()) {
+            buffer.clear();
+        }
+        self.registers = [0; 16];
+    }
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
 struct AudioChannel {
     period_a: u16,
@@ -257,7 +397,7 @@ struct AudioChannel {
     current_sample_n: f32,
 
     period_e: u16,
-    period_e_countdown: u16,
+    period_e_countdown: u32,
     current_value_e: f32,
     pub direction_e: i8,
     pub continue_e: bool,
@@ -265,37 +405,28 @@ struct AudioChannel {
     pub alternate_e: bool,
     pub hold_e: bool,
 
-    pub pulse_signal: bool,
-    pub pulse_signal_on_clocks: u8,
-    pub current_sample_p: f32,
-
-    sample_result: [u8; 2],
-
+    // 32 output levels of the chip's DAC; fixed volumes use every odd entry
     #[serde(skip)]
     volume_curve: Vec<f32>,
 
-    #[serde(skip)]
-    vol_pan_l: Vec<f32>,
-    #[serde(skip)]
-    vol_pan_r: Vec<f32>,
+    // Mute and solo flags, indexed by `PsgVoice`
+    muted: [bool; 5],
+    soloed: [bool; 5],
+
+    // Left/right gains for channels A, B and C
+    vol_pan_l: [f32; 3],
+    vol_pan_r: [f32; 3],
 
     lfsr: u32,
 }
 
 impl AudioChannel {
     pub fn new() -> Self {
-        let mut volume_curve = Vec::new();
-        // WebMSX volume curve: volumeCurve[v] = Math.pow(2, -(15 - v) / 2) * CHANNEL_MAX_VOLUME
-        volume_curve.push(0.0); // Volume 0 is always silent
-        for v in 1..16 {
-            let volume = (2.0_f32).powf(-((15 - v) as f32) / 2.0) * CHANNEL_MAX_VOLUME;
-            volume_curve.push(volume);
-        }
-
-        // if (VOLPAN) wmsx.AudioTables.setupVolPan(4, VOL, PAN, volPanL, volPanR);
-
         Self {
-            volume_curve,
+            volume_curve: PsgModel::default().volume_curve(),
+            period_e: 1,
+            vol_pan_l: [1.0; 3],
+            vol_pan_r: [1.0; 3],
             lfsr: 0x01fffe,
             ..Default::default()
         }
@@ -306,9 +437,6 @@ impl AudioChannel {
         self.set_amplitude_a(0);
         self.set_amplitude_b(0);
         self.set_amplitude_c(0);
-        self.pulse_signal = false;
-        self.pulse_signal_on_clocks = 0;
-        self.current_sample_p = 0.0;
     }
 
     fn set_period_a(&mut self, new_period: u16) {
@@ -358,11 +486,7 @@ impl AudioChannel {
     }
 
     fn set_period_e(&mut self, new_period: u16) {
-        let period = new_period & 0xFFFF;
-        if self.period_e == period {
-            return;
-        }
-        self.period_e = if period < 1 { 1 } else { period };
+        self.period_e = new_period.max(1);
     }
 
     fn set_amplitude_a(&mut self, new_amplitude: u8) {
@@ -371,7 +495,7 @@ impl AudioChannel {
             self.amplitude_a = self.volume_curve[self.current_value_e as usize];
         } else {
             self.envelope_a = false;
-            self.amplitude_a = self.volume_curve[(new_amplitude & 0x0f) as usize];
+            self.amplitude_a = self.volume_curve[fixed_volume_index(new_amplitude)];
         }
     }
 
@@ -381,7 +505,7 @@ impl AudioChannel {
             self.amplitude_b = self.volume_curve[self.current_value_e as usize];
         } else {
             self.envelope_b = false;
-            self.amplitude_b = self.volume_curve[(new_amplitude & 0x0f) as usize];
+            self.amplitude_b = self.volume_curve[fixed_volume_index(new_amplitude)];
         }
     }
 
@@ -391,7 +515,7 @@ impl AudioChannel {
             self.amplitude_c = self.volume_curve[self.current_value_e as usize];
         } else {
             self.envelope_c = false;
-            self.amplitude_c = self.volume_curve[(new_amplitude & 0x0f) as usize];
+            self.amplitude_c = self.volume_curve[fixed_volume_index(new_amplitude)];
         }
     }
 
@@ -404,7 +528,7 @@ impl AudioChannel {
         self.noise_c = (control & 0x20) == 0;
     }
 
-    fn next_sample(&mut self) -> [u8; 2] {
+    fn next_sample(&mut self) -> [f32; 2] {
         // Update values
         // The PSG runs at CPU_CLOCK / 8, and we're calling this per sample
         // So we need to advance counters appropriately
@@ -451,11 +575,16 @@ impl AudioChannel {
             }
         }
         if self.direction_e != 0 {
-            self.period_e_countdown += 1;
-            if self.period_e_countdown >= self.period_e {
-                self.period_e_countdown = 0;
+            // The envelope always runs through 32 steps at twice the rate of the 16 step
+            // AY-3-8910 envelope; the AY's DAC table pairs up the steps
+            // A period of 0 acts as 1; the countdown is wider than the period so a
+            // period of 0xFFFF cannot overflow it
+            let period = self.period_e.max(1) as u32;
+            self.period_e_countdown += 2;
+            while self.direction_e != 0 && self.period_e_countdown >= period {
+                self.period_e_countdown -= period;
                 self.current_value_e += self.direction_e as f32;
-                if self.current_value_e < 0.0 || self.current_value_e > 15.0 {
+                if self.current_value_e < 0.0 || self.current_value_e > ENVELOPE_MAX {
                     if self.continue_e {
                         self.cycle_envelope(self.alternate_e, self.hold_e);
                     } else {
@@ -467,102 +596,107 @@ impl AudioChannel {
             }
         }
 
-        let vol_pan = self.vol_pan_l.len() >= 4 && self.vol_pan_r.len() >= 4;
-        if vol_pan {
-            // has to be VOLPAN, the const
-            // Complete Stereo path (VOL/PAN)
-            let sample_a = if self.amplitude_a == 0.0
-                || (self.tone_a && self.current_sample_a == 0.0)
-                || (self.noise_a && self.current_sample_n == 0.0)
-            {
-                0.0
-            } else {
-                self.amplitude_a
-            };
-            let sample_b = if self.amplitude_b == 0.0
-                || (self.tone_b && self.current_sample_b == 0.0)
-                || (self.noise_b && self.current_sample_n == 0.0)
-            {
-                0.0
-            } else {
-                self.amplitude_b
-            };
-            let sample_c = if self.amplitude_c == 0.0
-                || (self.tone_c && self.current_sample_c == 0.0)
-                || (self.noise_c && self.current_sample_n == 0.0)
-            {
-                0.0
-            } else {
-                self.amplitude_c
-            };
-            let sample_p = if self.current_sample_p != 0.0 {
-                if !self.pulse_signal
-                // && self.get_bus_cycles() - self.pulse_signal_on_clocks >= MIN_PULSE_ON_CLOCKS
-                {
-                    self.current_sample_p = 0.0;
-                }
-                CHANNEL_MAX_VOLUME
-            } else {
-                0.0
-            };
-            self.sample_result[0] = (sample_a * self.vol_pan_l[0]
-                + sample_b * self.vol_pan_l[1]
-                + sample_c * self.vol_pan_l[2]
-                + sample_p * self.vol_pan_l[3]) as u8;
-            self.sample_result[1] = (sample_a * self.vol_pan_r[0]
-                + sample_b * self.vol_pan_r[1]
-                + sample_c * self.vol_pan_r[2]
-                + sample_p * self.vol_pan_r[3]) as u8;
-            self.sample_result
-        } else {
-            // Simple Mono path (no VOL/PAN)
-            // Correctly mix the channels by averaging them
-            let sample_a = if self.amplitude_a == 0.0
-                || (self.tone_a && self.current_sample_a == 0.0)
-                || (self.noise_a && self.current_sample_n == 0.0)
-            {
-                0.0
-            } else {
-                self.amplitude_a
-            };
-            let sample_b = if self.amplitude_b == 0.0
-                || (self.tone_b && self.current_sample_b == 0.0)
-                || (self.noise_b && self.current_sample_n == 0.0)
-            {
-                0.0
-            } else {
-                self.amplitude_b
-            };
-            let sample_c = if self.amplitude_c == 0.0
-                || (self.tone_c && self.current_sample_c == 0.0)
-                || (self.noise_c && self.current_sample_n == 0.0)
-            {
-                0.0
-            } else {
-                self.amplitude_c
-            };
-
-            let mut m_sample_result = (sample_a + sample_b + sample_c) / 3.0;
-
-            if self.current_sample_p != 0.0 {
-                if !self.pulse_signal
-                // && self.get_bus_cycles() - self.pulse_signal_on_clocks >= MIN_PULSE_ON_CLOCKS
-                {
-                    self.current_sample_p = 0.0;
+        let sample_a = self.channel_output(0, true);
+        let sample_b = self.channel_output(1, true);
+        let sample_c = self.channel_output(2, true);
+
+        // Average the tone channels so a centred mix matches the mono output, which
+        // WebMSX returns as the sum (max ~0.84 with 3 channels at 0.28 each)
+        let left = (sample_a * self.vol_pan_l[0]
+            + sample_b * self.vol_pan_l[1]
+            + sample_c * self.vol_pan_l[2])
+            / 3.0;
+        let right = (sample_a * self.vol_pan_r[0]
+            + sample_b * self.vol_pan_r[1]
+            + sample_c * self.vol_pan_r[2])
+            / 3.0;
+
+        [left.min(1.0), right.min(1.0)]
+    }
+
+    /// Output level of tone channel 0-2 (A-C), optionally with mute and solo applied
+    fn channel_output(&self, channel: usize, apply_mute: bool) -> f32 {
+        let (amplitude, tone, mut noise, envelope, square) = match channel {
+            0 => (
+                self.amplitude_a,
+                self.tone_a,
+                self.noise_a,
+                self.envelope_a,
+                self.current_sample_a,
+            ),
+            1 => (
+                self.amplitude_b,
+                self.tone_b,
+                self.noise_b,
+                self.envelope_b,
+                self.current_sample_b,
+            ),
+            _ => (
+                self.amplitude_c,
+                self.tone_c,
+                self.noise_c,
+                self.envelope_c,
+                self.current_sample_c,
+            ),
+        };
+
+        if apply_mute {
+            if !self.voice_audible(channel, noise, envelope) {
+                return 0.0;
+            }
+            // Without noise a channel keeps its tone, or goes quiet if it only had noise
+            if noise && self.muted[PsgVoice::Noise as usize] {
+                if !tone {
+                    return 0.0;
                 }
-                m_sample_result += CHANNEL_MAX_VOLUME;
+                noise = false;
             }
-            // Return the raw float value, let generate_sample handle conversion
-            // WebMSX returns the sum directly (max ~0.84 with 3 channels at 0.28 each)
-            [((m_sample_result * 255.0) as u8).min(255), 0]
         }
+
+        if amplitude == 0.0 || (tone && square == 0.0) || (noise && self.current_sample_n == 0.0) {
+            0.0
+        } else {
+            amplitude
+        }
+    }
+
+    fn voice_audible(&self, channel: usize, noise: bool, envelope: bool) -> bool {
+        if self.muted[channel] || (envelope && self.muted[PsgVoice::Envelope as usize]) {
+            return false;
+        }
+        if !self.soloed.iter().any(|&soloed| soloed) {
+            return true;
+        }
+        self.soloed[channel]
+            || (noise && self.soloed[PsgVoice::Noise as usize])
+            || (envelope && self.soloed[PsgVoice::Envelope as usize])
+    }
+
+    /// Current level of every voice, in `PsgVoice` order, for the scope tap
+    fn voice_levels(&self) -> [f32; 5] {
+        [
+            self.channel_output(0, false),
+            self.channel_output(1, false),
+            self.channel_output(2, false),
+            self.current_sample_n,
+            self.current_value_e.clamp(0.0, ENVELOPE_MAX) / ENVELOPE_MAX,
+        ]
+    }
+
+    /// Writing the shape register restarts the first envelope segment; hold and alternate
+    /// only apply once it ends
+    fn restart_envelope(&mut self) {
+        self.current_value_e = if self.attack_e { 0.0 } else { ENVELOPE_MAX };
+        self.direction_e = if self.attack_e { 1 } else { -1 };
+        self.period_e_countdown = 0;
+        self.set_envelope_amplitudes();
     }
 
     fn cycle_envelope(&mut self, alternate: bool, hold: bool) {
         if alternate ^ hold {
             self.attack_e = !self.attack_e;
         }
-        self.current_value_e = if self.attack_e { 0.0 } else { 15.0 };
+        self.current_value_e = if self.attack_e { 0.0 } else { ENVELOPE_MAX };
         self.direction_e = if hold {
             0
         } else if self.attack_e {
@@ -588,28 +722,327 @@ impl AudioChannel {
         self.lfsr = (self.lfsr >> 1) | ((((self.lfsr >> 2) ^ (self.lfsr & 0x01)) & 0x01) << 16); // shift right, push to left
         self.lfsr & 0x01
     }
+}
 
-    fn create_volume_curve(&mut self) {
-        // Assuming CHANNEL_VOLUME_CURVE_POWER and CHANNEL_MAX_VOLUME are constants
-        let channel_volume_curve_power = 2.0; // Replace with the correct value
-        let channel_max_volume = 15; // Replace with the correct value
+/// Index into the 32 entry DAC table for a fixed 4-bit channel volume
+fn fixed_volume_index(volume: u8) -> usize {
+    ((volume & 0x0F) as usize) * 2 + 1
+}
+
+/// Which of the pin compatible PSGs a machine uses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PsgModel {
+    /// General Instrument AY-3-8910: 16 envelope steps
+    #[default]
+    Ay38910,
+    /// Yamaha YM2149: 32 envelope steps and a finer DAC
+    Ym2149,
+}
+
+impl PsgModel {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ay" | "ay8910" | "ay-3-8910" | "ay38910" => Some(PsgModel::Ay38910),
+            "ym" | "ym2149" => Some(PsgModel::Ym2149),
+            _ => None,
+        }
+    }
 
-        for v in 0..16 {
-            let value = (f32::powf(channel_volume_curve_power, v as f32 / 15.0) - 1.0)
-                / (channel_volume_curve_power - 1.0)
-                * (channel_max_volume as f32);
-            self.volume_curve.push(value);
+    /// Picks the PSG of a machine profile from the MSX version byte of its main BIOS, at
+    /// 0x002D.
+    ///
+    /// MSX2 and later machines have the PSG in their MSX-ENGINE, a YM2149 core; MSX1
+    /// machines are taken to have the AY-3-8910. `set_model` overrides the choice.
+    pub fn detect(bios: &[u8]) -> Self {
+        match bios.get(0x2D) {
+            Some(&version) if version >= 1 => PsgModel::Ym2149,
+            _ => PsgModel::Ay38910,
         }
     }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PsgModel::Ay38910 => "ay-3-8910",
+            PsgModel::Ym2149 => "ym2149",
+        }
+    }
+
+    /// DAC output levels scaled to the mixer's channel volume
+    pub fn volume_curve(&self) -> Vec<f32> {
+        let table = match self {
+            PsgModel::Ay38910 => &AY_DAC_TABLE,
+            PsgModel::Ym2149 => &YM_DAC_TABLE,
+        };
+        table
+            .iter()
+            .map(|level| level * CHANNEL_MAX_VOLUME)
+            .collect()
+    }
 }
 
-const CHANNEL_MAX_VOLUME: f32 = 0.28;
-const CHANNEL_VOLUME_CURVE_POWER: u8 = 30;
+// Normalised DAC levels measured on real chips (as used by the ayumi emulator). The AY
+// only has 16 levels, so its entries come in pairs and the 32 step envelope walks each
+// level twice.
+const AY_DAC_TABLE: [f32; 32] = [
+    0.0,
+    0.0,
+    0.009994659,
+    0.009994659,
+    0.014450294,
+    0.014450294,
+    0.02105745,
+    0.02105745,
+    0.030701152,
+    0.030701152,
+    0.04554818,
+    0.04554818,
+    0.064499885,
+    0.064499885,
+    0.10736248,
+    0.10736248,
+    0.12658885,
+    0.12658885,
+    0.2049897,
+    0.2049897,
+    0.29221027,
+    0.29221027,
+    0.37283894,
+    0.37283894,
+    0.4925307,
+    0.4925307,
+    0.63532466,
+    0.63532466,
+    0.8055848,
+    0.8055848,
+    1.0,
+    1.0,
+];
 
-const MIN_PULSE_ON_CLOCKS: u8 = 160;
+const YM_DAC_TABLE: [f32; 32] = [
+    0.0,
+    0.0,
+    0.004654002,
+    0.007721065,
+    0.010955978,
+    0.013962005,
+    0.01699855,
+    0.020019837,
+    0.024368658,
+    0.029694057,
+    0.035065234,
+    0.04039063,
+    0.04853895,
+    0.05833524,
+    0.06805524,
+    0.07777523,
+    0.09251545,
+    0.11108568,
+    0.12974746,
+    0.14848554,
+    0.17666896,
+    0.21155108,
+    0.24638743,
+    0.2811017,
+    0.33373007,
+    0.40042725,
+    0.46738383,
+    0.534432,
+    0.63517207,
+    0.7580072,
+    0.87992674,
+    1.0,
+];
+
+const CHANNEL_MAX_VOLUME: f32 = 0.28;
+const ENVELOPE_MAX: f32 = 31.0;
 
 const BASE_VOLUME: f32 = 0.66;
-const SAMPLE_RATE: u32 = 112005; // Main CPU clock / 32 = 112005 Hz
+pub const PSG_SAMPLE_DIVIDER: u32 = 32;
+pub const SAMPLE_RATE: u32 = CPU_CLOCK_HZ / PSG_SAMPLE_DIVIDER; // Main CPU clock / 32 = 111860 Hz
+
+/// "ABC stereo": channel A on the left, B in the centre and C on the right
+pub const ABC_STEREO_PANS: [f32; 3] = [-1.0, 0.0, 1.0];
 
-const VOL: &str = "F";
-const PAN: &str = "0";
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mixer::OUTPUT_GAIN;
+
+    fn tone_on_channel_a(psg: &mut AY38910) {
+        for (reg, value) in [(0, 0x40), (1, 0x00), (7, 0xFE), (8, 0x0F)] {
+            psg.write(0xA0, reg);
+            psg.write(0xA1, value);
+        }
+    }
+
+    fn average_frame(psg: &mut AY38910) -> [f32; 2] {
+        // A few tone periods, scaled to the output range
+        let mut sum = [0.0; 2];
+        for _ in 0..4000 {
+            let [left, right] = psg.next_frame();
+            sum[0] += left * OUTPUT_GAIN / 4000.0;
+            sum[1] += right * OUTPUT_GAIN / 4000.0;
+        }
+        sum
+    }
+
+    #[test]
+    fn test_abc_stereo_pans_channel_a_left() {
+        let mut psg = AY38910::new();
+        tone_on_channel_a(&mut psg);
+        let [left, right] = average_frame(&mut psg);
+        assert!((left - right).abs() < 1e-4);
+
+        let mut psg = AY38910::new();
+        psg.set_stereo(true);
+        tone_on_channel_a(&mut psg);
+        let [left, right] = average_frame(&mut psg);
+        assert!(left > right + 0.05, "left {} right {}", left, right);
+
+        assert!(psg.set_pan(3, 0.0).is_err());
+        psg.set_pan(0, 1.0).unwrap();
+        let [left, right] = average_frame(&mut psg);
+        assert!(right > left + 0.05, "left {} right {}", left, right);
+    }
+
+    #[test]
+    fn test_mute_solo_and_scope() {
+        let mut psg = AY38910::new();
+        psg.set_scope_enabled(true);
+        tone_on_channel_a(&mut psg);
+        let [playing, _] = average_frame(&mut psg);
+
+        psg.set_muted(PsgVoice::ToneA, true);
+        let [muted, _] = average_frame(&mut psg);
+        assert!(playing > muted + 0.05);
+        assert!(muted.abs() < 1e-3, "silence is {}", muted);
+
+        // Soloing another voice keeps A silent after unmuting it
+        psg.set_muted(PsgVoice::ToneA, false);
+        psg.set_soloed(PsgVoice::ToneB, true);
+        let [soloed, _] = average_frame(&mut psg);
+        assert!((soloed - muted).abs() < 1e-3);
+
+        // The scope still sees channel A while it is silenced
+        psg.end_frame();
+        let scope = psg.scope(PsgVoice::ToneA);
+        assert_eq!(scope.len(), 3 * 4000);
+        assert!(scope.iter().any(|&level| level > 0.0));
+        assert!(psg.scope(PsgVoice::ToneB).iter().all(|&level| level == 0.0));
+        assert_eq!(psg.scope_registers()[8], 0x0F);
+    }
+
+    fn envelope_levels(model: PsgModel) -> usize {
+        let mut psg = AY38910::new();
+        psg.set_model(model);
+        psg.set_scope_enabled(true);
+        // Channel A with tone and noise off follows the envelope: attack, then hold
+        for (reg, value) in [(7, 0xFF), (8, 0x10), (11, 0x04), (12, 0x00), (13, 0x0D)] {
+            psg.write(0xA0, reg);
+            psg.write(0xA1, value);
+        }
+        for _ in 0..200 {
+            psg.next_frame();
+        }
+        psg.end_frame();
+
+        let mut levels: Vec<f32> = psg.scope(PsgVoice::ToneA).to_vec();
+        levels.dedup();
+        assert_eq!(*levels.last().unwrap(), CHANNEL_MAX_VOLUME);
+        levels.len()
+    }
+
+    fn write_registers(psg: &mut AY38910, writes: &[(u8, u8)]) {
+        for &(reg, value) in writes {
+            psg.write(0xA0, reg);
+            psg.write(0xA1, value);
+        }
+    }
+
+    #[test]
+    fn test_envelope_with_period_0() {
+        // A continuing sawtooth written before any period runs at the fastest rate
+        let mut psg = AY38910::new();
+        write_registers(&mut psg, &[(8, 0x10), (13, 0x08)]);
+        for _ in 0..100 {
+            psg.next_frame();
+        }
+        write_registers(&mut psg, &[(11, 0x00), (12, 0x00), (13, 0x0E)]);
+        for _ in 0..100 {
+            psg.next_frame();
+        }
+    }
+
+    #[test]
+    fn test_envelope_with_period_0xffff() {
+        let mut psg = AY38910::new();
+        write_registers(&mut psg, &[(8, 0x10), (11, 0xFF), (12, 0xFF), (13, 0x08)]);
+        // Past the point where a 16-bit countdown stepping by 2 would overflow
+        for _ in 0..40_000 {
+            psg.next_frame();
+        }
+        assert_eq!(psg.channel.period_e_countdown, 80_000 - 0xFFFF);
+        assert_eq!(psg.channel.current_value_e, ENVELOPE_MAX - 1.0);
+    }
+
+    #[test]
+    fn test_detect_model_per_msx_version() {
+        let mut bios = vec![0; 0x8000];
+        assert_eq!(PsgModel::detect(&bios), PsgModel::Ay38910, "MSX1");
+        bios[0x2D] = 1;
+        assert_eq!(PsgModel::detect(&bios), PsgModel::Ym2149, "MSX2");
+        assert_eq!(PsgModel::detect(&[]), PsgModel::Ay38910);
+    }
+
+    #[test]
+    fn test_envelope_resolution_per_model() {
+        // Silence plus 15 levels on the AY, silence plus 30 distinct levels on the YM
+        assert_eq!(envelope_levels(PsgModel::Ay38910), 16);
+        assert_eq!(envelope_levels(PsgModel::Ym2149), 31);
+
+        let ay = PsgModel::Ay38910.volume_curve();
+        let ym = PsgModel::Ym2149.volume_curve();
+        assert_eq!(ay[fixed_volume_index(15)], ym[fixed_volume_index(15)]);
+        assert!(ay[fixed_volume_index(8)] > ym[fixed_volume_index(8)]);
+    }
+
+    #[test]
+    fn test_joystick_select_and_output_pins() {
+        let mut psg = AY38910::new();
+        psg.joystick_ports = [0xFF & !0x01, 0xFF & !0x10];
+        let mut read_r14 = |psg: &mut AY38910, r15: u8| {
+            psg.write(0xA0, 15);
+            psg.write(0xA1, r15);
+            psg.write(0xA0, 14);
+            psg.read(0xA1)
+        };
+
+        // Port 1: up held, triggers released and their outputs high
+        assert_eq!(read_r14(&mut psg, 0x8F), 0xFE);
+        assert!(!psg.kana_led());
+        // Port 2: trigger A held
+        assert_eq!(read_r14(&mut psg, 0xCF), 0xEF);
+        // A low pin 7 output on port 2 pulls trigger B down; port 1's outputs do not count
+        assert_eq!(read_r14(&mut psg, 0x44), 0xCF);
+        assert!(psg.kana_led());
+        psg.cassette_input = false;
+        assert_eq!(read_r14(&mut psg, 0x44), 0x4F, "a low tape signal in bit 7");
+
+        psg.write(0xA0, 15);
+        assert_eq!(psg.read(0xA1), 0x44, "register 15 reads back its latch");
+        assert_eq!(psg.output_pins(0), 0b000);
+        assert_eq!(psg.output_pins(1), 0b001);
+        psg.write(0xA1, 0x30);
+        assert_eq!(psg.output_pins(0), 0b100, "pin 8 of port 1");
+        assert_eq!(psg.output_pins(1), 0b100, "pin 8 of port 2");
+
+        // After a reset register 7 makes port B an input, so its pins float high
+        psg.reset();
+        psg.cassette_input = true;
+        assert!(!psg.kana_led());
+        assert_eq!(psg.output_pins(0), 0b111);
+        assert_eq!(psg.output_pins(1), 0b111);
+        psg.write(0xA0, 14);
+        assert_eq!(psg.read(0xA1), 0xFF, "triggers released");
+    }
+}
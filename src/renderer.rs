@@ -1,4 +1,46 @@
-use crate::{vdp::DisplayMode, TMS9918};
+use serde::{Deserialize, Serialize};
+
+use crate::{clock::SCANLINES_PER_FRAME, palette::Palette, vdp::DisplayMode, TMS9918};
+
+/// Active display area produced by the pattern/sprite renderers
+pub const ACTIVE_WIDTH: usize = 256;
+pub const ACTIVE_HEIGHT: usize = 192;
+
+/// Visible border around the active area on an NTSC TMS9918 (in pixels / raster lines)
+pub const BORDER_LEFT: usize = 13;
+pub const BORDER_RIGHT: usize = 15;
+pub const BORDER_TOP: usize = 27;
+pub const BORDER_BOTTOM: usize = 24;
+
+pub const OVERSCAN_WIDTH: usize = BORDER_LEFT + ACTIVE_WIDTH + BORDER_RIGHT;
+pub const OVERSCAN_HEIGHT: usize = BORDER_TOP + ACTIVE_HEIGHT + BORDER_BOTTOM;
+
+/// Which part of the raster ends up in composed output frames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputMode {
+    /// Only the 256x192 active display area
+    #[default]
+    Cropped,
+    /// Full visible raster, including the border drawn with each line's backdrop colour
+    Overscan,
+}
+
+impl OutputMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "cropped" | "active" => Some(OutputMode::Cropped),
+            "overscan" | "full" | "border" => Some(OutputMode::Overscan),
+            _ => None,
+        }
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        match self {
+            OutputMode::Cropped => (ACTIVE_WIDTH, ACTIVE_HEIGHT),
+            OutputMode::Overscan => (OVERSCAN_WIDTH, OVERSCAN_HEIGHT),
+        }
+    }
+}
 
 pub struct Renderer<'a> {
     vdp: &'a TMS9918,
@@ -15,7 +57,7 @@ impl<'a> Renderer<'a> {
         let (base, size) = self.vdp.name_table_base_and_size();
         let mut text = String::new();
         for i in 0..size {
-            let c = self.vdp.vram[base + i];
+            let c = self.vdp.read_vram(base + i);
             if c == 0 {
                 text.push(' ');
             } else {
@@ -25,6 +67,58 @@ impl<'a> Renderer<'a> {
         text
     }
 
+    /// Converts the colour indices in `screen_buffer` into RGBA8888 pixels using `palette`.
+    /// Transparent pixels (colour 0) show the backdrop colour from R7, as on real hardware.
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        self.compose_rgba(OutputMode::Cropped, palette)
+    }
+
+    /// Same as `compose`, with every colour index looked up in `palette`
+    pub fn compose_rgba(&self, mode: OutputMode, palette: &Palette) -> Vec<u8> {
+        let colors = palette.colors();
+        let frame = self.compose(mode);
+
+        let mut rgba = Vec::with_capacity(frame.len() * 4);
+        for &index in frame.iter() {
+            rgba.extend_from_slice(&colors[(index & 0x0F) as usize]);
+        }
+        rgba
+    }
+
+    /// Builds an output frame of colour indices from `screen_buffer` (call `draw` first).
+    ///
+    /// Transparent pixels are resolved to the backdrop colour latched for their raster line, and
+    /// in `OutputMode::Overscan` the border is filled the same way. The top border shows the
+    /// last lines of the previous frame, as it does on screen.
+    pub fn compose(&self, mode: OutputMode) -> Vec<u8> {
+        let (width, height) = mode.dimensions();
+        let (left, top) = match mode {
+            OutputMode::Cropped => (0, 0),
+            OutputMode::Overscan => (BORDER_LEFT, BORDER_TOP),
+        };
+
+        let mut frame = Vec::with_capacity(width * height);
+        for row in 0..height {
+            let line = if row < top {
+                SCANLINES_PER_FRAME as usize - top + row
+            } else {
+                row - top
+            };
+            let border = self.vdp.border_color_at(line);
+
+            if line >= ACTIVE_HEIGHT {
+                frame.resize(frame.len() + width, border);
+                continue;
+            }
+
+            frame.resize(frame.len() + left, border);
+            let pixels = &self.screen_buffer[line * ACTIVE_WIDTH..(line + 1) * ACTIVE_WIDTH];
+            frame.extend(pixels.iter().map(|&c| if c == 0 { border } else { c }));
+            frame.resize(frame.len() + width - left - ACTIVE_WIDTH, border);
+        }
+        frame
+    }
+
     pub fn draw(&mut self) {
         // TODO check for scroll delta
 
@@ -47,19 +141,26 @@ impl<'a> Renderer<'a> {
         let height = y1 - y0;
 
         for y in y0..height {
+            // a blanked line only shows the backdrop colour, sprites included
+            if self.vdp.is_line_blanked(y) {
+                let border = self.vdp.border_color_at(y);
+                self.screen_buffer[y * ACTIVE_WIDTH..(y + 1) * ACTIVE_WIDTH].fill(border);
+                continue;
+            }
+
             // renders this raster line
             match self.vdp.display_mode {
                 DisplayMode::Text1 => {
                     // screen 0
-                    self.render_text1(y as usize);
+                    self.render_text1(y);
                 }
                 DisplayMode::Graphic1 => {
                     // screen 1
-                    self.render_graphic1(y as usize);
+                    self.render_graphic1(y);
                 }
                 DisplayMode::Graphic2 => {
                     // screen 2
-                    self.render_graphic2(y as usize);
+                    self.render_graphic2(y);
                 }
                 // DisplayMode::Multicolor => { // screen 3
                 //     self.render_text2(y as usize, fg, bg);
@@ -69,10 +170,6 @@ impl<'a> Renderer<'a> {
         }
     }
 
-    // In render_text1, after the main character rendering loop for a line:
-    // let mut pixel_ptr has advanced by 240 at this point for the current line.
-    // Let's rename pixel_ptr inside the loop to avoid confusion, or track current_x.
-
     pub fn render_text1(&mut self, line: usize) {
         let r7 = self.vdp.registers[7];
         let fg_color = (r7 & 0xF0) >> 4; // Corrected foreground
@@ -84,7 +181,15 @@ impl<'a> Renderer<'a> {
         let pnt_base = (self.vdp.registers[2] as usize & 0x0F) * 0x0400;
         let name_start_for_row = (line / 8) * 40; // Starting character index in PNT for this row
 
-        let mut current_x_on_scanline = 0;
+        // The 240 text pixels are centered in the 256 pixel active area, leaving
+        // 8 pixels of border on each side
+        let line_start = line * 256;
+        for x in 0..TEXT1_SIDE_BORDER {
+            self.screen_buffer[line_start + x] = bg_and_border_color;
+            self.screen_buffer[line_start + 256 - TEXT1_SIDE_BORDER + x] = bg_and_border_color;
+        }
+
+        let mut current_x_on_scanline = TEXT1_SIDE_BORDER;
 
         // Render 40 characters (240 pixels)
         for char_column_idx in 0..40 {
@@ -92,7 +197,7 @@ impl<'a> Renderer<'a> {
             // to avoid off-by-one errors with `pixel_ptr` logic.
             let char_code_vram_addr = pnt_base + name_start_for_row + char_column_idx;
             // Add bounds check for VRAM access if necessary
-            let char_code = self.vdp.vram[char_code_vram_addr % self.vdp.vram.len()]; // Modulo for safety for now
+            let char_code = self.vdp.read_vram(char_code_vram_addr);
 
             let pattern_offset_in_cpt = (char_code as usize * 8) + l;
             // Add bounds check for CPT access if necessary
@@ -101,26 +206,15 @@ impl<'a> Renderer<'a> {
 
             for bit_idx in 0..6 {
                 // 6 pixels per character
-                let screen_buffer_idx = (line * 256) + current_x_on_scanline + bit_idx;
-                if screen_buffer_idx < self.screen_buffer.len() {
-                    self.screen_buffer[screen_buffer_idx] = if (pattern & (0x80 >> bit_idx)) != 0 {
-                        fg_color
-                    } else {
-                        bg_and_border_color // Text background
-                    };
-                }
+                let screen_buffer_idx = line_start + current_x_on_scanline + bit_idx;
+                self.screen_buffer[screen_buffer_idx] = if (pattern & (0x80 >> bit_idx)) != 0 {
+                    fg_color
+                } else {
+                    bg_and_border_color // Text background
+                };
             }
             current_x_on_scanline += 6;
         }
-
-        // current_x_on_scanline is now 240.
-        // Fill the remaining 16 pixels on the right with the border color.
-        for i in 0..16 {
-            let screen_buffer_idx = (line * 256) + current_x_on_scanline + i;
-            if screen_buffer_idx < self.screen_buffer.len() {
-                self.screen_buffer[screen_buffer_idx] = bg_and_border_color; // Border
-            }
-        }
     }
 
     pub fn render_graphic1(&mut self, line: usize) {
@@ -138,7 +232,7 @@ impl<'a> Renderer<'a> {
         let mut pixel_ptr = line * 256;
         for name in name_start..name_end {
             let screen_offset = pnt_base + name;
-            let char_code = self.vdp.vram[screen_offset];
+            let char_code = self.vdp.read_vram(screen_offset);
             let color = color_table[char_code as usize / 8];
             let pattern = caracter_pattern_area[l + char_code as usize * 8];
             let fg = color >> 4;
@@ -180,7 +274,7 @@ impl<'a> Renderer<'a> {
 
         for x in 0..32 {
             let name_index = name_offset + x;
-            let char_code = self.vdp.vram[name_table_base + name_index] as usize;
+            let char_code = self.vdp.read_vram(name_table_base + name_index) as usize;
 
             // In Screen 2, pattern/color tables are organized differently:
             // Each bank (third of screen) can use different pattern definitions for the same character
@@ -223,3 +317,6 @@ impl<'a> Renderer<'a> {
         }
     }
 }
+
+/// Width of the extra border on each side of the 240 pixel wide Text1 mode
+const TEXT1_SIDE_BORDER: usize = 8;
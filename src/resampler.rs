@@ -0,0 +1,183 @@
+use std::{collections::VecDeque, f64::consts::PI};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+/// Zero crossings on each side of the windowed sinc kernel
+const KERNEL_HALF_WIDTH: usize = 12;
+/// Kernel table entries per zero crossing; values in between are interpolated
+const KERNEL_RESOLUTION: usize = 256;
+/// Fraction of the output Nyquist frequency kept by the low-pass filter
+const PASSBAND: f64 = 0.92;
+
+/// Blackman windowed sinc, sampled from 0 to `KERNEL_HALF_WIDTH`
+static KERNEL: Lazy<Vec<f32>> = Lazy::new(|| {
+    let size = KERNEL_HALF_WIDTH * KERNEL_RESOLUTION;
+    (0..=size)
+        .map(|i| {
+            let x = i as f64 / KERNEL_RESOLUTION as f64;
+            let sinc = if i == 0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            let n = 0.5 + x / (2.0 * KERNEL_HALF_WIDTH as f64);
+            let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+            (sinc * window) as f32
+        })
+        .collect()
+});
+
+fn kernel(x: f64) -> f64 {
+    let position = x.abs() * KERNEL_RESOLUTION as f64;
+    let index = position as usize;
+    if index >= KERNEL_HALF_WIDTH * KERNEL_RESOLUTION {
+        return 0.0;
+    }
+    let fraction = position - index as f64;
+    let a = KERNEL[index] as f64;
+    let b = KERNEL[index + 1] as f64;
+    a + (b - a) * fraction
+}
+
+/// Band-limited sample rate converter using a windowed sinc kernel.
+///
+/// Stereo frames are pushed at the chip's native rate and pulled at the host
+/// rate. When downsampling, the kernel is stretched so that it low-passes
+/// below the output Nyquist frequency, which removes the aliasing of a plain
+/// decimator.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Resampler {
+    input_rate: f64,
+    output_rate: f64,
+    step: f64,   // input samples per output sample
+    cutoff: f64, // relative to the input Nyquist frequency
+    reach: usize,
+    position: f64, // position of the next output sample, as an index into `history`
+    history: VecDeque<[f32; 2]>,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        let mut resampler = Self {
+            input_rate: input_rate as f64,
+            output_rate: output_rate as f64,
+            step: 1.0,
+            cutoff: 1.0,
+            reach: KERNEL_HALF_WIDTH,
+            position: 0.0,
+            history: VecDeque::new(),
+        };
+        resampler.set_output_rate(output_rate);
+        resampler
+    }
+
+    pub fn input_rate(&self) -> u32 {
+        self.input_rate as u32
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate as u32
+    }
+
+    /// Changes the host rate; pending input is discarded
+    pub fn set_output_rate(&mut self, output_rate: u32) {
+        self.output_rate = output_rate.max(1) as f64;
+        self.step = self.input_rate / self.output_rate;
+        self.cutoff = (1.0 / self.step).min(1.0) * PASSBAND;
+        self.reach = (KERNEL_HALF_WIDTH as f64 / self.cutoff).ceil() as usize;
+        self.reset();
+    }
+
+    /// Scales the conversion step by `ratio` without disturbing the signal, for small
+    /// rate corrections; above 1 fewer frames come out
+    pub fn set_ratio(&mut self, ratio: f64) {
+        self.step = self.input_rate / self.output_rate * ratio;
+    }
+
+    pub fn reset(&mut self) {
+        // Start with a window of silence so the first output sample has full history
+        self.history = VecDeque::from(vec![[0.0; 2]; self.reach]);
+        self.position = self.reach as f64;
+    }
+
+    pub fn push(&mut self, frame: [f32; 2]) {
+        self.history.push_back(frame);
+    }
+
+    /// Next output frame, once enough input has been pushed to compute it
+    pub fn next_frame(&mut self) -> Option<[f32; 2]> {
+        let center = self.position as usize;
+        if center + self.reach >= self.history.len() {
+            return None;
+        }
+
+        let first = (center + 1).saturating_sub(self.reach);
+        let mut sum = [0.0; 2];
+        let mut weights = 0.0;
+        for i in first..=center + self.reach {
+            let weight = kernel((self.position - i as f64) * self.cutoff);
+            let [left, right] = self.history[i];
+            sum[0] += left as f64 * weight;
+            sum[1] += right as f64 * weight;
+            weights += weight;
+        }
+
+        self.position += self.step;
+        while self.position >= (self.reach + 1) as f64 {
+            self.history.pop_front();
+            self.position -= 1.0;
+        }
+
+        // Normalising by the weight sum keeps DC exact across all kernel phases
+        Some([(sum[0] / weights) as f32, (sum[1] / weights) as f32])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(resampler: &mut Resampler, input: impl Iterator<Item = f32>) -> Vec<f32> {
+        let mut output = Vec::new();
+        for sample in input {
+            resampler.push([sample, -sample]);
+            while let Some([left, right]) = resampler.next_frame() {
+                assert_eq!(left, -right);
+                output.push(left);
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn test_rate_and_dc() {
+        let mut resampler = Resampler::new(111860, 44100);
+        let output = run(&mut resampler, std::iter::repeat(0.5).take(111860));
+        let expected = 44100.0 - (resampler.reach as f64 / resampler.step);
+        assert!((output.len() as f64 - expected).abs() < 2.0);
+        assert!(output[1000..].iter().all(|s| (s - 0.5).abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_removes_content_above_nyquist() {
+        let rate = 111860.0;
+        // 40kHz is above the 22.05kHz output Nyquist and must not alias down
+        let tone = (0..20000).map(|i| (2.0 * PI * 40000.0 * i as f64 / rate).sin() as f32);
+        let mut resampler = Resampler::new(111860, 44100);
+        let output = run(&mut resampler, tone);
+        let peak = output[200..]
+            .iter()
+            .fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak < 0.01, "aliased peak {}", peak);
+
+        // 1kHz passes through untouched
+        let tone = (0..20000).map(|i| (2.0 * PI * 1000.0 * i as f64 / rate).sin() as f32);
+        let mut resampler = Resampler::new(111860, 44100);
+        let output = run(&mut resampler, tone);
+        let peak = output[200..]
+            .iter()
+            .fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((peak - 1.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+
+/// Fixed size FIFO of stereo audio frames between the emulation and the host audio callback.
+///
+/// Pushing and popping are O(1) and never allocate. When the producer runs ahead the
+/// oldest frames are dropped (an overrun), and popping from an empty buffer repeats the
+/// last frame (an underrun); both are counted so frontends can report them.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SampleRing {
+    buffer: Vec<[f32; 2]>,
+    read: usize,
+    len: usize,
+    last: [f32; 2],
+    pub underruns: u64,
+    pub overruns: u64,
+}
+
+impl SampleRing {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buffer: vec![[0.0; 2]; capacity.max(1)],
+            read: 0,
+            len: 0,
+            last: [0.0; 2],
+            underruns: 0,
+            overruns: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, frame: [f32; 2]) {
+        let capacity = self.buffer.len();
+        if self.len == capacity {
+            // Drop the oldest frame to keep latency bounded
+            self.read = (self.read + 1) % capacity;
+            self.len -= 1;
+            self.overruns += 1;
+        }
+        let write = (self.read + self.len) % capacity;
+        self.buffer[write] = frame;
+        self.len += 1;
+    }
+
+    /// Next frame, or the last one played again when the buffer ran dry
+    pub fn pop(&mut self) -> [f32; 2] {
+        if self.len == 0 {
+            self.underruns += 1;
+            return self.last;
+        }
+        let frame = self.buffer[self.read];
+        self.read = (self.read + 1) % self.buffer.len();
+        self.len -= 1;
+        self.last = frame;
+        frame
+    }
+
+    /// Drops the `count` oldest frames, counting them as overruns
+    pub fn skip(&mut self, count: usize) {
+        let count = count.min(self.len);
+        self.read = (self.read + count) % self.buffer.len();
+        self.len -= count;
+        self.overruns += count as u64;
+    }
+
+    pub fn clear(&mut self) {
+        self.read = 0;
+        self.len = 0;
+        self.last = [0.0; 2];
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.underruns = 0;
+        self.overruns = 0;
+    }
+}
+
+impl Default for SampleRing {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// About 340ms of audio at 48kHz
+pub const DEFAULT_CAPACITY: usize = 16384;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrun_and_underrun() {
+        let mut ring = SampleRing::new(4);
+        for i in 0..6 {
+            ring.push([i as f32, -(i as f32)]);
+        }
+        assert_eq!(ring.len(), 4);
+        assert_eq!(ring.overruns, 2);
+
+        let popped: Vec<f32> = (0..4).map(|_| ring.pop()[0]).collect();
+        assert_eq!(popped, vec![2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(ring.underruns, 0);
+
+        // Holds the last value instead of dropping to zero, which would click
+        assert_eq!(ring.pop(), [5.0, -5.0]);
+        assert_eq!(ring.underruns, 1);
+    }
+}
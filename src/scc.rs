@@ -0,0 +1,243 @@
+use serde::{Deserialize, Serialize};
+
+use crate::psg::PSG_SAMPLE_DIVIDER;
+
+/// Konami SCC wavetable sound chip, as found in the SCC cartridges.
+///
+/// Five channels play a 32 byte signed waveform each, at a 12 bit period and a 4 bit
+/// volume. The plain SCC has only four waveforms, so channels 4 and 5 share one. The
+/// SCC-I (SCC+) has a separate waveform for every channel and a register to switch
+/// between the compatible and the extended register layout.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Scc {
+    chip: SccChip,
+    waves: [[i8; 32]; 5],
+    periods: [u16; 5],
+    volumes: [u8; 5],
+    enabled: u8,
+    deformation: u8,
+    // Set by the SCC-I mode register, selects the register layout with five waveforms
+    plus_mode: bool,
+    counters: [u32; 5],
+    positions: [usize; 5],
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SccChip {
+    #[default]
+    Scc,
+    SccI,
+}
+
+impl SccChip {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "scc" => Some(SccChip::Scc),
+            "scc-i" | "scci" | "scc+" | "sccplus" => Some(SccChip::SccI),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SccChip::Scc => "scc",
+            SccChip::SccI => "scc-i",
+        }
+    }
+}
+
+impl Scc {
+    pub fn new(chip: SccChip) -> Self {
+        Self {
+            chip,
+            waves: [[0; 32]; 5],
+            periods: [0; 5],
+            volumes: [0; 5],
+            enabled: 0,
+            deformation: 0,
+            plus_mode: false,
+            counters: [0; 5],
+            positions: [0; 5],
+        }
+    }
+
+    pub fn chip(&self) -> SccChip {
+        self.chip
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.chip);
+    }
+
+    pub fn waves(&self) -> &[[i8; 32]; 5] {
+        &self.waves
+    }
+
+    pub fn periods(&self) -> [u16; 5] {
+        self.periods
+    }
+
+    pub fn volumes(&self) -> [u8; 5] {
+        self.volumes
+    }
+
+    /// Channel enable bits, channel 1 in bit 0
+    pub fn enabled(&self) -> u8 {
+        self.enabled
+    }
+
+    pub fn deformation(&self) -> u8 {
+        self.deformation
+    }
+
+    /// Whether the SCC-I mode register selected the extended register layout
+    pub fn plus_mode(&self) -> bool {
+        self.plus_mode
+    }
+
+    /// Writes the SCC-I mode register (0xBFFE-0xBFFF); bit 5 selects SCC+ mode
+    pub fn write_mode(&mut self, value: u8) {
+        if self.chip == SccChip::SccI {
+            self.plus_mode = value & 0x20 != 0;
+        }
+    }
+
+    /// Reads a register in the compatible layout, `offset` being relative to 0x9800
+    pub fn read(&self, offset: u8) -> u8 {
+        match offset {
+            0x00..=0x7F => self.waves[offset as usize >> 5][offset as usize & 0x1F] as u8,
+            // The fifth channel's waveform can be read back here
+            0xA0..=0xBF => self.waves[4][offset as usize & 0x1F] as u8,
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes a register in the compatible layout, `offset` being relative to 0x9800
+    pub fn write(&mut self, offset: u8, value: u8) {
+        match offset {
+            0x00..=0x7F => {
+                let channel = offset as usize >> 5;
+                self.waves[channel][offset as usize & 0x1F] = value as i8;
+                if channel == 3 && !self.plus_mode {
+                    self.waves[4][offset as usize & 0x1F] = value as i8;
+                }
+            }
+            0x80..=0x9F => self.write_control(offset & 0x0F, value),
+            0xE0..=0xFF => self.deformation = value,
+            _ => {}
+        }
+    }
+
+    /// Reads a register in the SCC+ layout, `offset` being relative to 0xB800
+    pub fn read_plus(&self, offset: u8) -> u8 {
+        match offset {
+            0x00..=0x9F => self.waves[offset as usize >> 5][offset as usize & 0x1F] as u8,
+            _ => 0xFF,
+        }
+    }
+
+    /// Writes a register in the SCC+ layout, `offset` being relative to 0xB800
+    pub fn write_plus(&mut self, offset: u8, value: u8) {
+        match offset {
+            0x00..=0x9F => self.waves[offset as usize >> 5][offset as usize & 0x1F] = value as i8,
+            0xA0..=0xBF => self.write_control(offset & 0x0F, value),
+            0xC0..=0xDF => self.deformation = value,
+            _ => {}
+        }
+    }
+
+    fn write_control(&mut self, register: u8, value: u8) {
+        match register {
+            0x0..=0x9 => {
+                let channel = register as usize / 2;
+                self.periods[channel] = if register & 1 == 0 {
+                    (self.periods[channel] & 0xF00) | value as u16
+                } else {
+                    (self.periods[channel] & 0x0FF) | ((value as u16 & 0x0F) << 8)
+                };
+                if self.deformation & 0x20 != 0 {
+                    // Restart the waveform on frequency changes
+                    self.counters[channel] = 0;
+                    self.positions[channel] = 0;
+                }
+            }
+            0xA..=0xE => self.volumes[register as usize - 0xA] = value & 0x0F,
+            _ => self.enabled = value & 0x1F,
+        }
+    }
+
+    /// Advances the chip by one PSG sample period and returns its mono output level.
+    ///
+    /// Levels are unsigned, in the same units as the PSG channel levels; the mixer removes
+    /// the offset.
+    pub fn next_sample(&mut self) -> f32 {
+        let mut output = 0.0;
+        for channel in 0..5 {
+            // Each waveform step takes period + 1 clocks of the 3.58MHz chip clock
+            let step = self.periods[channel] as u32 + 1;
+            self.counters[channel] += PSG_SAMPLE_DIVIDER;
+            self.positions[channel] =
+                (self.positions[channel] + (self.counters[channel] / step) as usize) & 0x1F;
+            self.counters[channel] %= step;
+
+            if self.enabled & (1 << channel) == 0 || self.volumes[channel] == 0 {
+                continue;
+            }
+            let sample = self.waves[channel][self.positions[channel]] as f32 + 128.0;
+            output += sample / 255.0 * self.volumes[channel] as f32 / 15.0;
+        }
+        output * CHANNEL_MAX_VOLUME
+    }
+}
+
+impl Default for Scc {
+    fn default() -> Self {
+        Self::new(SccChip::Scc)
+    }
+}
+
+/// Five full volume channels stay below the PSG's headroom
+const CHANNEL_MAX_VOLUME: f32 = 0.12;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_fifth_wave_and_output() {
+        let mut scc = Scc::new(SccChip::Scc);
+        scc.write(0x60, 0x7F);
+        assert_eq!(scc.read(0xA0), 0x7F, "channel 5 shares channel 4's wave");
+
+        // Square wave on channel 1, a 32 sample period of 64 clocks (two per step)
+        for i in 0..32 {
+            scc.write(i, if i < 16 { 0x7F } else { 0x80 });
+        }
+        scc.write(0x80, 1);
+        scc.write(0x81, 0);
+        scc.write(0x8A, 0x0F);
+        assert_eq!(scc.next_sample(), 0.0, "channels start disabled");
+
+        scc.write(0x8F, 0x01);
+        let samples: Vec<f32> = (0..32).map(|_| scc.next_sample()).collect();
+        let high = samples.iter().cloned().fold(0.0, f32::max);
+        let low = samples.iter().cloned().fold(1.0, f32::min);
+        assert!((high - CHANNEL_MAX_VOLUME).abs() < 1e-6);
+        assert!(low < 1e-6);
+    }
+
+    #[test]
+    fn test_scc_plus_layout() {
+        let mut scc = Scc::new(SccChip::SccI);
+        scc.write_mode(0x20);
+        assert!(scc.plus_mode());
+        scc.write_plus(0x60, 0x11);
+        scc.write_plus(0x80, 0x22);
+        assert_eq!(scc.read_plus(0x60), 0x11);
+        assert_eq!(scc.read_plus(0x80), 0x22, "channel 5 has its own wave");
+
+        let mut scc = Scc::new(SccChip::Scc);
+        scc.write_mode(0x20);
+        assert!(!scc.plus_mode(), "only the SCC-I has the mode register");
+    }
+}
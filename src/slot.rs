@@ -7,11 +7,19 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    opll::Ym2413,
+    scc::{Scc, SccChip},
+    vgm::VgmWrite,
+};
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub enum SlotType {
     Empty,
     Ram(RamSlot),
     Rom(RomSlot),
+    Scc(SccSlot),
+    MsxMusic(MsxMusicSlot),
 }
 
 impl fmt::Display for SlotType {
@@ -24,6 +32,13 @@ impl fmt::Display for SlotType {
                 "ROM path={:?} base={:#06X} size={:#06X}",
                 slot.rom_path, slot.base, slot.size
             ),
+            SlotType::Scc(slot) => write!(
+                f,
+                "Konami SCC ROM chip={} size={:#06X}",
+                slot.scc.chip().name(),
+                slot.data.len()
+            ),
+            SlotType::MsxMusic(slot) => write!(f, "MSX-MUSIC ROM size={:#06X}", slot.data.len()),
         }
     }
 }
@@ -34,6 +49,8 @@ impl SlotType {
             SlotType::Empty => 0xFF,
             SlotType::Ram(slot) => slot.read(address),
             SlotType::Rom(slot) => slot.read(address),
+            SlotType::Scc(slot) => slot.read(address),
+            SlotType::MsxMusic(slot) => slot.read(address),
         }
     }
 
@@ -42,6 +59,17 @@ impl SlotType {
             SlotType::Empty => {}
             SlotType::Ram(slot) => slot.write(address, value),
             SlotType::Rom(slot) => slot.write(address, value),
+            SlotType::Scc(slot) => slot.write(address, value),
+            SlotType::MsxMusic(slot) => slot.write(address, value),
+        }
+    }
+
+    /// The sound chip register write that writing `value` to `address` would make, if any
+    pub fn sound_write(&self, address: u16, value: u8) -> Option<VgmWrite> {
+        match self {
+            SlotType::Scc(slot) => slot.sound_write(address, value),
+            SlotType::MsxMusic(slot) => slot.sound_write(address, value),
+            _ => None,
         }
     }
 
@@ -50,6 +78,8 @@ impl SlotType {
             SlotType::Empty => 0,
             SlotType::Ram(slot) => slot.size,
             SlotType::Rom(slot) => slot.size,
+            SlotType::Scc(slot) => slot.data.len() as u32,
+            SlotType::MsxMusic(slot) => slot.data.len() as u32,
         }
     }
 }
@@ -174,3 +204,217 @@ impl Slot for RamSlot {
         self.data[address as usize] = value;
     }
 }
+
+/// Konami SCC mapper cartridge: 8KB ROM banks at 0x4000, 0x6000, 0x8000 and 0xA000
+/// with the SCC sound chip.
+///
+/// Banks are selected by writing to 0x5000-0x57FF, 0x7000-0x77FF, 0x9000-0x97FF and
+/// 0xB000-0xB7FF. Selecting bank 0x3F at 0x8000 maps the SCC registers at 0x9800-0x9FFF.
+/// On an SCC-I in SCC+ mode, setting bit 7 of the 0xA000 bank maps the SCC+ registers at
+/// 0xB800-0xBFFF instead.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct SccSlot {
+    pub data: Vec<u8>,
+    pub banks: [u8; 4],
+    pub scc: Box<Scc>,
+}
+
+impl SccSlot {
+    pub fn new(rom: &[u8], chip: SccChip) -> Self {
+        // Pad to a whole number of banks so bank numbers can wrap around the ROM
+        let bank_count = rom.len().div_ceil(SCC_BANK_SIZE).max(1);
+        let mut data = vec![0xFF; bank_count * SCC_BANK_SIZE];
+        data[..rom.len()].copy_from_slice(rom);
+
+        SccSlot {
+            data,
+            banks: [0, 1, 2, 3],
+            scc: Box::new(Scc::new(chip)),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.banks = [0, 1, 2, 3];
+        self.scc.reset();
+    }
+
+    fn scc_registers_mapped(&self) -> bool {
+        !self.scc.plus_mode() && self.banks[2] & 0x3F == 0x3F
+    }
+
+    fn scc_plus_registers_mapped(&self) -> bool {
+        self.scc.plus_mode() && self.banks[3] & 0x80 != 0
+    }
+
+    pub fn sound_write(&self, address: u16, value: u8) -> Option<VgmWrite> {
+        let plus_layout = match address {
+            0x9800..=0x9FFF if self.scc_registers_mapped() => false,
+            0xB800..=0xBFFD if self.scc_plus_registers_mapped() => true,
+            _ => return None,
+        };
+        Some(VgmWrite::Scc {
+            chip: self.scc.chip(),
+            plus_layout,
+            offset: address as u8,
+            value,
+        })
+    }
+}
+
+impl Slot for SccSlot {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x9800..=0x9FFF if self.scc_registers_mapped() => self.scc.read(address as u8),
+            0xB800..=0xBFFD if self.scc_plus_registers_mapped() => {
+                self.scc.read_plus(address as u8)
+            }
+            0x4000..=0xBFFF => {
+                let page = (address as usize - 0x4000) / SCC_BANK_SIZE;
+                let bank_count = self.data.len() / SCC_BANK_SIZE;
+                let bank = self.banks[page] as usize % bank_count;
+                self.data[bank * SCC_BANK_SIZE + (address as usize % SCC_BANK_SIZE)]
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x57FF => self.banks[0] = value,
+            0x7000..=0x77FF => self.banks[1] = value,
+            0x9000..=0x97FF => self.banks[2] = value,
+            0xB000..=0xB7FF => self.banks[3] = value,
+            0x9800..=0x9FFF if self.scc_registers_mapped() => self.scc.write(address as u8, value),
+            0xBFFE..=0xBFFF => self.scc.write_mode(value),
+            0xB800..=0xBFFD if self.scc_plus_registers_mapped() => {
+                self.scc.write_plus(address as u8, value)
+            }
+            _ => tracing::trace!("Attempt to write to SCC ROM address {:#06X}", address),
+        }
+    }
+}
+
+const SCC_BANK_SIZE: usize = 0x2000;
+
+/// MSX-MUSIC cartridge: the YM2413 with its BASIC extension ROM at 0x4000.
+///
+/// A 16KB ROM is a plain MSX-MUSIC with the I/O ports always enabled. Larger ROMs behave
+/// like the Panasonic FM-PAC: 16KB banks selected at 0x7FF7, the I/O ports enabled by
+/// bit 0 of 0x7FF6, the chip also written through 0x7FF4-0x7FF5, and 8KB of SRAM that
+/// replaces the ROM at 0x4000 once 0x4D, 0x69 is written to 0x5FFE-0x5FFF.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct MsxMusicSlot {
+    pub data: Vec<u8>,
+    pub bank: u8,
+    pub control: u8,
+    pub sram: Vec<u8>,
+    sram_key: [u8; 2],
+    pub opll: Box<Ym2413>,
+}
+
+impl MsxMusicSlot {
+    pub fn new(rom: &[u8]) -> Self {
+        let bank_count = rom.len().div_ceil(MSX_MUSIC_BANK_SIZE).max(1);
+        let mut data = vec![0xFF; bank_count * MSX_MUSIC_BANK_SIZE];
+        data[..rom.len()].copy_from_slice(rom);
+
+        MsxMusicSlot {
+            data,
+            bank: 0,
+            control: 0,
+            sram: vec![0; 0x2000],
+            sram_key: [0; 2],
+            opll: Box::new(Ym2413::new()),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.bank = 0;
+        self.control = 0;
+        self.sram_key = [0; 2];
+        self.opll.reset();
+    }
+
+    fn is_fm_pac(&self) -> bool {
+        self.data.len() > MSX_MUSIC_BANK_SIZE
+    }
+
+    /// Whether the YM2413 answers on I/O ports 0x7C-0x7D
+    pub fn io_enabled(&self) -> bool {
+        !self.is_fm_pac() || self.control & 0x01 != 0
+    }
+
+    fn sram_enabled(&self) -> bool {
+        self.is_fm_pac() && self.sram_key == [0x4D, 0x69]
+    }
+
+    /// Writes to the memory mapped YM2413 data register of the FM-PAC
+    pub fn sound_write(&self, address: u16, value: u8) -> Option<VgmWrite> {
+        (self.is_fm_pac() && address == 0x7FF5).then(|| VgmWrite::Opll {
+            register: self.opll.address(),
+            value,
+        })
+    }
+}
+
+impl Slot for MsxMusicSlot {
+    fn read(&self, address: u16) -> u8 {
+        match address {
+            0x5FFE..=0x5FFF if self.is_fm_pac() => self.sram_key[address as usize - 0x5FFE],
+            0x4000..=0x5FFD if self.sram_enabled() => self.sram[address as usize - 0x4000],
+            0x7FF6 if self.is_fm_pac() => self.control,
+            0x7FF7 if self.is_fm_pac() => self.bank,
+            0x4000..=0x7FFF => {
+                let bank_count = self.data.len() / MSX_MUSIC_BANK_SIZE;
+                let bank = self.bank as usize % bank_count;
+                self.data[bank * MSX_MUSIC_BANK_SIZE + (address as usize - 0x4000)]
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if !self.is_fm_pac() {
+            tracing::trace!("Attempt to write to MSX-MUSIC ROM address {:#06X}", address);
+            return;
+        }
+        match address {
+            0x5FFE..=0x5FFF => self.sram_key[address as usize - 0x5FFE] = value,
+            0x4000..=0x5FFD if self.sram_enabled() => self.sram[address as usize - 0x4000] = value,
+            0x7FF4 => self.opll.write_address(value),
+            0x7FF5 => self.opll.write_data(value),
+            0x7FF6 => self.control = value & 0x11,
+            0x7FF7 => self.bank = value & 0x03,
+            _ => tracing::trace!("Attempt to write to FM-PAC ROM address {:#06X}", address),
+        }
+    }
+}
+
+const MSX_MUSIC_BANK_SIZE: usize = 0x4000;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scc_bank_switching_and_registers() {
+        let rom: Vec<u8> = (0..0x40)
+            .flat_map(|bank| vec![bank as u8; 0x2000])
+            .collect();
+        let mut slot = SccSlot::new(&rom, SccChip::Scc);
+        assert_eq!(slot.read(0x4000), 0);
+        assert_eq!(slot.read(0xBFFF), 3);
+
+        slot.write(0x7000, 0x12);
+        assert_eq!(slot.read(0x6000), 0x12);
+
+        // Waveform RAM is only visible once bank 0x3F is selected at 0x8000
+        slot.write(0x9800, 0x55);
+        assert_eq!(slot.read(0x9800), 2);
+        slot.write(0x9000, 0x3F);
+        slot.write(0x9800, 0x55);
+        assert_eq!(slot.read(0x9800), 0x55);
+        assert_eq!(slot.read(0x9900), 0x55, "registers mirror every 256 bytes");
+        assert_eq!(slot.read(0x8000), 0x3F);
+    }
+}
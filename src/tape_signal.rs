@@ -0,0 +1,262 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cassette::{Cassette, TapeBlockKind, CAS_HEADER},
+    clock::CPU_CLOCK_HZ,
+    wav::read_wav,
+};
+
+/// A tape played into the cassette input, for loaders that read the signal themselves.
+///
+/// The signal is kept as the level the input comparator sees at each sample, either
+/// from a recording or synthesized from a CAS image with the 1200 baud FSK encoding of
+/// the BIOS. It moves only while the motor relay is closed, measured in CPU cycles.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct TapeSignal {
+    levels: Vec<bool>,
+    sample_rate: u32,
+    // CPU cycles of tape played
+    played: u64,
+    motor: bool,
+    // CPU cycle the position was last brought up to date
+    last_cycle: u64,
+}
+
+impl TapeSignal {
+    fn new(levels: Vec<bool>, sample_rate: u32) -> Self {
+        Self {
+            levels,
+            sample_rate,
+            played: 0,
+            motor: false,
+            last_cycle: 0,
+        }
+    }
+
+    /// Squares up a recording: a DC blocker takes out any offset, then a comparator
+    /// with some hysteresis ignores noise around zero
+    pub fn from_wav(data: &[u8]) -> Result<Self, String> {
+        let (sample_rate, samples) = read_wav(data)?;
+        if sample_rate == 0 {
+            return Err("WAV file with a sample rate of 0".to_string());
+        }
+        let pole = 1.0 - DC_BLOCK_HZ * std::f32::consts::TAU / sample_rate as f32;
+        let (mut dc_input, mut dc_output, mut level) = (0.0, 0.0, false);
+        let levels = samples
+            .iter()
+            .map(|&sample| {
+                dc_output = sample - dc_input + pole * dc_output;
+                dc_input = sample;
+                if dc_output > HYSTERESIS {
+                    level = true;
+                } else if dc_output < -HYSTERESIS {
+                    level = false;
+                }
+                level
+            })
+            .collect();
+        Ok(Self::new(levels, sample_rate))
+    }
+
+    /// Encodes a CAS image the way the BIOS writes a tape at 1200 baud: silence and a
+    /// long sync tone before a file header, shorter ones before the blocks after it
+    pub fn from_cas(data: &[u8]) -> Result<Self, String> {
+        let mut cassette = Cassette::new();
+        cassette.insert(data.to_vec())?;
+        let blocks = cassette.blocks();
+
+        let mut encoder = FskEncoder::default();
+        for (index, block) in blocks.iter().enumerate() {
+            let start = block.offset + CAS_HEADER.len();
+            let end = blocks.get(index + 1).map_or(data.len(), |next| next.offset);
+            if block.kind == TapeBlockKind::Data {
+                encoder.silence(SHORT_SILENCE);
+                encoder.sync(SHORT_SYNC);
+            } else {
+                encoder.silence(LONG_SILENCE);
+                encoder.sync(LONG_SYNC);
+            }
+            for &byte in &data[start..end] {
+                encoder.byte(byte);
+            }
+        }
+        encoder.silence(SHORT_SILENCE);
+        Ok(Self::new(encoder.levels, FSK_SAMPLE_RATE))
+    }
+
+    /// CPU cycles of tape played by CPU cycle `cycle`
+    fn played_at(&self, cycle: u64) -> u64 {
+        match self.motor {
+            true => self.played + cycle.saturating_sub(self.last_cycle),
+            false => self.played,
+        }
+    }
+
+    fn advance(&mut self, cycle: u64) {
+        self.played = self.played_at(cycle);
+        self.last_cycle = cycle;
+    }
+
+    /// Follows the motor relay, PPI port C bit 4, as it changes at CPU cycle `cycle`
+    pub fn set_motor(&mut self, on: bool, cycle: u64) {
+        self.advance(cycle);
+        self.motor = on;
+    }
+
+    fn sample(&self, cycle: u64) -> usize {
+        let played = self.played_at(cycle) as u128;
+        (played * self.sample_rate as u128 / CPU_CLOCK_HZ as u128) as usize
+    }
+
+    /// The cassette input at CPU cycle `cycle`; low past the end of the tape
+    pub fn level(&mut self, cycle: u64) -> bool {
+        self.advance(cycle);
+        self.levels
+            .get(self.sample(cycle))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Whether the tape is moving and has signal left at CPU cycle `cycle`
+    pub fn playing(&self, cycle: u64) -> bool {
+        self.motor && self.sample(cycle) < self.levels.len()
+    }
+
+    pub fn rewind(&mut self) {
+        self.played = 0;
+    }
+
+    /// Position at CPU cycle `cycle` and length, in seconds
+    pub fn position_secs(&self, cycle: u64) -> f64 {
+        self.played_at(cycle) as f64 / CPU_CLOCK_HZ as f64
+    }
+
+    pub fn length_secs(&self) -> f64 {
+        self.levels.len() as f64 / self.sample_rate as f64
+    }
+}
+
+/// Builds a 1200 baud tape signal: a 0 bit is one cycle at 1200Hz, a 1 bit two cycles
+/// at 2400Hz, and a byte is a 0 start bit, eight data bits from the lowest and two
+/// 1 stop bits
+#[derive(Default)]
+struct FskEncoder {
+    levels: Vec<bool>,
+}
+
+impl FskEncoder {
+    fn silence(&mut self, samples: usize) {
+        self.levels.resize(self.levels.len() + samples, false);
+    }
+
+    fn cycle(&mut self, half: usize) {
+        let len = self.levels.len();
+        self.levels.resize(len + half, true);
+        self.levels.resize(len + half * 2, false);
+    }
+
+    fn sync(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.cycle(2);
+        }
+    }
+
+    fn bit(&mut self, one: bool) {
+        if one {
+            self.cycle(2);
+            self.cycle(2);
+        } else {
+            self.cycle(4);
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.bit(false);
+        for bit in 0..8 {
+            self.bit(byte & 1 << bit != 0);
+        }
+        self.bit(true);
+        self.bit(true);
+    }
+}
+
+/// Eight samples per 1200Hz cycle
+const FSK_SAMPLE_RATE: u32 = 9600;
+// Cycles of 2400Hz before file headers and before the other blocks
+const LONG_SYNC: usize = 16000;
+const SHORT_SYNC: usize = 4000;
+const LONG_SILENCE: usize = FSK_SAMPLE_RATE as usize * 2;
+const SHORT_SILENCE: usize = FSK_SAMPLE_RATE as usize;
+
+const DC_BLOCK_HZ: f32 = 20.0;
+const HYSTERESIS: f32 = 0.02;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::{CaptureRate, WavCapture};
+
+    /// CPU cycles from the start of the synthesized signal to a sample
+    fn cycles(sample: usize) -> u64 {
+        (sample as u64 * CPU_CLOCK_HZ as u64).div_ceil(FSK_SAMPLE_RATE as u64)
+    }
+
+    #[test]
+    fn test_cas_encoding_and_motor() {
+        let mut data = CAS_HEADER.to_vec();
+        data.push(0x01);
+        let mut signal = TapeSignal::from_cas(&data).unwrap();
+
+        // A data block: short silence and sync, then a 0 start bit and a 1 as the
+        // first data bit
+        let sync = SHORT_SILENCE + SHORT_SYNC * 4;
+        assert!(signal.levels[..SHORT_SILENCE].iter().all(|level| !level));
+        assert_eq!(
+            signal.levels[SHORT_SILENCE..SHORT_SILENCE + 4],
+            [true, true, false, false]
+        );
+        assert_eq!(
+            signal.levels[sync..sync + 8],
+            [true, true, true, true, false, false, false, false]
+        );
+        assert_eq!(
+            signal.levels[sync + 8..sync + 12],
+            [true, true, false, false]
+        );
+        assert_eq!(signal.levels.len(), sync + 11 * 8 + SHORT_SILENCE);
+
+        // The tape only moves with the motor on
+        assert!(!signal.level(1_000_000));
+        assert_eq!(signal.position_secs(1_000_000), 0.0);
+        signal.set_motor(true, 1_000_000);
+        let start = 1_000_000 + cycles(SHORT_SILENCE);
+        assert!(signal.playing(start));
+        assert!(signal.level(start));
+        assert!(!signal.playing(start + 100_000_000), "past the end");
+        signal.set_motor(false, 1_000_000 + cycles(SHORT_SILENCE + 2));
+        assert!(!signal.level(start + 1_000_000), "stopped on a low level");
+    }
+
+    #[test]
+    fn test_wav_is_squared_up() {
+        let mut capture = WavCapture::new(CaptureRate::Host, 44100, false, None);
+        // A 2400Hz tone with a DC offset and a little noise
+        for index in 0..4410 {
+            let phase = index as f32 * 2400.0 / 44100.0 * std::f32::consts::TAU;
+            let noise = if index % 2 == 0 { 0.01 } else { -0.01 };
+            capture.push([0.3 + 0.5 * phase.sin() + noise, 0.0]);
+        }
+        let signal = TapeSignal::from_wav(&capture.to_wav()).unwrap();
+        assert_eq!(signal.sample_rate, 44100);
+
+        let edges = signal.levels[2205..]
+            .windows(2)
+            .filter(|pair| pair[0] != pair[1])
+            .count();
+        assert!(
+            (239..=241).contains(&edges),
+            "{} edges in 120 cycles",
+            edges
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+
+use crate::keyboard_layout::{KeyStroke, KeyboardLayout};
+
+/// Types text on the key matrix, one character at a time.
+///
+/// Each character is held for `HOLD_FRAMES` video frames and released for
+/// `RELEASE_FRAMES`, so the BIOS, which scans the keyboard from the VDP interrupt,
+/// sees every press, even of the same key twice in a row. Enter is followed by a
+/// longer pause to give BASIC time to store the line.
+#[derive(Clone, Debug, Default)]
+pub struct Typist {
+    queue: VecDeque<KeyStroke>,
+    // Matrix positions held for the current character
+    held: Vec<(u8, u8)>,
+    // Frames left in the current press or pause
+    frames: u32,
+    // Frames to wait after the current press
+    pause: u32,
+}
+
+impl Typist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `text` for typing on a `layout` keyboard. Line breaks press Enter.
+    ///
+    /// Nothing is queued if a character has no key on the layout.
+    pub fn type_text(&mut self, layout: KeyboardLayout, text: &str) -> Result<(), String> {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let strokes = text
+            .chars()
+            .map(|c| {
+                layout
+                    .stroke(c)
+                    .ok_or_else(|| format!("The {} keyboard has no key for {:?}", layout.name(), c))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.queue.extend(strokes);
+        Ok(())
+    }
+
+    /// Whether characters are still being typed
+    pub fn typing(&self) -> bool {
+        !self.queue.is_empty() || !self.held.is_empty() || self.frames > 0
+    }
+
+    /// Drops the queued characters and releases the keys
+    pub fn cancel(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Matrix positions pressed by the typist
+    pub fn held(&self) -> &[(u8, u8)] {
+        &self.held
+    }
+
+    /// Called at the end of every video frame to move on to the next press or release
+    pub fn end_frame(&mut self) {
+        if self.frames > 0 {
+            self.frames -= 1;
+            if self.frames > 0 {
+                return;
+            }
+        }
+        if !self.held.is_empty() {
+            self.held.clear();
+            self.frames = self.pause;
+        } else if let Some(stroke) = self.queue.pop_front() {
+            self.held = stroke.keys();
+            self.frames = HOLD_FRAMES;
+            self.pause = match (stroke.row, stroke.col) {
+                ENTER => ENTER_FRAMES,
+                _ => RELEASE_FRAMES,
+            };
+        }
+    }
+}
+
+/// About 10 characters per second at 60Hz
+pub const HOLD_FRAMES: u32 = 3;
+pub const RELEASE_FRAMES: u32 = 3;
+/// Pause after Enter, enough for BASIC to tokenize and store a long program line
+pub const ENTER_FRAMES: u32 = 15;
+
+const ENTER: (u8, u8) = (7, 7);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Steps through frames until the typist is done, collecting what it held
+    fn frames(typist: &mut Typist) -> Vec<Vec<(u8, u8)>> {
+        let mut frames = Vec::new();
+        typist.end_frame();
+        while typist.typing() {
+            frames.push(typist.held().to_vec());
+            typist.end_frame();
+        }
+        frames
+    }
+
+    #[test]
+    fn test_presses_and_releases_every_character() {
+        let mut typist = Typist::new();
+        typist
+            .type_text(KeyboardLayout::International, "aA")
+            .unwrap();
+        let held = frames(&mut typist);
+
+        let press = HOLD_FRAMES as usize;
+        let stroke = press + RELEASE_FRAMES as usize;
+        assert_eq!(held.len(), stroke * 2);
+        assert_eq!(held[0], [(2, 6)]);
+        assert_eq!(held[press - 1], [(2, 6)]);
+        assert!(held[press..stroke].iter().all(|keys| keys.is_empty()));
+        assert_eq!(held[stroke], [(6, 0), (2, 6)], "Shift for the capital");
+    }
+
+    #[test]
+    fn test_modifiers_line_breaks_and_unknown_characters() {
+        let mut typist = Typist::new();
+        assert!(typist.type_text(KeyboardLayout::Japanese, "→").is_err());
+        assert!(!typist.typing(), "nothing queued");
+
+        typist.type_text(KeyboardLayout::Uk, "½\r\n").unwrap();
+        let held = frames(&mut typist);
+        assert_eq!(held[0], [(6, 2), (0, 2)], "GRAPH and 2");
+        let enter = (HOLD_FRAMES + RELEASE_FRAMES) as usize;
+        assert_eq!(held[enter], [(7, 7)], "a single Enter for CR LF");
+        assert_eq!(held.len(), enter + (HOLD_FRAMES + ENTER_FRAMES) as usize);
+    }
+}
@@ -1,11 +1,11 @@
 #![allow(dead_code)]
 
-use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+use std::{borrow::Cow, cell::RefCell, collections::VecDeque, rc::Rc};
 
 use serde::{Deserialize, Serialize};
 use tracing::{error, info};
 
-use crate::machine::Message;
+use crate::{clock::SCANLINES_PER_FRAME, machine::Message};
 
 #[derive(Clone)]
 pub struct TMS9918 {
@@ -23,6 +23,7 @@ pub struct TMS9918 {
     pub sprites: [Sprite; 32],
     pub frame: u8,
     pub line: u8,
+    pub line_border_colors: Vec<u8>, // R7 backdrop colour latched at the start of each raster line
     pub vblank: bool,
     pub display_mode: DisplayMode,
     pub f: u8,
@@ -35,7 +36,15 @@ pub struct TMS9918 {
     pub blink_page_duration: u8,
     pub blink_per_line: bool,
     pub blink_even_page: bool,
-    pub _blanking_change_pending: bool, // Renamed from blanking_change_pending
+    pub blanking_change_pending: bool, // R1 bit 6 changed, applied at the next line
+    pub display_enabled: bool,
+    pub line_blanked: Vec<Option<bool>>, // Blanking state latched at the start of each raster line, None until then
+
+    pub accurate_timing: bool, // Enforce the minimum interval between port 0x98 accesses
+    pub cpu_cycle: u64, // CPU cycle at which the current instruction started, fed from the Clock
+    pub last_vram_access: Option<u64>,
+    pub vram_timing_violations: u64,
+    vram_timing_burst: bool, // A violation was already reported since the last in-time access
 
     pub layout_table_address: u16,
     pub _layout_table_address_mask: u16, // Renamed from layout_table_address_mask
@@ -68,6 +77,7 @@ impl TMS9918 {
             }; 32],
             frame: 0,
             line: 0,
+            line_border_colors: vec![0; SCANLINES_PER_FRAME as usize],
             vblank: false,
             display_mode: DisplayMode::Graphic1,
 
@@ -82,7 +92,15 @@ impl TMS9918 {
             blink_per_line: false,
             blink_even_page: false,
             blink_page_duration: 0,
-            _blanking_change_pending: false,
+            blanking_change_pending: false,
+            display_enabled: false,
+            line_blanked: vec![None; SCANLINES_PER_FRAME as usize],
+
+            accurate_timing: false,
+            cpu_cycle: 0,
+            last_vram_access: None,
+            vram_timing_violations: 0,
+            vram_timing_burst: false,
 
             layout_table_address: 0,
             _layout_table_address_mask: 0,
@@ -115,6 +133,7 @@ impl TMS9918 {
             }; 32],
             frame: 0,
             line: 0,
+            line_border_colors: vec![0; SCANLINES_PER_FRAME as usize],
             vblank: false,
             display_mode: DisplayMode::Graphic1,
 
@@ -129,7 +148,15 @@ impl TMS9918 {
             blink_per_line: false,
             blink_even_page: false,
             blink_page_duration: 0,
-            _blanking_change_pending: false,
+            blanking_change_pending: false,
+            display_enabled: false,
+            line_blanked: vec![None; SCANLINES_PER_FRAME as usize],
+
+            accurate_timing: false,
+            cpu_cycle: 0,
+            last_vram_access: None,
+            vram_timing_violations: 0,
+            vram_timing_burst: false,
 
             layout_table_address: 0,
             _layout_table_address_mask: 0,
@@ -160,6 +187,7 @@ impl TMS9918 {
         }; 32];
         self.frame = 0;
         self.line = 0;
+        self.line_border_colors = vec![0; SCANLINES_PER_FRAME as usize];
         self.vblank = false;
         self.display_mode = DisplayMode::Graphic1;
         self.f = 0;
@@ -168,6 +196,12 @@ impl TMS9918 {
         self.sprites_invalid = None;
         self.sprites_max_computed = 0;
         self.sprites_visible = vec![Vec::new(); 192];
+        self.blanking_change_pending = false;
+        self.display_enabled = false;
+        self.line_blanked = vec![None; SCANLINES_PER_FRAME as usize];
+        self.last_vram_access = None;
+        self.vram_timing_violations = 0;
+        self.vram_timing_burst = false;
 
         self.update_blinking();
         // self.update_color_table_address(); // Called when R3/R10 is written
@@ -183,7 +217,7 @@ impl TMS9918 {
         }
     }
 
-    pub fn char_pattern_table(&self) -> &[u8] {
+    pub fn char_pattern_table(&self) -> Cow<'_, [u8]> {
         let base_address = match self.display_mode {
             DisplayMode::Text1 => (self.registers[4] as usize & 0x07) * 0x800,
             DisplayMode::Graphic1 | DisplayMode::Graphic2 => {
@@ -200,18 +234,18 @@ impl TMS9918 {
         };
 
         if base_address + size <= self.vram.len() {
-            &self.vram[base_address..(base_address + size)]
+            self.vram_range(base_address, size)
         } else {
             error!(
                 "Invalid character pattern table range: {:04X} to {:04X}",
                 base_address,
                 base_address + size
             );
-            &self.vram[0..0]
+            Cow::Borrowed(&self.vram[0..0])
         }
     }
 
-    pub fn color_table(&self) -> &[u8] {
+    pub fn color_table(&self) -> Cow<'_, [u8]> {
         let ct_base = match self.display_mode {
             DisplayMode::Graphic1 => ((self.registers[3] as usize) & 0x80) << 6,
             DisplayMode::Graphic2 => ((self.registers[3] as usize) & 0x80) << 6,
@@ -225,13 +259,13 @@ impl TMS9918 {
         };
 
         if ct_base.saturating_add(ct_table_size) <= self.vram.len() {
-            &self.vram[ct_base..(ct_base + ct_table_size)]
+            self.vram_range(ct_base, ct_table_size)
         } else {
             tracing::error!(
                 "VDP::color_table OOB access: base={:04X}, size={:04X} (mode {:?}), R3={:02X}, calculated ct_base={:04X}. VRAM len={:04X}",
                 self.color_table_address, ct_table_size, self.display_mode, self.registers[3], ct_base, self.vram.len()
             );
-            &self.vram[0..0] // Fallback to empty slice
+            Cow::Borrowed(&self.vram[0..0]) // Fallback to empty slice
         }
     }
 
@@ -279,10 +313,10 @@ impl TMS9918 {
             let sprite_addr = sat_addr + (i * 4);
 
             // Read sprite attributes from VRAM
-            let y = self.vram[sprite_addr];
-            let x = self.vram[sprite_addr + 1];
-            let pattern = self.vram[sprite_addr + 2];
-            let color = self.vram[sprite_addr + 3];
+            let y = self.read_vram(sprite_addr);
+            let x = self.read_vram(sprite_addr + 1);
+            let pattern = self.read_vram(sprite_addr + 2);
+            let color = self.read_vram(sprite_addr + 3);
 
             self.sprites[i] = Sprite {
                 y,
@@ -419,7 +453,7 @@ impl TMS9918 {
                 sprite.pattern as usize * 8 + sprite_line
             };
 
-            let pattern_data = self.vram[spt_addr + pattern_offset];
+            let pattern_data = self.read_vram(spt_addr + pattern_offset);
 
             // Render sprite pixels
             for bit in 0..8 {
@@ -452,7 +486,7 @@ impl TMS9918 {
                     spt_addr + (sprite.pattern as usize & 0xFC) * 8 + 24 + (sprite_line - 8)
                 };
 
-                let pattern_data_2 = self.vram[right_offset];
+                let pattern_data_2 = self.read_vram(right_offset);
 
                 for bit in 0..8 {
                     let pixel_set = (pattern_data_2 & (0x80 >> bit)) != 0;
@@ -475,17 +509,138 @@ impl TMS9918 {
         }
     }
 
+    /// Maps a VRAM address onto the physical VRAM array, for CPU accesses and display
+    /// fetches alike.
+    ///
+    /// With R1 bit 7 clear the VDP drives 4K (4027) DRAMs and splits the address into
+    /// 6-bit row/column halves differently than in 16K (4116) mode, which scrambles the
+    /// address bits as seen from the 16K layout. Software that stays in one mode sees
+    /// its data where it put it; switching modes moves it around.
+    pub fn vram_address(&self, address: u16) -> usize {
+        let address = address & 0x3FFF;
+        if self.registers[1] & 0x80 != 0 {
+            address as usize
+        } else {
+            ((address & 0x203F) | ((address >> 6) & 0x0040) | ((address << 1) & 0x1F80)) as usize
+        }
+    }
+
+    /// A VRAM byte as the display fetches it
+    pub fn read_vram(&self, address: usize) -> u8 {
+        self.vram[self.vram_address(address as u16)]
+    }
+
+    /// `len` bytes of VRAM from `start` as the display fetches them; only copied when
+    /// 4K addressing scatters them
+    fn vram_range(&self, start: usize, len: usize) -> Cow<'_, [u8]> {
+        if self.registers[1] & 0x80 != 0 {
+            Cow::Borrowed(&self.vram[start..start + len])
+        } else {
+            Cow::Owned(
+                (start..start + len)
+                    .map(|address| self.read_vram(address))
+                    .collect(),
+            )
+        }
+    }
+
+    /// Whether the display was blanked (R1 bit 6 clear) on the given raster line.
+    ///
+    /// Lines not latched yet, as on a VDP that has not run a frame, follow R1 as it is.
+    pub fn is_line_blanked(&self, line: usize) -> bool {
+        self.line_blanked
+            .get(line)
+            .copied()
+            .flatten()
+            .unwrap_or(self.registers[1] & 0x40 == 0)
+    }
+
+    /// Minimum number of CPU cycles between two VRAM accesses through port 0x98.
+    ///
+    /// Taken from the TMS9918A datasheet: 2 µs while the display is blanked or in the
+    /// vertical border, otherwise 2 µs in Text 1, 3.5 µs in Multicolor and 8 µs in
+    /// Graphic 1/2, where the VDP spends most of each line fetching tables. This is the
+    /// worst case gap; where the VDP's free access slots fall within a line is not
+    /// modelled.
+    pub fn vram_access_interval(&self) -> u64 {
+        let active_display = !self.vblank && self.line < 192 && self.display_enabled;
+        if !active_display {
+            return 8;
+        }
+
+        match self.display_mode {
+            DisplayMode::Text1 => 8,
+            DisplayMode::Multicolor => 13,
+            DisplayMode::Graphic1 | DisplayMode::Graphic2 => 29,
+        }
+    }
+
+    /// Records the CPU cycle at which the instruction making the next I/O access
+    /// started. Accesses are timed from there rather than from their I/O cycle, a few
+    /// cycles early for an OUT at the end of a long instruction.
+    pub fn set_cpu_cycle(&mut self, cycle: u64) {
+        self.cpu_cycle = cycle;
+    }
+
+    /// Checks a port 0x98 access against the minimum access interval of the current
+    /// display mode. Always succeeds when accurate timing is disabled.
+    fn vram_access_in_time(&mut self, kind: &str) -> bool {
+        if !self.accurate_timing {
+            return true;
+        }
+
+        let interval = self.vram_access_interval();
+        let in_time = match self.last_vram_access {
+            Some(last) => self.cpu_cycle.saturating_sub(last) >= interval,
+            None => true,
+        };
+
+        if in_time {
+            self.last_vram_access = Some(self.cpu_cycle);
+            self.vram_timing_burst = false;
+            return true;
+        }
+
+        self.vram_timing_violations += 1;
+        if !self.vram_timing_burst {
+            self.vram_timing_burst = true;
+            tracing::warn!(
+                "[VDP] VRAM {} too fast: {} cycles since last access, {:?} needs {} (line {}, address {:04X})",
+                kind,
+                self.cpu_cycle.saturating_sub(self.last_vram_access.unwrap_or(0)),
+                self.display_mode,
+                interval,
+                self.line,
+                self.address
+            );
+        }
+        false
+    }
+
     fn read98(&mut self) -> u8 {
         self.first_write = None;
         let data = self.data_pre_read;
-        self.data_pre_read = self.vram[self.address as usize];
+        // A read that comes too early returns the stale read-ahead value and skips the prefetch
+        if !self.vram_access_in_time("read") {
+            self.address_wrapping_inc();
+            return data;
+        }
+        self.data_pre_read = self.vram[self.vram_address(self.address)];
         self.address_wrapping_inc();
         data
     }
 
     pub fn write_98(&mut self, data: u8) {
+        if !self.vram_access_in_time("write") {
+            // The VDP misses the byte, but the address counter still advances
+            self.address = (self.address + 1) & 0x3FFF;
+            self.first_write = None;
+            return;
+        }
+
         if self.address < self.vram.len() as u16 {
-            self.vram[self.address as usize] = data;
+            let address = self.vram_address(self.address);
+            self.vram[address] = data;
             self.data_pre_read = data;
         } else {
             error!(
@@ -574,7 +729,7 @@ impl TMS9918 {
                     self.update_irq();
                 }
                 if modified & 0x40 != 0 {
-                    self._blanking_change_pending = true;
+                    self.blanking_change_pending = true;
                 }
                 if modified & 0x18 != 0 {
                     self.set_display_mode();
@@ -819,7 +974,7 @@ impl TMS9918 {
         } else {
             self.address = (((val & 0x3f) as u16) << 8) | (data_first_write as u16) & 0x3FFF;
             if (val & 0x40) == 0 {
-                self.data_pre_read = self.vram[self.address as usize];
+                self.data_pre_read = self.vram[self.vram_address(self.address)];
                 self.address = (self.address + 1) & 0x3FFF;
             }
         }
@@ -868,6 +1023,27 @@ impl TMS9918 {
 
     pub fn set_current_scanline(&mut self, line: u16) {
         self.line = (line & 0xFF) as u8;
+
+        // Blanking changes take effect from the next line on
+        if self.blanking_change_pending {
+            self.blanking_change_pending = false;
+            self.display_enabled = self.registers[1] & 0x40 != 0;
+        }
+
+        if let Some(border) = self.line_border_colors.get_mut(line as usize) {
+            *border = self.registers[7] & 0x0F;
+        }
+        if let Some(blanked) = self.line_blanked.get_mut(line as usize) {
+            *blanked = Some(!self.display_enabled);
+        }
+    }
+
+    /// Backdrop colour that was active on the given raster line (0 = first active display line)
+    pub fn border_color_at(&self, line: usize) -> u8 {
+        self.line_border_colors
+            .get(line)
+            .copied()
+            .unwrap_or(self.registers[7] & 0x0F)
     }
 
     pub fn is_interrupt_enabled(&self) -> bool {
@@ -885,6 +1061,10 @@ impl TMS9918 {
             return None;
         }
 
+        if self.is_line_blanked(scanline as usize) {
+            return Some(vec![self.border_color_at(scanline as usize); 256]);
+        }
+
         // Create a temporary renderer for this scanline
         let mut renderer = crate::renderer::Renderer::new(self);
 
@@ -997,3 +1177,41 @@ const MODE_DATA_MULTICOLOR: ModeData = ModeData {
 const COLOR_TABLE_ADDRESS_MASK_BASE: i16 = !(-1 << 6);
 const LAYOUT_TABLE_ADDRESS_MASK_BASE: i16 = !(-1 << 10);
 const PATTERN_TABLE_ADDRESS_MASK_BASE: i16 = !(-1 << 11);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minimum_access_interval_drops_fast_writes() {
+        let mut vdp = TMS9918::new(Rc::new(RefCell::new(VecDeque::new())));
+        vdp.write_register(1, 0xC0); // 16K, display enabled, Graphic1
+        for line in 0..SCANLINES_PER_FRAME as u16 {
+            vdp.set_current_scanline(line);
+        }
+        vdp.set_current_scanline(50);
+        vdp.accurate_timing = true;
+
+        // Set write address 0x0000
+        vdp.write(0x99, 0x00);
+        vdp.write(0x99, 0x40);
+
+        // OUT (n),A every 11 cycles is too fast for Graphic1 during active display
+        for (i, value) in [0x11, 0x22, 0x33].iter().enumerate() {
+            vdp.set_cpu_cycle(1000 + i as u64 * 11);
+            vdp.write(0x98, *value);
+        }
+        assert_eq!(&vdp.vram[0..3], &[0x11, 0x00, 0x00]);
+        assert_eq!(vdp.address, 3);
+        assert_eq!(vdp.vram_timing_violations, 2);
+
+        // The same sequence during vblank goes through
+        vdp.set_vblank(true);
+        for (i, value) in [0x44, 0x55].iter().enumerate() {
+            vdp.set_cpu_cycle(2000 + i as u64 * 11);
+            vdp.write(0x98, *value);
+        }
+        assert_eq!(&vdp.vram[3..5], &[0x44, 0x55]);
+        assert_eq!(vdp.vram_timing_violations, 2);
+    }
+}
@@ -0,0 +1,396 @@
+//! Decoders that turn the VDP tables in VRAM into standalone images and structured data,
+//! for VRAM viewers and for checking graphics conversions.
+//!
+//! Images are built from colour indices; colour 0 stays transparent so a viewer can show
+//! what the backdrop would fill in.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{
+    palette::Palette,
+    vdp::{DisplayMode, TMS9918},
+};
+
+/// Tiles per row in the pattern and sprite sheets
+const SHEET_COLUMNS: usize = 16;
+
+/// Table base addresses as programmed in R2-R6
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TableAddresses {
+    pub name_table: u16,
+    pub color_table: u16,
+    pub pattern_table: u16,
+    pub sprite_attribute_table: u16,
+    pub sprite_pattern_table: u16,
+}
+
+impl TableAddresses {
+    pub fn from_vdp(vdp: &TMS9918) -> Self {
+        let r = &vdp.registers;
+        let (color_table, pattern_table) = match vdp.display_mode {
+            // Graphic 2 only honours the top bit of R3 and bit 2 of R4, the rest are masks
+            DisplayMode::Graphic2 => (((r[3] & 0x80) as u16) << 6, ((r[4] & 0x04) as u16) << 11),
+            _ => ((r[3] as u16) << 6, ((r[4] & 0x07) as u16) << 11),
+        };
+
+        Self {
+            name_table: ((r[2] & 0x0F) as u16) << 10,
+            color_table,
+            pattern_table,
+            sprite_attribute_table: ((r[5] & 0x7F) as u16) << 7,
+            sprite_pattern_table: ((r[6] & 0x07) as u16) << 11,
+        }
+    }
+}
+
+/// One entry of the sprite attribute table
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SpriteInfo {
+    pub index: u8,
+    pub x: u8,
+    pub y: u8,
+    pub pattern: u8,
+    pub color: u8,
+    pub early_clock: bool,
+    /// False for the terminator entry (Y = 0xD0) and every sprite after it
+    pub active: bool,
+}
+
+/// Everything a VRAM viewer needs besides the images
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VdpTables {
+    pub display_mode: DisplayMode,
+    pub registers: [u8; 8],
+    pub addresses: TableAddresses,
+    pub sprite_size: u8,
+    pub sprite_magnification: u8,
+    pub sprites: Vec<SpriteInfo>,
+}
+
+impl VdpTables {
+    pub fn from_vdp(vdp: &TMS9918) -> Self {
+        Self {
+            display_mode: vdp.display_mode.clone(),
+            registers: vdp.registers,
+            addresses: TableAddresses::from_vdp(vdp),
+            sprite_size: vdp.sprite_size(),
+            sprite_magnification: vdp.sprite_magnification(),
+            sprites: sprites(vdp),
+        }
+    }
+}
+
+/// Image of colour indices produced by the decoders
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VdpImage {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl VdpImage {
+    /// Colour indices, row by row; map them through `Machine.palette` for display
+    #[wasm_bindgen(getter)]
+    pub fn indices(&self) -> Vec<u8> {
+        self.pixels.clone()
+    }
+}
+
+impl VdpImage {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// RGBA8888 version of the image, with colour 0 fully transparent
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        let colors = palette.colors();
+        let mut rgba = Vec::with_capacity(self.pixels.len() * 4);
+        for &index in self.pixels.iter() {
+            if index == 0 {
+                rgba.extend_from_slice(&[0, 0, 0, 0]);
+            } else {
+                rgba.extend_from_slice(&colors[(index & 0x0F) as usize]);
+            }
+        }
+        rgba
+    }
+
+    fn draw_tile(&mut self, x: usize, y: usize, tile: &Tile) {
+        for (row, line) in tile.pixels.iter().enumerate() {
+            let start = (y + row) * self.width + x;
+            self.pixels[start..start + tile.width].copy_from_slice(&line[..tile.width]);
+        }
+    }
+}
+
+/// An 8 line high character, 6 (Text 1) or 8 pixels wide
+struct Tile {
+    width: usize,
+    pixels: [[u8; 8]; 8],
+}
+
+/// Decodes a pattern with its colours the way the current display mode would show it.
+/// `bank` selects the screen third in Graphic 2 and is ignored elsewhere.
+fn decode_tile(vdp: &TMS9918, addresses: &TableAddresses, pattern: u8, bank: usize) -> Tile {
+    let mut tile = Tile {
+        width: 8,
+        pixels: [[0; 8]; 8],
+    };
+    let pattern_base = addresses.pattern_table as usize + pattern as usize * 8;
+
+    match vdp.display_mode {
+        DisplayMode::Text1 => {
+            tile.width = 6;
+            let fg = vdp.registers[7] >> 4;
+            let bg = vdp.registers[7] & 0x0F;
+            for (row, line) in tile.pixels.iter_mut().enumerate() {
+                let bits = vdp.read_vram(pattern_base + row);
+                for (i, pixel) in line.iter_mut().take(6).enumerate() {
+                    *pixel = if bits & (0x80 >> i) != 0 { fg } else { bg };
+                }
+            }
+        }
+        DisplayMode::Graphic1 => {
+            let color = vdp.read_vram(addresses.color_table as usize + pattern as usize / 8);
+            for (row, line) in tile.pixels.iter_mut().enumerate() {
+                let bits = vdp.read_vram(pattern_base + row);
+                decode_row(line, bits, color);
+            }
+        }
+        DisplayMode::Graphic2 => {
+            let offset = bank * 0x800 + pattern as usize * 8;
+            for (row, line) in tile.pixels.iter_mut().enumerate() {
+                let bits = vdp.read_vram(addresses.pattern_table as usize + offset + row);
+                let color = vdp.read_vram(addresses.color_table as usize + offset + row);
+                decode_row(line, bits, color);
+            }
+        }
+        DisplayMode::Multicolor => {
+            // Each pattern byte holds two 4 pixel wide colour blocks
+            for (row, line) in tile.pixels.iter_mut().enumerate() {
+                let colors = vdp.read_vram(pattern_base + row);
+                line[..4].fill(colors >> 4);
+                line[4..].fill(colors & 0x0F);
+            }
+        }
+    }
+
+    tile
+}
+
+fn decode_row(line: &mut [u8; 8], bits: u8, color: u8) {
+    let fg = color >> 4;
+    let bg = color & 0x0F;
+    for (i, pixel) in line.iter_mut().enumerate() {
+        *pixel = if bits & (0x80 >> i) != 0 { fg } else { bg };
+    }
+}
+
+/// The pattern table as a sheet of 16 tiles per row, coloured with the colour table.
+/// Graphic 2 shows its three banks of 256 patterns one below the other.
+pub fn pattern_sheet(vdp: &TMS9918) -> VdpImage {
+    let addresses = TableAddresses::from_vdp(vdp);
+    let banks = match vdp.display_mode {
+        DisplayMode::Graphic2 => 3,
+        _ => 1,
+    };
+    let tile_width = match vdp.display_mode {
+        DisplayMode::Text1 => 6,
+        _ => 8,
+    };
+    let rows_per_bank = 256 / SHEET_COLUMNS;
+
+    let mut image = VdpImage::new(SHEET_COLUMNS * tile_width, banks * rows_per_bank * 8);
+    for bank in 0..banks {
+        for pattern in 0..256 {
+            let tile = decode_tile(vdp, &addresses, pattern as u8, bank);
+            let x = (pattern % SHEET_COLUMNS) * tile_width;
+            let y = (bank * rows_per_bank + pattern / SHEET_COLUMNS) * 8;
+            image.draw_tile(x, y, &tile);
+        }
+    }
+    image
+}
+
+/// The name table as a tilemap: the screen as the patterns alone would draw it, without
+/// sprites, blanking or the backdrop
+pub fn name_table_map(vdp: &TMS9918) -> VdpImage {
+    let addresses = TableAddresses::from_vdp(vdp);
+    let (columns, tile_width) = match vdp.display_mode {
+        DisplayMode::Text1 => (40, 6),
+        _ => (32, 8),
+    };
+
+    let mut image = VdpImage::new(columns * tile_width, 24 * 8);
+    for row in 0..24 {
+        for column in 0..columns {
+            let name = vdp.read_vram(addresses.name_table as usize + row * columns + column);
+            let tile = match vdp.display_mode {
+                // Multicolor picks two pattern bytes per name depending on the screen row
+                DisplayMode::Multicolor => multicolor_tile(vdp, &addresses, name, row),
+                _ => decode_tile(vdp, &addresses, name, row / 8),
+            };
+            image.draw_tile(column * tile_width, row * 8, &tile);
+        }
+    }
+    image
+}
+
+fn multicolor_tile(vdp: &TMS9918, addresses: &TableAddresses, name: u8, row: usize) -> Tile {
+    let mut tile = Tile {
+        width: 8,
+        pixels: [[0; 8]; 8],
+    };
+    let base = addresses.pattern_table as usize + name as usize * 8 + (row & 3) * 2;
+    for (line_index, line) in tile.pixels.iter_mut().enumerate() {
+        let colors = vdp.read_vram(base + line_index / 4);
+        line[..4].fill(colors >> 4);
+        line[4..].fill(colors & 0x0F);
+    }
+    tile
+}
+
+/// All 32 entries of the sprite attribute table
+pub fn sprites(vdp: &TMS9918) -> Vec<SpriteInfo> {
+    let sat = TableAddresses::from_vdp(vdp).sprite_attribute_table as usize;
+    let mut active = true;
+
+    (0..32)
+        .map(|index| {
+            let entry = sat + index * 4;
+            let y = vdp.read_vram(entry);
+            if y == 0xD0 {
+                active = false;
+            }
+            let color = vdp.read_vram(entry + 3);
+            SpriteInfo {
+                index: index as u8,
+                x: vdp.read_vram(entry + 1),
+                y,
+                pattern: vdp.read_vram(entry + 2),
+                color: color & 0x0F,
+                early_clock: color & 0x80 != 0,
+                active,
+            }
+        })
+        .collect()
+}
+
+/// The 32 sprites' patterns in their own colour, 16 per row, at the configured size
+/// (without magnification)
+pub fn sprite_sheet(vdp: &TMS9918) -> VdpImage {
+    let addresses = TableAddresses::from_vdp(vdp);
+    let size = vdp.sprite_size() as usize;
+    let columns = SHEET_COLUMNS;
+
+    let mut image = VdpImage::new(columns * size, (32 / columns) * size);
+    for sprite in sprites(vdp) {
+        let index = sprite.index as usize;
+        let origin_x = (index % columns) * size;
+        let origin_y = (index / columns) * size;
+
+        // 16x16 sprites use four consecutive 8x8 patterns: top-left, bottom-left,
+        // top-right, bottom-right
+        let pattern = if size == 16 {
+            sprite.pattern & 0xFC
+        } else {
+            sprite.pattern
+        };
+        let base = addresses.sprite_pattern_table as usize + pattern as usize * 8;
+
+        for y in 0..size {
+            for x in 0..size {
+                let quadrant = (x / 8) * 2 + y / 8;
+                let bits = vdp.read_vram(base + quadrant * 8 + (y & 7));
+                if bits & (0x80 >> (x & 7)) != 0 {
+                    image.pixels[(origin_y + y) * image.width + origin_x + x] = sprite.color;
+                }
+            }
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    use super::*;
+
+    fn vdp_with_registers(registers: [u8; 8]) -> TMS9918 {
+        let mut vdp = TMS9918::new(Rc::new(RefCell::new(VecDeque::new())));
+        for (reg, value) in registers.iter().enumerate() {
+            vdp.write(0x99, *value);
+            vdp.write(0x99, 0x80 | reg as u8);
+        }
+        vdp
+    }
+
+    #[test]
+    fn test_screen1_tables() {
+        // SCREEN 1 layout as set up by the BIOS
+        let mut vdp = vdp_with_registers([0x00, 0xE0, 0x06, 0x80, 0x00, 0x36, 0x07, 0x07]);
+        let addresses = TableAddresses::from_vdp(&vdp);
+        assert_eq!(addresses.name_table, 0x1800);
+        assert_eq!(addresses.color_table, 0x2000);
+        assert_eq!(addresses.pattern_table, 0x0000);
+        assert_eq!(addresses.sprite_attribute_table, 0x1B00);
+        assert_eq!(addresses.sprite_pattern_table, 0x3800);
+
+        // Character 'A' (0x41): solid first row, white on dark blue
+        vdp.vram[0x41 * 8] = 0xFF;
+        vdp.vram[0x2000 + 0x41 / 8] = 0xF4;
+        vdp.vram[0x1800 + 33] = 0x41;
+
+        let sheet = pattern_sheet(&vdp);
+        assert_eq!((sheet.width, sheet.height), (128, 128));
+        let (tile_x, tile_y) = ((0x41 % 16) * 8, (0x41 / 16) * 8);
+        assert_eq!(sheet.pixel(tile_x, tile_y), 0x0F);
+        assert_eq!(sheet.pixel(tile_x, tile_y + 1), 0x04);
+
+        let map = name_table_map(&vdp);
+        assert_eq!((map.width, map.height), (256, 192));
+        assert_eq!(map.pixel(8, 8), 0x0F);
+        assert_eq!(map.to_rgba(&Palette::default()).len(), 256 * 192 * 4);
+    }
+
+    #[test]
+    fn test_sprites() {
+        let mut vdp = vdp_with_registers([0x00, 0xE2, 0x06, 0x80, 0x00, 0x36, 0x07, 0x07]);
+        let sat = 0x1B00;
+        vdp.vram[sat..sat + 4].copy_from_slice(&[10, 20, 4, 0x88]);
+        vdp.vram[sat + 4] = 0xD0;
+        // Top-right quadrant of pattern 4, first row
+        vdp.vram[0x3800 + 4 * 8 + 16] = 0x80;
+
+        let sprites = sprites(&vdp);
+        assert_eq!(sprites.len(), 32);
+        assert_eq!(
+            (sprites[0].x, sprites[0].y, sprites[0].pattern),
+            (20, 10, 4)
+        );
+        assert_eq!(sprites[0].color, 8);
+        assert!(sprites[0].early_clock && sprites[0].active);
+        assert!(!sprites[1].active && !sprites[31].active);
+
+        let sheet = sprite_sheet(&vdp);
+        assert_eq!((sheet.width, sheet.height), (256, 32));
+        assert_eq!(sheet.pixel(8, 0), 8);
+        assert_eq!(sheet.pixel(0, 0), 0);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::{
+    clock::CPU_CLOCK_HZ,
+    opll::Ym2413,
+    psg::{PsgModel, AY38910},
+    scc::{Scc, SccChip},
+    y8950::Y8950,
+};
+
+/// A sound chip register write, as seen on the bus
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VgmWrite {
+    Psg {
+        register: u8,
+        value: u8,
+    },
+    Opll {
+        register: u8,
+        value: u8,
+    },
+    Y8950 {
+        register: u8,
+        value: u8,
+    },
+    /// A write to the SCC register window, `offset` being relative to 0x9800 or to
+    /// 0xB800 when `plus_layout` is set
+    Scc {
+        chip: SccChip,
+        plus_layout: bool,
+        offset: u8,
+        value: u8,
+    },
+}
+
+/// Records sound chip register writes with their timing into a VGM file.
+///
+/// Cycle timestamps are converted to the 44.1kHz sample clock of the format. Recording
+/// starts with a dump of the chips' current registers, so a capture taken from a running
+/// game plays back with the right instruments.
+#[derive(Clone, Debug)]
+pub struct VgmLog {
+    start_cycle: u64,
+    // Samples already covered by wait commands
+    samples: u64,
+    commands: Vec<u8>,
+    psg_model: PsgModel,
+    opll: bool,
+    y8950: bool,
+    scc: Option<SccChip>,
+}
+
+impl VgmLog {
+    pub fn new(start_cycle: u64, psg_model: PsgModel) -> Self {
+        Self {
+            start_cycle,
+            samples: 0,
+            commands: Vec::new(),
+            psg_model,
+            opll: false,
+            y8950: false,
+            scc: None,
+        }
+    }
+
+    /// Samples of the 44.1kHz VGM clock recorded so far
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    pub fn write(&mut self, cycle: u64, write: VgmWrite) {
+        self.wait_until(cycle);
+        match write {
+            VgmWrite::Psg { register, value } => {
+                // Registers 14 and 15 are the I/O ports, not sound
+                if register < 14 {
+                    self.push(&[0xA0, register, value]);
+                }
+            }
+            VgmWrite::Opll { register, value } => {
+                self.opll = true;
+                self.push(&[0x51, register, value]);
+            }
+            VgmWrite::Y8950 { register, value } => {
+                self.y8950 = true;
+                self.push(&[0x5C, register, value]);
+            }
+            VgmWrite::Scc {
+                chip,
+                plus_layout,
+                offset,
+                value,
+            } => self.write_scc(chip, plus_layout, offset, value),
+        }
+    }
+
+    pub fn write_psg_state(&mut self, psg: &AY38910) {
+        for (register, value) in psg.registers().into_iter().enumerate().take(14) {
+            self.push(&[0xA0, register as u8, value]);
+        }
+    }
+
+    pub fn write_opll_state(&mut self, opll: &Ym2413) {
+        self.opll = true;
+        for (register, &value) in opll.registers().iter().enumerate().take(0x39) {
+            if register != 0x0F {
+                self.push(&[0x51, register as u8, value]);
+            }
+        }
+    }
+
+    /// Dumps the FM registers; the ADPCM unit is left alone since its control register
+    /// starts memory accesses
+    pub fn write_y8950_state(&mut self, y8950: &Y8950) {
+        self.y8950 = true;
+        let registers = y8950.registers();
+        for register in std::iter::once(0x08).chain(0x20..=0xFF) {
+            self.push(&[0x5C, register as u8, registers[register]]);
+        }
+    }
+
+    pub fn write_scc_state(&mut self, scc: &Scc) {
+        let chip = scc.chip();
+        self.scc.get_or_insert(chip);
+        let waves = scc.waves();
+        let channels = if chip == SccChip::SccI { 5 } else { 4 };
+        for (channel, wave) in waves.iter().enumerate().take(channels) {
+            for (index, &sample) in wave.iter().enumerate() {
+                let (port, register) = if channel == 4 {
+                    (4, index as u8)
+                } else {
+                    (0, (channel * 32 + index) as u8)
+                };
+                self.push(&[0xD2, port, register, sample as u8]);
+            }
+        }
+        for (channel, period) in scc.periods().into_iter().enumerate() {
+            let register = channel as u8 * 2;
+            self.push(&[0xD2, 1, register, period as u8]);
+            self.push(&[0xD2, 1, register + 1, (period >> 8) as u8]);
+        }
+        for (channel, volume) in scc.volumes().into_iter().enumerate() {
+            self.push(&[0xD2, 2, channel as u8, volume]);
+        }
+        self.push(&[0xD2, 3, 0, scc.enabled()]);
+        self.push(&[0xD2, 5, 0, scc.deformation()]);
+    }
+
+    /// Ends the recording at `end_cycle` and returns the complete VGM file
+    pub fn finish(mut self, end_cycle: u64) -> Vec<u8> {
+        self.wait_until(end_cycle);
+        self.commands.push(0x66);
+
+        let mut header = vec![0u8; HEADER_SIZE];
+        let mut set = |offset: usize, value: u32| {
+            header[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        set(0x00, u32::from_le_bytes(*b"Vgm "));
+        set(0x04, (HEADER_SIZE + self.commands.len() - 4) as u32);
+        set(0x08, VERSION);
+        if self.opll {
+            set(0x10, CPU_CLOCK_HZ);
+        }
+        set(0x18, self.samples as u32);
+        set(0x24, 60);
+        set(0x34, (HEADER_SIZE - 0x34) as u32);
+        if self.y8950 {
+            set(0x58, CPU_CLOCK_HZ);
+        }
+        set(0x74, CPU_CLOCK_HZ / 2);
+        if let Some(chip) = self.scc {
+            // Bit 31 selects the K052539 (SCC-I) with its separate fifth waveform
+            let plus = if chip == SccChip::SccI { 1 << 31 } else { 0 };
+            set(0x9C, (CPU_CLOCK_HZ / 2) | plus);
+        }
+        header[0x78] = match self.psg_model {
+            PsgModel::Ay38910 => 0x00,
+            PsgModel::Ym2149 => 0x10,
+        };
+        // Legacy output, as on the MSX
+        header[0x79] = 0x01;
+
+        header.extend_from_slice(&self.commands);
+        header
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.commands.extend_from_slice(bytes);
+    }
+
+    fn wait_until(&mut self, cycle: u64) {
+        let elapsed = cycle.saturating_sub(self.start_cycle);
+        let target = elapsed * VGM_SAMPLE_RATE / CPU_CLOCK_HZ as u64;
+        let mut remaining = target.saturating_sub(self.samples);
+        self.samples = self.samples.max(target);
+
+        while remaining > 0 {
+            let wait = remaining.min(0xFFFF);
+            match wait {
+                735 => self.commands.push(0x62),
+                882 => self.commands.push(0x63),
+                1..=16 => self.commands.push(0x70 + wait as u8 - 1),
+                _ => {
+                    self.commands.push(0x61);
+                    self.push(&(wait as u16).to_le_bytes());
+                }
+            }
+            remaining -= wait;
+        }
+    }
+
+    fn write_scc(&mut self, chip: SccChip, plus_layout: bool, offset: u8, value: u8) {
+        self.scc.get_or_insert(chip);
+        let (port, register) = match (plus_layout, offset) {
+            (_, 0x00..=0x7F) => (0, offset),
+            (false, 0x80..=0x9F) | (true, 0xA0..=0xBF) => match offset & 0x0F {
+                register @ 0x0..=0x9 => (1, register),
+                register @ 0xA..=0xE => (2, register - 0xA),
+                _ => (3, 0),
+            },
+            (true, 0x80..=0x9F) => (4, offset - 0x80),
+            (false, 0xE0..=0xFF) | (true, 0xC0..=0xDF) => (5, 0),
+            _ => return,
+        };
+        self.push(&[0xD2, port, register, value]);
+
+        // In the compatible layout the SCC-I still mirrors channel 4's wave to channel 5,
+        // which a K052539 player would not do on its own
+        if chip == SccChip::SccI && !plus_layout && (0x60..=0x7F).contains(&offset) {
+            self.push(&[0xD2, 4, offset - 0x60, value]);
+        }
+    }
+}
+
+const VERSION: u32 = 0x171;
+const HEADER_SIZE: usize = 0x100;
+const VGM_SAMPLE_RATE: u64 = 44100;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_and_waits() {
+        let mut log = VgmLog::new(1000, PsgModel::Ym2149);
+        log.write(
+            1000,
+            VgmWrite::Psg {
+                register: 8,
+                value: 0x0F,
+            },
+        );
+        // One NTSC frame later
+        log.write(
+            1000 + 59_736,
+            VgmWrite::Opll {
+                register: 0x30,
+                value: 0x10,
+            },
+        );
+        let vgm = log.finish(1000 + 59_736 + 81);
+
+        let word = |offset: usize| u32::from_le_bytes(vgm[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&vgm[0..4], b"Vgm ");
+        assert_eq!(word(0x04) as usize, vgm.len() - 4);
+        assert_eq!(word(0x08), 0x171);
+        assert_eq!(word(0x10), CPU_CLOCK_HZ, "OPLL clock");
+        assert_eq!(word(0x18), 736);
+        assert_eq!(word(0x34) as usize + 0x34, HEADER_SIZE);
+        assert_eq!(word(0x58), 0, "no Y8950 writes");
+        assert_eq!(word(0x74), CPU_CLOCK_HZ / 2);
+        assert_eq!(vgm[0x78], 0x10, "YM2149");
+        assert_eq!(word(0x9C), 0, "no SCC writes");
+
+        assert_eq!(
+            &vgm[HEADER_SIZE..],
+            &[0xA0, 8, 0x0F, 0x62, 0x51, 0x30, 0x10, 0x70, 0x66]
+        );
+    }
+
+    #[test]
+    fn test_scc_port_translation() {
+        let mut log = VgmLog::new(0, PsgModel::Ay38910);
+        let scc = |plus_layout, offset, value| VgmWrite::Scc {
+            chip: SccChip::SccI,
+            plus_layout,
+            offset,
+            value,
+        };
+        log.write(0, scc(false, 0x61, 0x11));
+        log.write(0, scc(false, 0x83, 0x02));
+        log.write(0, scc(false, 0x8C, 0x0F));
+        log.write(0, scc(false, 0x8F, 0x1F));
+        log.write(0, scc(true, 0x85, 0x22));
+        log.write(0, scc(true, 0xC0, 0x20));
+        let vgm = log.finish(0);
+
+        assert_eq!(
+            vgm[0x9C..0xA0],
+            ((CPU_CLOCK_HZ / 2) | 1 << 31).to_le_bytes()
+        );
+        assert_eq!(
+            &vgm[HEADER_SIZE..],
+            &[
+                0xD2, 0, 0x61, 0x11, 0xD2, 4, 0x01, 0x11, // wave 4, mirrored to wave 5
+                0xD2, 1, 0x03, 0x02, // period high byte of channel 2
+                0xD2, 2, 0x02, 0x0F, // volume of channel 3
+                0xD2, 3, 0x00, 0x1F, // enable bits
+                0xD2, 4, 0x05, 0x22, // wave 5 in the SCC+ layout
+                0xD2, 5, 0x00, 0x20, // deformation
+                0x66,
+            ]
+        );
+    }
+}
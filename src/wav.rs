@@ -0,0 +1,224 @@
+use serde::{Deserialize, Serialize};
+
+/// Where in the output path a WAV capture takes its samples
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CaptureRate {
+    /// The host rate stream, as `generateAudioSamples` hands it out: with the silence
+    /// of A/V sync filling the buffer and without a dropped backlog
+    #[default]
+    Host,
+    /// The mixed output at the native ~112kHz rate, before resampling
+    Native,
+}
+
+impl CaptureRate {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "host" => Some(CaptureRate::Host),
+            "native" => Some(CaptureRate::Native),
+            _ => None,
+        }
+    }
+}
+
+/// Records audio frames as 16-bit PCM for a WAV file.
+///
+/// Mono captures keep the left channel, like the mono output of the machine. With a
+/// frame limit the capture stops by itself after that many video frames, and the
+/// samples stay available until they are taken.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct WavCapture {
+    rate: CaptureRate,
+    sample_rate: u32,
+    channels: u16,
+    // Video frames left before the capture stops, if limited
+    frames_left: Option<u32>,
+    samples: Vec<i16>,
+}
+
+impl WavCapture {
+    pub fn new(rate: CaptureRate, sample_rate: u32, stereo: bool, frames: Option<u32>) -> Self {
+        Self {
+            rate,
+            sample_rate,
+            channels: if stereo { 2 } else { 1 },
+            frames_left: frames,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn rate(&self) -> CaptureRate {
+        self.rate
+    }
+
+    /// Whether samples are still being recorded
+    pub fn recording(&self) -> bool {
+        self.frames_left != Some(0)
+    }
+
+    /// Audio frames recorded so far
+    pub fn frame_count(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    pub fn push(&mut self, frame: [f32; 2]) {
+        if !self.recording() {
+            return;
+        }
+        let channels = &frame[..self.channels as usize];
+        self.samples
+            .extend(channels.iter().map(|sample| to_pcm(*sample)));
+    }
+
+    /// Called at the end of every video frame to count down a limited capture
+    pub fn end_frame(&mut self) {
+        if let Some(frames) = self.frames_left.as_mut() {
+            *frames = frames.saturating_sub(1);
+        }
+    }
+
+    /// The complete WAV file
+    pub fn to_wav(&self) -> Vec<u8> {
+        let data_size = (self.samples.len() * 2) as u32;
+        let block_align = self.channels * 2;
+
+        let mut wav = Vec::with_capacity(44 + data_size as usize);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend_from_slice(b"fmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        // PCM
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&self.channels.to_le_bytes());
+        wav.extend_from_slice(&self.sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(self.sample_rate * block_align as u32).to_le_bytes());
+        wav.extend_from_slice(&block_align.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_size.to_le_bytes());
+        for sample in &self.samples {
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        wav
+    }
+}
+
+/// Reads the first channel of an 8 or 16-bit PCM WAV file, as its sample rate and
+/// samples in -1.0..1.0
+pub fn read_wav(data: &[u8]) -> Result<(u32, Vec<f32>), String> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err("Not a WAV file".to_string());
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+    let u32_at = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+
+    let mut format = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let id = &data[offset..offset + 4];
+        let size = u32_at(offset + 4) as usize;
+        let body = offset + 8;
+        let end = body
+            .checked_add(size)
+            .filter(|&end| end <= data.len())
+            .ok_or("WAV chunk runs past the end of the file")?;
+        if id == b"fmt " && size >= 16 {
+            // Format, channels, sample rate and bits per sample
+            format = Some((
+                u16_at(body),
+                u16_at(body + 2),
+                u32_at(body + 4),
+                u16_at(body + 14),
+            ));
+        } else if id == b"data" {
+            let (tag, channels, sample_rate, bits) = format.ok_or("WAV data before its format")?;
+            if tag != 1 || !(bits == 8 || bits == 16) || channels == 0 {
+                return Err(format!(
+                    "Unsupported WAV format: type {}, {} bits",
+                    tag, bits
+                ));
+            }
+            let frame = channels as usize * bits as usize / 8;
+            let samples = data[body..end]
+                .chunks_exact(frame)
+                .map(|frame| match bits {
+                    8 => (frame[0] as f32 - 128.0) / 128.0,
+                    _ => i16::from_le_bytes([frame[0], frame[1]]) as f32 / 32768.0,
+                })
+                .collect();
+            return Ok((sample_rate, samples));
+        }
+        // Chunks are padded to an even size
+        offset = end + (size & 1);
+    }
+    Err("WAV file without data".to_string())
+}
+
+/// Converts an output sample from -1.0..1.0 to 16-bit PCM
+pub fn to_pcm(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_wav() {
+        let mut capture = WavCapture::new(CaptureRate::Host, 22050, false, None);
+        capture.push([0.5, 0.25]);
+        capture.push([-1.0, 0.0]);
+        let (rate, samples) = read_wav(&capture.to_wav()).unwrap();
+        assert_eq!(rate, 22050);
+        assert_eq!(samples.len(), 2);
+        assert!((samples[0] - 0.5).abs() < 1e-3);
+        assert!((samples[1] + 1.0).abs() < 1e-3);
+
+        assert!(read_wav(b"RIFF\0\0\0\0WAVE").is_err());
+
+        // A truncated data chunk, and a chunk size that would overflow the offset
+        let wav = capture.to_wav();
+        assert!(read_wav(&wav[..wav.len() - 1]).is_err());
+        let mut oversized = wav[..20].to_vec();
+        oversized[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_wav(&oversized).is_err());
+    }
+
+    #[test]
+    fn test_wav_header_and_frame_limit() {
+        let mut capture = WavCapture::new(CaptureRate::Host, 44100, true, Some(2));
+        capture.push([1.0, -1.0]);
+        capture.end_frame();
+        capture.push([0.5, 2.0]);
+        capture.end_frame();
+        assert!(!capture.recording());
+        capture.push([0.0, 0.0]);
+        assert_eq!(capture.frame_count(), 2);
+
+        let wav = capture.to_wav();
+        let word = |offset: usize| u32::from_le_bytes(wav[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(word(4) as usize, wav.len() - 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u16::from_le_bytes([wav[22], wav[23]]), 2, "channels");
+        assert_eq!(word(24), 44100);
+        assert_eq!(word(28), 44100 * 4, "byte rate");
+        assert_eq!(word(40), 8, "data size");
+
+        let samples: Vec<i16> = wav[44..]
+            .chunks(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        assert_eq!(samples, vec![32767, -32767, 16384, 32767]);
+    }
+
+    #[test]
+    fn test_mono_keeps_left_channel() {
+        let mut capture = WavCapture::new(CaptureRate::Native, 111860, false, None);
+        capture.push([0.25, -0.25]);
+        capture.end_frame();
+        assert!(capture.recording(), "unlimited captures run until stopped");
+        assert_eq!(capture.to_wav()[44..], to_pcm(0.25).to_le_bytes());
+    }
+}
@@ -0,0 +1,717 @@
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use crate::{
+    fm::{self, Modulation, Operator, OperatorPatch, MODULATION_DEPTH, NATIVE_RATE},
+    machine::Message,
+};
+
+/// Yamaha Y8950 (MSX-AUDIO): an OPL FM synthesizer with an ADPCM voice and sample RAM.
+///
+/// The FM part has nine two-operator channels with a rhythm mode, like the YM3526.
+/// The ADPCM unit plays and records 4 bit samples in up to 256KB of RAM, which the CPU
+/// fills and reads back through register 0x0F. Two timers raise interrupts, which are
+/// sent as `Message::AudioInterrupt` to the machine's interrupt queue.
+#[derive(Clone)]
+pub struct Y8950 {
+    pub queue: Rc<RefCell<VecDeque<Message>>>,
+    registers: Vec<u8>,
+    address: u8,
+    // Two per channel, the modulator first
+    operators: Vec<Operator>,
+    modulation: Modulation,
+    // Timer flags, end of sample and buffer ready, as read in the status register
+    status: u8,
+    irq: bool,
+    timer_cycles: [u32; 2],
+    timer_counters: [u8; 2],
+    pub adpcm: Adpcm,
+}
+
+/// The ADPCM unit and its sample RAM
+#[derive(Clone, Debug, Default)]
+pub struct Adpcm {
+    pub ram: Vec<u8>,
+    playing: bool,
+    recording: bool,
+    // Current position in nibbles
+    position: usize,
+    rate_counter: u32,
+    predictor: i32,
+    step: i32,
+    // Byte written by the CPU for playback, or encoded for it while recording
+    cpu_byte: Option<u8>,
+    // Host samples waiting to be recorded
+    input: VecDeque<f32>,
+}
+
+impl Y8950 {
+    /// `ram_size` is the size of the ADPCM sample RAM in bytes, 32KB to 256KB
+    pub fn new(queue: Rc<RefCell<VecDeque<Message>>>, ram_size: usize) -> Self {
+        Self {
+            queue,
+            registers: vec![0; 0x100],
+            address: 0,
+            operators: vec![Operator::default(); 18],
+            modulation: Modulation::default(),
+            status: 0,
+            irq: false,
+            timer_cycles: [0; 2],
+            timer_counters: [0; 2],
+            adpcm: Adpcm {
+                ram: vec![0; ram_size],
+                step: ADPCM_MIN_STEP,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn reset(&mut self) {
+        let mut chip = Self::new(self.queue.clone(), self.adpcm.ram.len());
+        // The sample RAM keeps its contents
+        std::mem::swap(&mut chip.adpcm.ram, &mut self.adpcm.ram);
+        let irq = self.irq;
+        *self = chip;
+        // The new chip starts with its interrupt released
+        if irq {
+            self.queue
+                .borrow_mut()
+                .push_back(Message::AudioInterrupt(false));
+        }
+    }
+
+    pub fn ram_size(&self) -> usize {
+        self.adpcm.ram.len()
+    }
+
+    /// Port 0xC0: the status register
+    pub fn read_status(&self) -> u8 {
+        let mut status = self.status;
+        if self.irq {
+            status |= 0x80;
+        }
+        if self.adpcm.playing || self.adpcm.recording {
+            status |= 0x01;
+        }
+        // Bits 1 and 2 identify the Y8950 to the MSX-AUDIO BIOS
+        status | 0x06
+    }
+
+    /// Port 0xC0: selects the register for the next data access
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value;
+    }
+
+    /// Register that the next data access goes to
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// Port 0xC1: reads the selected register, for the few that can be read
+    pub fn read_data(&mut self) -> u8 {
+        match self.address {
+            0x0F => self.read_adpcm_data(),
+            0x13 => self.adpcm.predictor as u8,
+            0x14 => (self.adpcm.predictor >> 8) as u8,
+            _ => 0xFF,
+        }
+    }
+
+    /// Port 0xC1: writes the selected register
+    pub fn write_data(&mut self, value: u8) {
+        let register = self.address as usize;
+        let previous = self.registers[register];
+        self.registers[register] = value;
+
+        match register {
+            0x02 => self.timer_counters[0] = value,
+            0x03 => self.timer_counters[1] = value,
+            0x04 => {
+                if value & 0x80 != 0 {
+                    // IRQ reset: clears the flags and leaves the control bits alone
+                    self.registers[0x04] = previous;
+                    self.status = 0;
+                } else {
+                    for timer in 0..2 {
+                        if value & (1 << timer) != 0 && previous & (1 << timer) == 0 {
+                            self.timer_counters[timer] = self.registers[0x02 + timer];
+                            self.timer_cycles[timer] = 0;
+                        }
+                    }
+                    // Masked flags are not reported
+                    self.status &= !(value & 0x78);
+                }
+                self.update_irq();
+            }
+            0x07 => self.write_adpcm_control(value),
+            0x0F => self.write_adpcm_data(value),
+            0xB0..=0xB8 => {
+                let channel = register - 0xB0;
+                if (previous ^ value) & 0x20 != 0 && !(channel >= 6 && self.rhythm_mode()) {
+                    let key_on = value & 0x20 != 0;
+                    self.set_key(channel * 2, key_on);
+                    self.set_key(channel * 2 + 1, key_on);
+                }
+            }
+            0xBD => self.update_rhythm_keys(previous),
+            _ => {}
+        }
+    }
+
+    /// Queues host audio for the ADPCM recorder, as -1.0 to 1.0 samples at the native rate
+    pub fn push_adpcm_input(&mut self, samples: &[f32]) {
+        let input = &mut self.adpcm.input;
+        input.extend(samples);
+        if input.len() > ADPCM_INPUT_CAPACITY {
+            let excess = input.len() - ADPCM_INPUT_CAPACITY;
+            input.drain(..excess);
+        }
+    }
+
+    /// Runs the timers for the CPU cycles just executed
+    pub fn clock(&mut self, cycles: u32) {
+        let control = self.registers[0x04];
+        for (timer, period) in TIMER_PERIODS.iter().enumerate() {
+            if control & (1 << timer) == 0 {
+                continue;
+            }
+            self.timer_cycles[timer] += cycles;
+            while self.timer_cycles[timer] >= *period {
+                self.timer_cycles[timer] -= period;
+                let (counter, overflow) = self.timer_counters[timer].overflowing_add(1);
+                if overflow {
+                    self.timer_counters[timer] = self.registers[0x02 + timer];
+                    self.set_flag(0x40 >> timer);
+                } else {
+                    self.timer_counters[timer] = counter;
+                }
+            }
+        }
+    }
+
+    fn set_flag(&mut self, flag: u8) {
+        if self.registers[0x04] & flag == 0 {
+            self.status |= flag;
+            self.update_irq();
+        }
+    }
+
+    fn update_irq(&mut self) {
+        let irq = self.status & 0x78 != 0;
+        if irq != self.irq {
+            self.irq = irq;
+            self.queue
+                .borrow_mut()
+                .push_back(Message::AudioInterrupt(irq));
+        }
+    }
+
+    fn rhythm_mode(&self) -> bool {
+        self.registers[0xBD] & 0x20 != 0
+    }
+
+    fn update_rhythm_keys(&mut self, previous: u8) {
+        let value = self.registers[0xBD];
+        if previous & 0x20 != 0 && value & 0x20 == 0 {
+            for operator in 12..18 {
+                self.set_key(operator, false);
+            }
+        }
+        if !self.rhythm_mode() {
+            return;
+        }
+        let operator_keys = [
+            (12, value & 0x10 != 0),
+            (13, value & 0x10 != 0),
+            (14, value & 0x01 != 0),
+            (15, value & 0x08 != 0),
+            (16, value & 0x04 != 0),
+            (17, value & 0x02 != 0),
+        ];
+        for (operator, key_on) in operator_keys {
+            if self.operators[operator].key_on != key_on {
+                self.set_key(operator, key_on);
+            }
+        }
+    }
+
+    fn set_key(&mut self, operator: usize, key_on: bool) {
+        let attack = self.operator_patch(operator).attack;
+        self.operators[operator].set_key(key_on, attack);
+    }
+
+    /// Offset of an operator's registers within each 0x20 register group
+    fn operator_offset(operator: usize) -> usize {
+        let channel = operator / 2;
+        (channel / 3) * 8 + channel % 3 + 3 * (operator & 1)
+    }
+
+    fn operator_patch(&self, operator: usize) -> OperatorPatch {
+        let offset = Self::operator_offset(operator);
+        let flags = self.registers[0x20 + offset];
+        OperatorPatch {
+            am: flags & 0x80 != 0,
+            pm: flags & 0x40 != 0,
+            sustained: flags & 0x20 != 0,
+            key_scale_rate: flags & 0x10 != 0,
+            multiple: flags & 0x0F,
+            key_scale_level: self.registers[0x40 + offset] >> 6,
+            half_sine: false,
+            attack: self.registers[0x60 + offset] >> 4,
+            decay: self.registers[0x60 + offset] & 0x0F,
+            sustain_level: self.registers[0x80 + offset] >> 4,
+            release: self.registers[0x80 + offset] & 0x0F,
+        }
+    }
+
+    fn frequency(&self, channel: usize) -> (u32, u32) {
+        let fnum = self.registers[0xA0 + channel] as u32
+            | ((self.registers[0xB0 + channel] as u32 & 0x03) << 8);
+        let block = (self.registers[0xB0 + channel] as u32 >> 2) & 0x07;
+        (fnum, block)
+    }
+
+    /// Advances the chip by one sample at its native rate and returns the output level,
+    /// signed and in PSG channel level units
+    pub fn next_sample(&mut self) -> f32 {
+        self.generate()
+    }
+
+    fn generate(&mut self) -> f32 {
+        self.modulation.advance(NATIVE_RATE);
+        let depth = self.registers[0xBD];
+        let pm = self
+            .modulation
+            .pm(if depth & 0x40 != 0 { 14.0 } else { 7.0 });
+        for operator in 0..18 {
+            let patch = self.operator_patch(operator);
+            let (fnum, block) = self.frequency(operator / 2);
+            self.operators[operator].advance(&patch, fnum, block, pm, patch.release);
+        }
+
+        let melodic_channels = if self.rhythm_mode() { 6 } else { 9 };
+        let mut output = 0.0;
+        for channel in 0..melodic_channels {
+            output += self.channel_output(channel);
+        }
+        if self.rhythm_mode() {
+            output += self.rhythm_output() * 2.0;
+        }
+        output * CHANNEL_MAX_VOLUME + self.generate_adpcm() * ADPCM_MAX_VOLUME
+    }
+
+    /// Attenuation in 0.375dB steps, or `None` when the operator is silent
+    fn attenuation(&self, operator: usize) -> Option<u32> {
+        let envelope = self.operators[operator].envelope_level()?;
+        let patch = self.operator_patch(operator);
+        let (fnum, block) = self.frequency(operator / 2);
+        let total_level = (self.registers[0x40 + Self::operator_offset(operator)] & 0x3F) as u32;
+        let mut attenuation =
+            envelope + total_level * 2 + fm::key_scale_level(patch.key_scale_level, fnum, block);
+        if patch.am {
+            // 4.8dB or 1dB deep
+            let depth = if self.registers[0xBD] & 0x80 != 0 {
+                13.0
+            } else {
+                2.7
+            };
+            attenuation += self.modulation.am(depth);
+        }
+        Some(attenuation)
+    }
+
+    fn operator_output(&self, operator: usize, phase_offset: f32) -> f32 {
+        match self.attenuation(operator) {
+            Some(attenuation) => fm::wave(
+                self.operators[operator].phase() + phase_offset,
+                false,
+                attenuation,
+            ),
+            None => 0.0,
+        }
+    }
+
+    fn modulator_output(&mut self, operator: usize) -> f32 {
+        let feedback = (self.registers[0xC0 + operator / 2] >> 1) & 0x07;
+        let offset = fm::feedback_offset(feedback, self.operators[operator].feedback);
+        let output = self.operator_output(operator, offset);
+        let state = &mut self.operators[operator];
+        state.feedback = [state.feedback[1], output];
+        output
+    }
+
+    fn channel_output(&mut self, channel: usize) -> f32 {
+        let modulator = self.modulator_output(channel * 2);
+        if self.registers[0xC0 + channel] & 0x01 != 0 {
+            // Additive synthesis: both operators are heard
+            modulator + self.operator_output(channel * 2 + 1, 0.0)
+        } else {
+            self.operator_output(channel * 2 + 1, modulator * MODULATION_DEPTH)
+        }
+    }
+
+    fn rhythm_output(&mut self) -> f32 {
+        let noise = self.modulation.noise();
+        let mut output = self.channel_output(6);
+
+        let (hat, cymbal) = (&self.operators[14], &self.operators[17]);
+        let metallic = fm::metallic(hat, cymbal);
+        if let Some(attenuation) = self.attenuation(14) {
+            output += fm::hi_hat(attenuation, noise, metallic);
+        }
+        if let Some(attenuation) = self.attenuation(15) {
+            output += fm::snare_drum(attenuation, noise, hat);
+        }
+        output += self.operator_output(16, 0.0);
+        if let Some(attenuation) = self.attenuation(17) {
+            output += fm::top_cymbal(attenuation, metallic);
+        }
+        output
+    }
+
+    fn adpcm_control(&self) -> u8 {
+        self.registers[0x07]
+    }
+
+    fn uses_memory(&self) -> bool {
+        self.adpcm_control() & 0x20 != 0
+    }
+
+    /// Start and end of the sample in RAM, in nibbles; addresses count 4 byte units
+    fn adpcm_range(&self) -> (usize, usize) {
+        let address = |low: usize| {
+            ((self.registers[low + 1] as usize) << 8 | self.registers[low] as usize) << 2
+        };
+        let mask = self.adpcm.ram.len() * 2 - 1;
+        let start = (address(0x09) * 2) & mask;
+        let end = ((address(0x0B) + 3) * 2 + 1) & mask;
+        (start, end)
+    }
+
+    fn write_adpcm_control(&mut self, value: u8) {
+        let (start, _) = self.adpcm_range();
+        let adpcm = &mut self.adpcm;
+        if value & 0x01 != 0 {
+            adpcm.playing = false;
+            adpcm.recording = false;
+            return;
+        }
+        adpcm.position = start;
+        adpcm.rate_counter = 0;
+        adpcm.predictor = 0;
+        adpcm.step = ADPCM_MIN_STEP;
+        adpcm.cpu_byte = None;
+        adpcm.playing = value & 0xC0 == 0x80;
+        adpcm.recording = value & 0xC0 == 0xC0;
+        if adpcm.playing && value & 0x20 == 0 {
+            // The CPU feeds the samples; ask for the first byte
+            self.set_flag(0x08);
+        }
+    }
+
+    /// CPU access to the sample RAM, or to the sample stream when not using memory
+    fn write_adpcm_data(&mut self, value: u8) {
+        let control = self.adpcm_control();
+        if control & 0x80 == 0 && control & 0x60 == 0x60 {
+            // Memory write mode
+            let (_, end) = self.adpcm_range();
+            let position = self.adpcm.position;
+            self.adpcm.ram[position / 2] = value;
+            self.advance_memory_position(position, end);
+        } else if control & 0xA0 == 0x80 {
+            self.adpcm.cpu_byte = Some(value);
+            self.status &= !0x08;
+            self.update_irq();
+        }
+    }
+
+    fn read_adpcm_data(&mut self) -> u8 {
+        let control = self.adpcm_control();
+        if control & 0xE0 == 0x20 {
+            // Memory read mode
+            let (_, end) = self.adpcm_range();
+            let position = self.adpcm.position;
+            let value = self.adpcm.ram[position / 2];
+            self.advance_memory_position(position, end);
+            value
+        } else if let Some(value) = self.adpcm.cpu_byte.take() {
+            // A byte encoded by the recorder
+            self.status &= !0x08;
+            self.update_irq();
+            value
+        } else {
+            0xFF
+        }
+    }
+
+    /// Moves a CPU memory access on by one byte, flagging the end of the sample
+    fn advance_memory_position(&mut self, position: usize, end: usize) {
+        if position / 2 == end / 2 {
+            self.set_flag(0x10);
+            self.adpcm.position = self.adpcm_range().0;
+        } else {
+            self.adpcm.position = (position + 2) % (self.adpcm.ram.len() * 2);
+            self.set_flag(0x08);
+        }
+    }
+
+    /// Runs the ADPCM unit for one native sample; returns its output from -1.0 to 1.0
+    fn generate_adpcm(&mut self) -> f32 {
+        if !self.adpcm.playing && !self.adpcm.recording {
+            return 0.0;
+        }
+        let delta_n = self.registers[0x10] as u32 | (self.registers[0x11] as u32) << 8;
+        self.adpcm.rate_counter += delta_n;
+        while self.adpcm.rate_counter >= 0x10000 {
+            self.adpcm.rate_counter -= 0x10000;
+            if self.adpcm.recording {
+                self.record_nibble();
+            } else {
+                self.play_nibble();
+            }
+            if !self.adpcm.playing && !self.adpcm.recording {
+                break;
+            }
+        }
+        if self.adpcm.recording {
+            return 0.0;
+        }
+        let volume = self.registers[0x12] as f32 / 255.0;
+        self.adpcm.predictor as f32 / 32768.0 * volume
+    }
+
+    fn play_nibble(&mut self) {
+        let position = self.adpcm.position;
+        let nibble = if self.uses_memory() {
+            self.adpcm.ram[position / 2]
+        } else {
+            match self.adpcm.cpu_byte {
+                Some(byte) => byte,
+                // The CPU is late; hold the current level
+                None => return,
+            }
+        };
+        let nibble = if position & 1 == 0 {
+            nibble >> 4
+        } else {
+            nibble & 0x0F
+        };
+        self.adpcm.decode(nibble);
+        self.next_nibble(position);
+    }
+
+    fn record_nibble(&mut self) {
+        let input = self.adpcm.input.pop_front().unwrap_or(0.0);
+        let nibble = self.adpcm.encode((input.clamp(-1.0, 1.0) * 32767.0) as i32);
+        let position = self.adpcm.position;
+        if self.uses_memory() {
+            let byte = &mut self.adpcm.ram[position / 2];
+            *byte = if position & 1 == 0 {
+                (*byte & 0x0F) | nibble << 4
+            } else {
+                (*byte & 0xF0) | nibble
+            };
+        } else {
+            let byte = self.adpcm.cpu_byte.unwrap_or(0);
+            self.adpcm.cpu_byte = Some(if position & 1 == 0 {
+                nibble << 4
+            } else {
+                byte | nibble
+            });
+        }
+        self.next_nibble(position);
+    }
+
+    fn next_nibble(&mut self, position: usize) {
+        if !self.uses_memory() {
+            // Streams from and to the CPU a byte at a time
+            self.adpcm.position = position ^ 1;
+            if position & 1 == 1 {
+                if self.adpcm.playing {
+                    self.adpcm.cpu_byte = None;
+                }
+                self.set_flag(0x08);
+            }
+            return;
+        }
+
+        let (start, end) = self.adpcm_range();
+        if position == end {
+            self.set_flag(0x10);
+            if self.adpcm_control() & 0x10 != 0 {
+                // Repeat from the start
+                self.adpcm.position = start;
+                self.adpcm.predictor = 0;
+                self.adpcm.step = ADPCM_MIN_STEP;
+            } else {
+                self.adpcm.playing = false;
+                self.adpcm.recording = false;
+            }
+        } else {
+            self.adpcm.position = (position + 1) % (self.adpcm.ram.len() * 2);
+        }
+    }
+}
+
+impl Adpcm {
+    fn decode(&mut self, nibble: u8) {
+        let magnitude = (((nibble & 0x07) as i32) * 2 + 1) * self.step / 8;
+        let difference = if nibble & 0x08 != 0 {
+            -magnitude
+        } else {
+            magnitude
+        };
+        self.predictor = (self.predictor + difference).clamp(-32768, 32767);
+        self.step = (self.step * ADPCM_STEP_SCALE[(nibble & 0x07) as usize] / 64)
+            .clamp(ADPCM_MIN_STEP, ADPCM_MAX_STEP);
+    }
+
+    /// Picks the nibble that best follows `sample`, keeping the decoder state in step
+    fn encode(&mut self, sample: i32) -> u8 {
+        let difference = sample - self.predictor;
+        let sign = if difference < 0 { 0x08 } else { 0 };
+        let magnitude = ((difference.abs() * 4 / self.step).min(7)) as u8;
+        let nibble = sign | magnitude;
+        self.decode(nibble);
+        nibble
+    }
+}
+
+/// CPU cycles per count of timer 1 (80us) and timer 2 (320us)
+const TIMER_PERIODS: [u32; 2] = [288, 1152];
+
+const ADPCM_STEP_SCALE: [i32; 8] = [57, 57, 57, 57, 77, 102, 128, 153];
+const ADPCM_MIN_STEP: i32 = 127;
+const ADPCM_MAX_STEP: i32 = 24576;
+/// About a second of recording input at the native rate
+const ADPCM_INPUT_CAPACITY: usize = 50000;
+
+pub const MIN_RAM_SIZE: usize = 32 * 1024;
+pub const MAX_RAM_SIZE: usize = 256 * 1024;
+
+/// Level of one full scale channel, in PSG channel level units
+const CHANNEL_MAX_VOLUME: f32 = 0.05;
+const ADPCM_MAX_VOLUME: f32 = 0.1;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip() -> (Y8950, Rc<RefCell<VecDeque<Message>>>) {
+        let queue = Rc::new(RefCell::new(VecDeque::new()));
+        (Y8950::new(queue.clone(), MIN_RAM_SIZE), queue)
+    }
+
+    fn write(chip: &mut Y8950, register: u8, value: u8) {
+        chip.write_address(register);
+        chip.write_data(value);
+    }
+
+    #[test]
+    fn test_timer_interrupt() {
+        let (mut chip, queue) = chip();
+        assert_eq!(chip.read_status(), 0x06);
+
+        // Timer 1 overflows after 16 counts of 80us
+        write(&mut chip, 0x02, 0xF0);
+        write(&mut chip, 0x04, 0x01);
+        chip.clock(288 * 15);
+        assert!(queue.borrow().is_empty());
+        chip.clock(288);
+        assert_eq!(chip.read_status() & 0xC0, 0xC0);
+        assert_eq!(
+            queue.borrow_mut().pop_front(),
+            Some(Message::AudioInterrupt(true))
+        );
+
+        write(&mut chip, 0x04, 0x80);
+        assert_eq!(chip.read_status() & 0xC0, 0x00);
+        assert_eq!(
+            queue.borrow_mut().pop_front(),
+            Some(Message::AudioInterrupt(false))
+        );
+
+        // A masked timer raises no flag
+        write(&mut chip, 0x04, 0x41);
+        chip.clock(288 * 16);
+        assert_eq!(chip.read_status() & 0xC0, 0x00);
+    }
+
+    #[test]
+    fn test_reset_releases_interrupt() {
+        let (mut chip, queue) = chip();
+        write(&mut chip, 0x02, 0xFF);
+        write(&mut chip, 0x04, 0x01);
+        chip.clock(288);
+        assert_eq!(
+            queue.borrow_mut().pop_front(),
+            Some(Message::AudioInterrupt(true))
+        );
+
+        chip.reset();
+        assert_eq!(chip.read_status() & 0x80, 0x00);
+        assert_eq!(
+            queue.borrow_mut().pop_front(),
+            Some(Message::AudioInterrupt(false))
+        );
+
+        // Nothing to release when no interrupt was pending
+        chip.reset();
+        assert!(queue.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_adpcm_memory_access_and_playback() {
+        let (mut chip, _queue) = chip();
+        // Write 8 bytes through register 0x0F, starting at address 0
+        write(&mut chip, 0x09, 0x00);
+        write(&mut chip, 0x0A, 0x00);
+        write(&mut chip, 0x0B, 0x01);
+        write(&mut chip, 0x0C, 0x00);
+        write(&mut chip, 0x07, 0x60);
+        for _ in 0..8 {
+            write(&mut chip, 0x0F, 0x77);
+        }
+        assert_ne!(chip.read_status() & 0x10, 0, "end of sample reached");
+        assert_eq!(
+            &chip.adpcm.ram[..9],
+            &[0x77, 0x77, 0x77, 0x77, 0x77, 0x77, 0x77, 0x77, 0]
+        );
+
+        // Read them back
+        write(&mut chip, 0x07, 0x01);
+        write(&mut chip, 0x07, 0x20);
+        chip.write_address(0x0F);
+        assert_eq!(chip.read_data(), 0x77);
+
+        // Play them: a run of maximum positive steps ramps the output up
+        write(&mut chip, 0x10, 0x00);
+        write(&mut chip, 0x11, 0x80);
+        write(&mut chip, 0x12, 0xFF);
+        write(&mut chip, 0x07, 0xA0);
+        assert_ne!(chip.read_status() & 0x01, 0, "busy while playing");
+        let levels: Vec<f32> = (0..32).map(|_| chip.generate_adpcm()).collect();
+        assert!(levels[10] > levels[2] && levels[2] > 0.0);
+        assert_eq!(chip.read_status() & 0x01, 0, "stops at the end address");
+    }
+
+    #[test]
+    fn test_fm_key_on() {
+        let (mut chip, _queue) = chip();
+        // Channel 1: sustained carrier at full volume with instant attack, A4
+        write(&mut chip, 0x23, 0x21);
+        write(&mut chip, 0x43, 0x00);
+        write(&mut chip, 0x63, 0xF0);
+        write(&mut chip, 0x83, 0x0F);
+        write(&mut chip, 0x40, 0x3F);
+        write(&mut chip, 0xA0, 0x41);
+        write(&mut chip, 0xB0, 0x32);
+        let peak = (0..1000)
+            .map(|_| chip.generate().abs())
+            .fold(0.0f32, f32::max);
+        assert!(peak > CHANNEL_MAX_VOLUME * 0.9, "peak {}", peak);
+    }
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::{boot_hotbit, finish_typing, screen_contains};
+use wasmsx::{
+    cassette::{TapeBlockKind, CAS_HEADER},
+    clock::CPU_CLOCK_HZ,
+    machine::FAST_FORWARD_FRAMES,
+    Machine,
+};
+
+fn type_and_wait(machine: &mut Machine, text: &str) {
+    machine.type_text(text).unwrap();
+    finish_typing(machine);
+}
+
+/// A BSAVE'd program at 0x9000 that stores 42 at 0x9100
+fn binary_tape() -> Vec<u8> {
+    let mut data = CAS_HEADER.to_vec();
+    data.extend_from_slice(&[0xD0; 10]);
+    data.extend_from_slice(b"POKE42");
+    data.extend_from_slice(&CAS_HEADER);
+    // Start, end and execution addresses, then the code: LD A,42; LD (9100h),A; RET
+    data.extend_from_slice(&[0x00, 0x90, 0x05, 0x90, 0x00, 0x90]);
+    data.extend_from_slice(&[0x3E, 42, 0x32, 0x00, 0x91, 0xC9]);
+    data
+}
+
+#[test]
+fn test_bload_from_cas() {
+    let mut machine = boot_hotbit();
+    let tape = binary_tape();
+    machine.insert_tape(tape.clone()).unwrap();
+    let blocks = machine.tape_blocks();
+    assert_eq!(blocks[0].kind, TapeBlockKind::Binary);
+    assert_eq!(blocks[0].name, "POKE42");
+
+    type_and_wait(&mut machine, "BLOAD\"CAS:\",R\n");
+    assert_eq!(machine.ram()[0x9100], 42);
+    assert_eq!(machine.tape_position(), tape.len());
+}
+
+#[test]
+fn test_csave_and_cload() {
+    let mut machine = boot_hotbit();
+    machine.insert_tape(Vec::new()).unwrap();
+    type_and_wait(
+        &mut machine,
+        "10 PRINT \"FROM TAPE\"\nCSAVE \"PROG\"\nNEW\n",
+    );
+    let blocks = machine.tape_blocks();
+    assert_eq!(blocks[0].kind, TapeBlockKind::Basic);
+    assert_eq!(blocks[0].name, "PROG");
+
+    machine.set_tape_position(0);
+    // Clear the listing typed above, so only the program prints the text
+    type_and_wait(&mut machine, "CLOAD\nCLS:RUN\n");
+    assert!(screen_contains(&machine, "FROM TAPE"));
+}
+
+#[test]
+fn test_step_for_fast_forwards_a_tape_signal() {
+    let mut machine = Machine::default();
+    machine.insert_tape_signal(&binary_tape()).unwrap();
+    // Motor on through PPI port C bit 4
+    machine.bus.borrow_mut().output(0xAB, 0x08);
+
+    machine.step_for(100_000);
+    let (played, _) = machine.tape_signal_secs().unwrap();
+    let cycles = (played * CPU_CLOCK_HZ as f64).round() as usize;
+    assert!(
+        cycles >= 100_000 * FAST_FORWARD_FRAMES as usize,
+        "{} cycles",
+        cycles
+    );
+
+    machine.set_tape_fast_forward(false);
+    machine.step_for(100_000);
+    let (now, _) = machine.tape_signal_secs().unwrap();
+    let cycles = ((now - played) * CPU_CLOCK_HZ as f64).round() as usize;
+    assert!((100_000..100_100).contains(&cycles), "{} cycles", cycles);
+}
+
+#[test]
+fn test_bload_from_tape_signal_through_step_for() {
+    let mut machine = boot_hotbit();
+    machine.insert_tape_signal(&binary_tape()).unwrap();
+    machine.type_text("BLOAD\"CAS:\",R\n").unwrap();
+
+    // The tape lasts about 12 seconds; fast-forward loads it in under 3
+    let frame = CPU_CLOCK_HZ as usize / 60;
+    for _ in 0..60 * 3 {
+        machine.step_for(frame);
+        if machine.ram()[0x9100] == 42 {
+            return;
+        }
+    }
+    panic!("BLOAD did not finish");
+}
@@ -0,0 +1,28 @@
+use wasmsx::{get_machine, Machine};
+
+/// Boots the Hotbit BIOS into BASIC
+pub fn boot_hotbit() -> Machine {
+    let rom = std::fs::read("roms/hotbit.rom").unwrap();
+    let mut machine = get_machine(&rom);
+    // The BIOS logo and BASIC start up take a few seconds
+    for _ in 0..300 {
+        machine.step_frame();
+    }
+    machine
+}
+
+/// Steps frames until the typed text is in and BASIC has had time to act on it
+pub fn finish_typing(machine: &mut Machine) {
+    while machine.typing() {
+        machine.step_frame();
+    }
+    for _ in 0..30 {
+        machine.step_frame();
+    }
+}
+
+pub fn screen_contains(machine: &Machine, text: &str) -> bool {
+    let vram = machine.get_vdp().vram;
+    vram.windows(text.len())
+        .any(|window| window == text.as_bytes())
+}
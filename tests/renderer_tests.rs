@@ -0,0 +1,167 @@
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use wasmsx::{
+    renderer::{BORDER_LEFT, BORDER_TOP, OVERSCAN_HEIGHT, OVERSCAN_WIDTH},
+    OutputMode, Palette, Renderer, TMS9918,
+};
+
+fn get_vdp() -> TMS9918 {
+    let queue = Rc::new(RefCell::new(VecDeque::new()));
+    TMS9918::new(queue)
+}
+
+fn write_register(vdp: &mut TMS9918, reg: u8, value: u8) {
+    vdp.write(0x99, value);
+    vdp.write(0x99, 0x80 | reg);
+}
+
+fn run_frame(vdp: &mut TMS9918) {
+    for line in 0..262 {
+        vdp.set_current_scanline(line);
+    }
+}
+
+#[test]
+fn test_overscan_border_per_line() {
+    let mut vdp = get_vdp();
+    vdp.registers[7] = 0x04; // dark blue backdrop
+    for line in 0..262 {
+        if line == 100 {
+            vdp.registers[7] = 0x08; // switched to red mid-frame
+        }
+        vdp.set_current_scanline(line);
+    }
+
+    let mut renderer = Renderer::new(&vdp);
+    renderer.draw();
+
+    let frame = renderer.compose(OutputMode::Overscan);
+    assert_eq!(frame.len(), OVERSCAN_WIDTH * OVERSCAN_HEIGHT);
+
+    // top border and left border of the first active line
+    assert_eq!(frame[0], 0x08);
+    assert_eq!(frame[BORDER_TOP * OVERSCAN_WIDTH], 0x04);
+    // left border of active line 100
+    assert_eq!(frame[(BORDER_TOP + 100) * OVERSCAN_WIDTH], 0x08);
+    // transparent active pixels show the backdrop of their line
+    assert_eq!(
+        frame[(BORDER_TOP + 10) * OVERSCAN_WIDTH + BORDER_LEFT + 5],
+        0x04
+    );
+
+    let cropped = renderer.compose(OutputMode::Cropped);
+    assert_eq!(cropped.len(), 256 * 192);
+    assert_eq!(cropped[150 * 256], 0x08);
+
+    let rgba = renderer.compose_rgba(OutputMode::Overscan, &Palette::Tms9918Ntsc);
+    assert_eq!(rgba.len(), OVERSCAN_WIDTH * OVERSCAN_HEIGHT * 4);
+}
+
+#[test]
+fn test_text1_side_borders() {
+    let mut vdp = get_vdp();
+    write_register(&mut vdp, 1, 0xD0); // 16K, display enabled, Text1 mode
+    write_register(&mut vdp, 7, 0xF5); // white on light blue
+    write_register(&mut vdp, 4, 0x01); // pattern table at 0x0800
+    vdp.vram[0x0800] = 0xFC; // first row of character 0 fully set
+    run_frame(&mut vdp);
+
+    let mut renderer = Renderer::new(&vdp);
+    renderer.draw();
+
+    assert_eq!(&renderer.screen_buffer[0..8], &[0x05; 8]);
+    assert_eq!(&renderer.screen_buffer[8..14], &[0x0F; 6]);
+    assert_eq!(&renderer.screen_buffer[248..256], &[0x05; 8]);
+}
+
+#[test]
+fn test_fixture_vdp_draws_without_running_a_frame() {
+    let mut vram = vec![0; 0x4000];
+    vram[0] = 0xFF; // first row of character 0 fully set
+    vram[0x2000] = 0xF4; // white on dark blue
+    let queue = Rc::new(RefCell::new(VecDeque::new()));
+    let mut vdp = TMS9918::new_with_vram(queue, vram);
+    write_register(&mut vdp, 1, 0xC0); // 16K, display enabled, Graphic1 mode
+    write_register(&mut vdp, 2, 0x06); // name table at 0x1800
+    write_register(&mut vdp, 3, 0x80); // color table at 0x2000
+    write_register(&mut vdp, 7, 0x01);
+
+    let mut renderer = Renderer::new(&vdp);
+    renderer.draw();
+    assert_eq!(&renderer.screen_buffer[0..8], &[0x0F; 8]);
+    assert_eq!(renderer.screen_buffer[256], 0x04);
+}
+
+#[test]
+fn test_display_blanking_applies_from_next_line() {
+    let mut vdp = get_vdp();
+    write_register(&mut vdp, 1, 0xD0); // display enabled, Text1 mode
+    write_register(&mut vdp, 7, 0xF4);
+    write_register(&mut vdp, 4, 0x01);
+    vdp.vram[0x0800] = 0xFC;
+
+    for line in 0..262 {
+        if line == 100 {
+            write_register(&mut vdp, 1, 0x90); // blank the display mid-line
+            assert!(!vdp.is_line_blanked(99));
+        }
+        vdp.set_current_scanline(line);
+    }
+
+    assert!(!vdp.is_line_blanked(99));
+    assert!(vdp.is_line_blanked(100));
+
+    let mut renderer = Renderer::new(&vdp);
+    renderer.draw();
+    assert_eq!(renderer.screen_buffer[8], 0x0F); // text still visible on line 0
+    assert_eq!(renderer.screen_buffer[96 * 256 + 8], 0x0F); // and on line 96
+    assert!(renderer.screen_buffer[100 * 256..101 * 256]
+        .iter()
+        .all(|&c| c == 0x04));
+}
+
+#[test]
+fn test_4k_vram_address_scrambling() {
+    let mut vdp = get_vdp();
+
+    // 16K mode: linear addressing
+    write_register(&mut vdp, 1, 0x80);
+    assert_eq!(vdp.vram_address(0x1040), 0x1040);
+
+    // 4K mode: bit 12 moves to bit 6, bits 6-11 move up by one
+    write_register(&mut vdp, 1, 0x00);
+    assert_eq!(vdp.vram_address(0x0040), 0x0080);
+    assert_eq!(vdp.vram_address(0x1000), 0x0040);
+    assert_eq!(vdp.vram_address(0x203F), 0x203F);
+
+    // Writes through port 0x98 land on the scrambled address
+    vdp.write(0x99, 0x00);
+    vdp.write(0x99, 0x50); // write address 0x1000
+    vdp.write(0x98, 0xAA);
+    assert_eq!(vdp.vram[0x0040], 0xAA);
+    assert_eq!(vdp.vram[0x1000], 0x00);
+}
+
+#[test]
+fn test_4k_mode_software_renders() {
+    let mut vdp = get_vdp();
+    write_register(&mut vdp, 1, 0x40); // 4K, display enabled, Graphic1 mode
+    write_register(&mut vdp, 2, 0x06); // name table at 0x1800
+    write_register(&mut vdp, 3, 0x80); // color table at 0x2000
+    write_register(&mut vdp, 7, 0x01);
+
+    let mut poke = |address: u16, data: u8| {
+        vdp.write(0x99, address as u8);
+        vdp.write(0x99, 0x40 | (address >> 8) as u8);
+        vdp.write(0x98, data);
+    };
+    poke(0x41 * 8, 0xFF); // first row of 'A'
+    poke(0x2000 + 0x41 / 8, 0xF4); // white on dark blue
+    poke(0x1800 + 1, 0x41); // 'A' in the second column
+    assert_eq!(vdp.vram[0x1801], 0x00, "stored at a scrambled address");
+
+    let mut renderer = Renderer::new(&vdp);
+    renderer.draw();
+    assert_eq!(&renderer.screen_buffer[8..16], &[0x0F; 8]);
+    assert_eq!(renderer.screen_buffer[256 + 8], 0x04);
+}
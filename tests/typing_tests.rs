@@ -0,0 +1,33 @@
+mod common;
+
+use common::{boot_hotbit, finish_typing, screen_contains};
+use wasmsx::keyboard_layout::KeyboardLayout;
+
+#[test]
+fn test_type_into_basic() {
+    let mut machine = boot_hotbit();
+    assert_eq!(machine.keyboard_layout(), KeyboardLayout::Brazilian);
+
+    machine.type_text("PRINT 6*7\n").unwrap();
+    finish_typing(&mut machine);
+    assert!(screen_contains(&machine, " 42 "));
+}
+
+#[test]
+fn test_type_and_run_a_program() {
+    let mut machine = boot_hotbit();
+    let program = std::fs::read_to_string("test_audio.bas").unwrap();
+    machine.type_text(&program).unwrap();
+    machine.type_text("\nRUN\n").unwrap();
+    finish_typing(&mut machine);
+
+    let registers = machine.bus.borrow().psg.registers();
+    assert_eq!(registers[0], 254, "channel A tone");
+    assert_eq!(registers[8..11], [15, 15, 15], "volumes");
+    assert!(screen_contains(&machine, "Press any key to stop"));
+
+    machine.type_text(" ").unwrap();
+    finish_typing(&mut machine);
+    assert_eq!(machine.bus.borrow().psg.registers()[8], 0);
+    assert!(screen_contains(&machine, "Audio stopped"));
+}